@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use morkovmap::adjacency::OctileAdjacencyGenerator;
+use morkovmap::assigner::{MapColoringAssigner, MapColoringJob};
+use morkovmap::map2d::Map2D;
+use morkovmap::map2dnode::Map2DNode;
+use morkovmap::position2d::Position2D;
+use morkovmap::sampler::MultinomialDistribution;
+
+type BenchMap = Map2D<OctileAdjacencyGenerator<Position2D<u16>>, u8, Position2D<u16>>;
+
+/// A landmass-style ruleset: water, sand, grass & forest, each only bordering its neighbors in that list.
+fn rules() -> MapColoringAssigner<u8> {
+    MapColoringAssigner::with_rules(HashMap::from([
+        (1, MultinomialDistribution::from(HashMap::from([(1, 30.), (2, 1.)]))),
+        (2, MultinomialDistribution::from(HashMap::from([(1, 2.), (2, 5.), (3, 2.)]))),
+        (3, MultinomialDistribution::from(HashMap::from([(2, 1.), (3, 30.), (4, 3.)]))),
+        (4, MultinomialDistribution::from(HashMap::from([(3, 3.), (4, 20.)]))),
+    ]))
+}
+
+fn empty_map(size: u16) -> BenchMap {
+    Map2D::from_tiles((0..size).flat_map(|x| (0..size).map(move |y| {
+        Map2DNode::with_possibilities(
+            Position2D::new(x, y),
+            MultinomialDistribution::uniform_over(1..=4)
+        )
+    })))
+}
+
+fn chunked_scaling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunked_scaling");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(30));
+
+    for size in [256u16, 1024] {
+        group.bench_with_input(BenchmarkId::new("sequential", size), &size, |b, size| {
+            b.iter_batched(
+                || MapColoringJob::new(rules(), empty_map(*size)).with_seed(1),
+                |mut job| { job.queue_and_assign(); job },
+                BatchSize::LargeInput
+            )
+        });
+
+        for threads in [1usize, 2, 4, 8] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let id = BenchmarkId::new(format!("chunked_{}_threads", threads), size);
+
+            group.bench_with_input(id, &size, |b, size| {
+                b.iter_batched(
+                    || MapColoringJob::new(rules(), empty_map(*size)).with_seed(1),
+                    |mut job| { pool.install(|| { job.par_queue_and_assign(); }); job },
+                    BatchSize::LargeInput
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, chunked_scaling);
+criterion_main!(benches);
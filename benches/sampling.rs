@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::distributions::Standard;
+use rand::prelude::*;
+use morkovmap::sampler::MultinomialDistribution;
+
+/// The sampler as it was before the keys were sorted: a walk over the HashMap in whatever
+/// order it iterates in, re-summing the weights on every call.
+fn hashmap_walk_sample<R: Rng>(weights: &HashMap<u32, f32>, rng: &mut R) -> u32 {
+    let total: f32 = weights.values().sum();
+    let mut rope_len: f32 = rng.sample::<f32, _>(Standard) * total;
+    let mut candidate = None;
+
+    while rope_len > 0. {
+        for (key, weight) in weights.iter() {
+            if rope_len <= *weight {
+                candidate = Some(*key);
+                rope_len -= *weight;
+                break
+            }
+            rope_len -= *weight
+        }
+    }
+    candidate.unwrap_or_default()
+}
+
+fn weights_of_size(size: u32) -> HashMap<u32, f32> {
+    (0..size).map(|key| (key, (key % 7 + 1) as f32)).collect()
+}
+
+fn repeated_sampling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("repeated_sampling");
+
+    for size in [4u32, 16, 64, 256] {
+        let weights = weights_of_size(size);
+        let dist = MultinomialDistribution::from(weights.to_owned());
+        let table = dist.alias_table();
+        let mut rng = StdRng::seed_from_u64(size as u64);
+
+        group.bench_with_input(BenchmarkId::new("hashmap_walk", size), &size, |b, _| {
+            b.iter(|| hashmap_walk_sample(black_box(&weights), &mut rng))
+        });
+        group.bench_with_input(BenchmarkId::new("sorted_walk", size), &size, |b, _| {
+            b.iter(|| black_box(&dist).sample(&mut rng))
+        });
+        group.bench_with_input(BenchmarkId::new("alias_table", size), &size, |b, _| {
+            b.iter(|| black_box(table).sample(&mut rng))
+        });
+    }
+    group.finish();
+}
+
+fn one_off_sampling(c: &mut Criterion) {
+    // the assigner samples each freshly-propagated distribution just once, so setup cost counts
+    let mut group = c.benchmark_group("one_off_sampling");
+
+    for size in [4u32, 16, 64] {
+        let weights = weights_of_size(size);
+        let mut rng = StdRng::seed_from_u64(size as u64);
+
+        group.bench_with_input(BenchmarkId::new("sorted_walk", size), &size, |b, _| {
+            b.iter(|| MultinomialDistribution::from(weights.to_owned()).sample(&mut rng))
+        });
+        group.bench_with_input(BenchmarkId::new("alias_table", size), &size, |b, _| {
+            b.iter(|| MultinomialDistribution::from(weights.to_owned()).alias_table().sample(&mut rng))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, repeated_sampling, one_off_sampling);
+criterion_main!(benches);
@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 use arrayvec::ArrayVec;
-use num::{One, Zero};
+use crate::distance::{offset_position, DistanceMetric};
 use crate::position::MapPosition;
 
 
@@ -23,78 +23,108 @@ impl<MP: MapPosition<2>> AdjacencyGenerator<2> for CardinalAdjacencyGenerator<MP
     type Output = ArrayVec<MP, 4>;
 
     fn adjacents(bound_position: Self::Input) -> Self::Output {
-        let mut adjacents: ArrayVec::<MP, 4> = ArrayVec::new();
-        let type_unity: MP::Key = num::one();
-        let type_three: MP::Key = type_unity + type_unity + type_unity;
+        // offsets that do not fit the position type (i.e. negatives for unsigned keys) are off the map anyway
+        [(-1, 0), (1, 0), (0, -1), (0, 1)]
+            .into_iter()
+            .filter_map(|(dx, dy)| offset_position(bound_position, dx, dy))
+            .collect()
+    }
+}
 
-        for dim in 0..2 {
+#[derive(Copy, Clone)]
+pub struct OctileAdjacencyGenerator<MP: MapPosition<2>> {
+    bound_position: PhantomData<MP>
+}
 
-            let offset_range = num::range(
-                 num::zero(),
-                 type_three
-            );
+impl<MP: MapPosition<2>> AdjacencyGenerator<2> for OctileAdjacencyGenerator<MP>
+{
+    type Input = MP;
+    type Output = ArrayVec<MP, 8>;
+
+    fn adjacents(bound_position: Self::Input) -> Self::Output {
+        let mut adjacents: ArrayVec<MP, 8> = ArrayVec::new();
 
-            for offset in offset_range {
-                if offset == type_unity {
+        for x_dim in -1..=1 {
+            for y_dim in -1..=1 {
+                if x_dim == 0 && y_dim == 0 {
                     continue
                 };
-                let true_offset = offset - type_unity;
-
-                let mut pos_buffer = bound_position.get_dims();
-                pos_buffer[dim] = pos_buffer[dim] + true_offset;
-
-                let new_pos = MP::from_dims(pos_buffer);
-
-                adjacents.push(new_pos);
+                if let Some(new_pos) = offset_position(bound_position, x_dim, y_dim) {
+                    adjacents.push(new_pos);
+                }
             }
         }
+
         adjacents
     }
 }
 
+
+/// All tiles within `RADIUS` king moves - a (2*RADIUS+1)-wide square around the tile.
 #[derive(Copy, Clone)]
-pub struct OctileAdjacencyGenerator<MP: MapPosition<2>> {
+pub struct ChebyshevAdjacencyGenerator<MP: MapPosition<2>, const RADIUS: u32> {
     bound_position: PhantomData<MP>
 }
 
-impl<MP: MapPosition<2>> AdjacencyGenerator<2> for OctileAdjacencyGenerator<MP>
+impl<MP: MapPosition<2>, const RADIUS: u32> AdjacencyGenerator<2> for ChebyshevAdjacencyGenerator<MP, RADIUS>
 {
     type Input = MP;
-    type Output = ArrayVec<MP, 8>;
+    type Output = Vec<MP>;
 
     fn adjacents(bound_position: Self::Input) -> Self::Output {
-        let mut adjacents: ArrayVec<MP, 8> = ArrayVec::new();
+        DistanceMetric::Chebyshev.positions_around(bound_position, 1, RADIUS)
+    }
+}
+
+/// All tiles within `RADIUS` rook steps - a diamond around the tile.
+#[derive(Copy, Clone)]
+pub struct ManhattanAdjacencyGenerator<MP: MapPosition<2>, const RADIUS: u32> {
+    bound_position: PhantomData<MP>
+}
 
-        let type_unity: MP::Key = MP::Key::one();
-        let type_three = type_unity + type_unity + type_unity;
-        let pos_array = bound_position.get_dims();
+impl<MP: MapPosition<2>, const RADIUS: u32> AdjacencyGenerator<2> for ManhattanAdjacencyGenerator<MP, RADIUS>
+{
+    type Input = MP;
+    type Output = Vec<MP>;
 
-        let x_range = num::range(
-            MP::Key::zero(),
-            type_three
-        );
+    fn adjacents(bound_position: Self::Input) -> Self::Output {
+        DistanceMetric::Manhattan.positions_around(bound_position, 1, RADIUS)
+    }
+}
 
-        for raw_x_dim in x_range {
-            let x_dim = raw_x_dim - type_unity;
+/// All tiles within a straight-line distance of `RADIUS` - a circle around the tile.
+#[derive(Copy, Clone)]
+pub struct EuclideanAdjacencyGenerator<MP: MapPosition<2>, const RADIUS: u32> {
+    bound_position: PhantomData<MP>
+}
 
-            let y_range = num::range(
-                MP::Key::zero(),
-                type_three
-            );
+impl<MP: MapPosition<2>, const RADIUS: u32> AdjacencyGenerator<2> for EuclideanAdjacencyGenerator<MP, RADIUS>
+{
+    type Input = MP;
+    type Output = Vec<MP>;
 
-            for raw_y_dim in y_range {
-                if raw_x_dim.is_one() && raw_y_dim.is_one() {
-                    continue
-                };
-                let y_dim = raw_y_dim - type_unity;
-                let new_pos = MP::from_dims([
-                    pos_array[0] + x_dim,
-                    pos_array[1] + y_dim
-                ]);
-                adjacents.push(new_pos);
-            }
-        }
+    fn adjacents(bound_position: Self::Input) -> Self::Output {
+        DistanceMetric::Euclidean.positions_around(bound_position, 1, RADIUS)
+    }
+}
 
-        adjacents
+#[cfg(test)]
+mod tests {
+    use crate::position2d::Position2D;
+    use super::*;
+
+    #[test]
+    fn edge_positions_skip_offsets_below_zero() {
+        let corner = Position2D::<u8>::new(0, 0);
+        let edge = Position2D::<u8>::new(0, 5);
+
+        let cardinal_corner: Vec<_> = CardinalAdjacencyGenerator::adjacents(corner).into_iter().collect();
+        assert_eq!(cardinal_corner, vec![Position2D::new(1, 0), Position2D::new(0, 1)]);
+        assert_eq!(CardinalAdjacencyGenerator::adjacents(edge).len(), 3);
+
+        let octile_corner = OctileAdjacencyGenerator::adjacents(corner);
+        assert_eq!(octile_corner.len(), 3);
+        assert!(octile_corner.iter().all(|pos| pos.x <= 1 && pos.y <= 1));
+        assert_eq!(OctileAdjacencyGenerator::adjacents(edge).len(), 5);
     }
-}
\ No newline at end of file
+}
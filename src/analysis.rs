@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::assigner::MapColoringAssigner;
+use crate::map2d::Map2D;
+use crate::map2dnode::MapNodeState;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+/// Statistics of a generated map and how closely it follows the transition rules it was generated with.
+///
+/// Adjacencies are counted from the point of view of each tile, so with a symmetric adjacency
+/// (like the built-in ones) every pair of neighbors is counted twice, once in each direction.
+/// Undecided tiles are left out of everything except `undecided_count`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapStatistics<K: DistributionKey> {
+    pub tile_count: usize,
+    pub undecided_count: usize,
+    /// Number of tiles of each key.
+    pub frequencies: BTreeMap<K, usize>,
+    /// For each key, how many of its neighbors had each key.
+    pub co_occurrence: BTreeMap<K, BTreeMap<K, usize>>,
+    /// For each key with a transition rule, the Jensen-Shannon divergence (in bits, between 0 and 1)
+    /// between the observed keys of its neighbors and the weights of its rule.
+    pub divergence: BTreeMap<K, f64>,
+    pub adjacency_count: usize,
+    /// Adjacencies where the neighbor's key has no weight in the tile's rule,
+    /// or where the tile's key has no rule at all.
+    pub violations: usize,
+    /// Tiles whose key has no transition rule - usually the `K::default()` left over from a contradiction.
+    pub fallback_tiles: usize,
+    /// For each key, the sizes of the connected areas of tiles with that key, largest first.
+    pub component_sizes: BTreeMap<K, Vec<usize>>,
+}
+
+impl<K: DistributionKey> MapStatistics<K> {
+    /// Gathers the statistics of a map, checked against the transition rules of an assigner.
+    pub fn analyze<AG, MP>(map: &Map2D<AG, K, MP>, rules: &MapColoringAssigner<K>) -> Self
+    where
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>
+    {
+        let assignments: HashMap<MP, K> = map.tiles.iter().filter_map(|tile| {
+            let reader = tile.read().unwrap();
+            match reader.state {
+                MapNodeState::Finalized(assignment) => Some((reader.position, assignment)),
+                MapNodeState::Undecided(_) => None
+            }
+        }).collect();
+
+        let mut frequencies: BTreeMap<K, usize> = BTreeMap::new();
+        let mut co_occurrence: BTreeMap<K, BTreeMap<K, usize>> = BTreeMap::new();
+        let mut adjacency_count = 0;
+        let mut violations = 0;
+        let mut fallback_tiles = 0;
+
+        for (pos, key) in assignments.iter() {
+            *frequencies.entry(*key).or_default() += 1;
+            let rule = rules.transition_rules.get(key);
+            if rule.is_none() {
+                fallback_tiles += 1;
+            }
+
+            for neighbor in map.adjacent_from_pos(*pos) {
+                let neighbor_pos = neighbor.read().unwrap().position;
+                let neighbor_key = match assignments.get(&neighbor_pos) {
+                    Some(neighbor_key) => neighbor_key,
+                    None => continue
+                };
+
+                adjacency_count += 1;
+                *co_occurrence.entry(*key).or_default().entry(*neighbor_key).or_default() += 1;
+                if rule.map(|rule| rule.weight(neighbor_key) <= 0.).unwrap_or(true) {
+                    violations += 1;
+                }
+            }
+        }
+
+        let divergence = co_occurrence.iter().filter_map(|(key, observed)| {
+            let rule = rules.transition_rules.get(key)?;
+            let observed_total = observed.values().sum::<usize>() as f64;
+            let expected_total = rule.total_weights() as f64;
+            if observed_total <= 0. || expected_total <= 0. { return None }
+
+            let neighbor_keys: HashSet<&K> = observed.keys().chain(rule.keys()).collect();
+            let pairs: Vec<(f64, f64)> = neighbor_keys.into_iter().map(|neighbor_key| (
+                observed.get(neighbor_key).copied().unwrap_or(0) as f64 / observed_total,
+                rule.weight(neighbor_key).max(0.) as f64 / expected_total
+            )).collect();
+            Some((*key, jensen_shannon(&pairs)))
+        }).collect();
+
+        Self {
+            tile_count: map.tiles.len(),
+            undecided_count: map.tiles.len() - assignments.len(),
+            frequencies,
+            co_occurrence,
+            divergence,
+            adjacency_count,
+            violations,
+            fallback_tiles,
+            component_sizes: component_sizes(map, &assignments),
+        }
+    }
+
+    /// Share of adjacencies that break the rules, between 0 and 1.
+    pub fn violation_rate(&self) -> f64 {
+        match self.adjacency_count {
+            0 => 0.,
+            count => self.violations as f64 / count as f64
+        }
+    }
+}
+
+/// Jensen-Shannon divergence in bits between two discrete distributions,
+/// given as pairs of probabilities of the same outcome.
+fn jensen_shannon(pairs: &[(f64, f64)]) -> f64 {
+    let relative_entropy = |p: f64, m: f64| if p > 0. { p * (p / m).log2() } else { 0. };
+    pairs.iter().map(|(p, q)| {
+        let m = (p + q) / 2.;
+        (relative_entropy(*p, m) + relative_entropy(*q, m)) / 2.
+    }).sum()
+}
+
+fn component_sizes<AG, K, MP>(map: &Map2D<AG, K, MP>, assignments: &HashMap<MP, K>) -> BTreeMap<K, Vec<usize>>
+where
+    AG: AdjacencyGenerator<2, Input = MP>,
+    K: DistributionKey,
+    MP: MapPosition<2>
+{
+    let mut visited = HashSet::with_capacity(assignments.len());
+    let mut sizes: BTreeMap<K, Vec<usize>> = BTreeMap::new();
+
+    for (start, key) in assignments.iter() {
+        if !visited.insert(*start) { continue }
+
+        let mut size = 0;
+        let mut frontier = VecDeque::from([*start]);
+        while let Some(pos) = frontier.pop_front() {
+            size += 1;
+            for neighbor in map.adjacent_from_pos(pos) {
+                let neighbor_pos = neighbor.read().unwrap().position;
+                if assignments.get(&neighbor_pos) == Some(key) && visited.insert(neighbor_pos) {
+                    frontier.push_back(neighbor_pos);
+                }
+            }
+        }
+        sizes.entry(*key).or_default().push(size);
+    }
+
+    sizes.values_mut().for_each(|key_sizes| key_sizes.sort_by_key(|size| std::cmp::Reverse(*size)));
+    sizes
+}
+
+impl<K: DistributionKey> Display for MapStatistics<K> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Map statistics: {} tile(s), {} undecided; {} of {} adjacencies ({:.2}%) break the rules; {} tile(s) with keys missing from the rules",
+            self.tile_count,
+            self.undecided_count,
+            self.violations,
+            self.adjacency_count,
+            100. * self.violation_rate(),
+            self.fallback_tiles
+        )?;
+
+        let assigned = (self.tile_count - self.undecided_count).max(1) as f64;
+        for (key, count) in self.frequencies.iter() {
+            let divergence = match self.divergence.get(key) {
+                Some(divergence) => format!("{:.3} bits", divergence),
+                None => "n/a".to_string()
+            };
+            let components = self.component_sizes.get(key).map(Vec::as_slice).unwrap_or_default();
+            writeln!(
+                f,
+                "  {:?}: {} tile(s) ({:.1}%), divergence from rules {}, {} area(s), largest {}",
+                key,
+                count,
+                100. * *count as f64 / assigned,
+                divergence,
+                components.len(),
+                components.first().copied().unwrap_or(0)
+            )?;
+            let neighbors = self.co_occurrence.get(key).map(
+                |observed| observed.iter().map(|(neighbor, count)| format!("{:?}: {}", neighbor, count)).collect::<Vec<_>>().join(", ")
+            ).unwrap_or_default();
+            writeln!(f, "    neighbors: {{{}}}", neighbors)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use crate::sampler::MultinomialDistribution;
+    use super::*;
+
+    #[test]
+    fn statistics_of_a_striped_map() {
+        // two columns of 1s next to one column of 2s, plus a 0 that no rule covers
+        let tiles = (0..3).flat_map(|x| (0..3).map(move |y| {
+            let key = match (x, y) {
+                (2, 2) => 0,
+                (2, _) => 2,
+                _ => 1
+            };
+            Map2DNode::with_assignment(Position2D::new(x, y), key)
+        }));
+        let map: Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>> = Map2D::from_tiles(tiles);
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (1, MultinomialDistribution::uniform_over([1, 2])),
+            (2, MultinomialDistribution::uniform_over([1])),
+        ]));
+
+        let stats = MapStatistics::analyze(&map, &rules);
+        assert_eq!(stats.frequencies, BTreeMap::from([(0, 1), (1, 6), (2, 2)]));
+        assert_eq!(stats.fallback_tiles, 1);
+        assert_eq!(stats.adjacency_count, 24);
+        // 2-2 and 2-0 both ways, 1-0 one way, and both neighbors of the ruleless 0
+        assert_eq!(stats.violations, 6);
+        assert_eq!(stats.component_sizes[&1], vec![6]);
+        assert_eq!(stats.component_sizes[&2], vec![2]);
+        assert!(stats.divergence[&1] > 0. && stats.divergence[&1] < 1.);
+    }
+
+    #[test]
+    fn matching_neighbors_do_not_diverge() {
+        assert_eq!(jensen_shannon(&[(0.5, 0.5), (0.5, 0.5)]), 0.);
+        assert!((jensen_shannon(&[(1., 0.), (0., 1.)]) - 1.).abs() < 1e-9);
+    }
+}
@@ -1,19 +1,30 @@
 use std::borrow::Borrow;
 use std::collections::binary_heap::BinaryHeap;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ops::Deref;
 use std::sync::{Arc, RwLock};
 
+use num::ToPrimitive;
+use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use rayon::prelude::*;
 
 use crate::map2d::Map2D;
-use crate::sampler::{DistributionKey, MultinomialDistribution};
+use crate::sampler::{DistributionKey, EntropyMetric, MultinomialDistribution};
 use crate::adjacency::AdjacencyGenerator;
-use crate::map2dnode::{MapNodeEntropyOrdering, MapNodeState, MapNodeWrapper};
+use crate::boundary::BoundaryConditions;
+use crate::distance::{offset_position, DistanceBand};
+use crate::field::LoadedModulation;
+use crate::map2dnode::{MapNodeState, ThreadsafeNodeRef};
 use crate::position::{MapPosition};
+use crate::region::RegionMap;
+use crate::selection::{CellSelection, PrioritizedNode, SelectionHeuristic};
+use crate::symmetry::Symmetry;
 
-type Queue<AG, K, MP> = Arc<RwLock<BinaryHeap<MapNodeEntropyOrdering<AG, K, MP>>>>;
+type Queue<AG, K, MP> = Arc<RwLock<BinaryHeap<PrioritizedNode<AG, K, MP>>>>;
+
+/// Called with the map being collapsed and the number of tiles the job has collapsed so far.
+pub type SnapshotHook<AG, K, MP> = Arc<dyn Fn(&Map2D<AG, K, MP>, usize) + Send + Sync>;
 
 
 #[derive(Serialize, Deserialize)]
@@ -26,6 +37,12 @@ enum QueueState {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct MapColoringAssigner<K: DistributionKey> {
     pub(crate) transition_rules: HashMap<K, MultinomialDistribution<K>>,
+    /// How the uncertainty of a tile is measured when ranking it for collapse.
+    #[serde(default)]
+    pub(crate) priority_metric: EntropyMetric,
+    /// Rules for tiles further away than the direct neighbors, applied on top of the transition rules.
+    #[serde(default)]
+    pub(crate) distance_rules: Vec<DistanceBand<K>>,
     comments: Option<String>
 }
 
@@ -33,9 +50,243 @@ impl<K: DistributionKey> MapColoringAssigner<K> {
     pub fn with_rules(rules: HashMap<K, MultinomialDistribution<K>>) -> Self {
         Self {
             transition_rules: rules,
+            priority_metric: EntropyMetric::default(),
+            distance_rules: Vec::new(),
             comments: None
         }
     }
+
+    pub fn with_priority_metric(mut self, metric: EntropyMetric) -> Self {
+        self.priority_metric = metric;
+        self
+    }
+
+    pub fn with_distance_rules(mut self, bands: Vec<DistanceBand<K>>) -> Self {
+        self.distance_rules = bands;
+        self
+    }
+
+    /// Applies the distance bands for a freshly assigned tile to the undecided tiles around it.
+    ///
+    /// **Returns**: the tiles whose possibilities were updated.
+    fn propagate_distance_rules<AG, MP>(&self, map: &Map2D<AG, K, MP>, position: MP, assignment: K) -> Vec<ThreadsafeNodeRef<AG, K, MP>>
+    where
+        AG: AdjacencyGenerator<2>,
+        MP: MapPosition<2>
+    {
+        let mut updated = Vec::new();
+
+        for band in self.distance_rules.iter() {
+            let band_rules = match band.rules.get(&assignment) {
+                Some(rules) => rules,
+                None => continue
+            };
+
+            for pos in band.positions_around(position) {
+                let tile = match map.position_index.get(&pos) {
+                    Some(tile) => tile,
+                    None => continue
+                };
+
+                let mut tile_writer = tile.write().unwrap();
+                let conditioned = match &tile_writer.state {
+                    MapNodeState::Undecided(dist) => band_rules.joint_probability(dist),
+                    MapNodeState::Finalized(_) => continue
+                };
+
+                if conditioned.total_weights() > 0. {
+                    tile_writer.state = MapNodeState::from(conditioned);
+                    updated.push(tile.to_owned());
+                }
+            }
+        }
+        updated
+    }
+
+    /// Narrows down the possibilities of the undecided tiles at the specified positions
+    /// based on the rules of all their already-assigned neighbors, as if those neighbors
+    /// had just been collapsed. Used to stitch re-generated areas into the existing map.
+    ///
+    /// Tiles whose possibilities would be narrowed down to nothing are left as they were.
+    pub fn condition_on_neighbors<AG, MP, I>(&self, map: &Map2D<AG, K, MP>, positions: I)
+    where
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>,
+        I: IntoIterator<Item = MP>
+    {
+        self.condition_on_neighbors_in_regions(map, positions, None)
+    }
+
+    /// Like `condition_on_neighbors()`, but with the rules of each tile and neighbor
+    /// looked up in the regions first, falling back to these rules outside of them.
+    pub fn condition_on_neighbors_in_regions<AG, MP, I>(&self, map: &Map2D<AG, K, MP>, positions: I, regions: Option<&RegionMap<K, MP>>)
+    where
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>,
+        I: IntoIterator<Item = MP>
+    {
+        for pos in positions {
+            let tile = match map.position_index.get(&pos) {
+                Some(tile) => tile,
+                None => continue
+            };
+
+            let mut dist = match &tile.read().unwrap().state {
+                MapNodeState::Undecided(dist) => dist.to_owned(),
+                MapNodeState::Finalized(_) => continue
+            };
+            let tile_rules = rules_at(self, regions, &pos);
+
+            for neighbor in map.adjacent_from_pos(pos) {
+                let neighbor_reader = neighbor.read().unwrap();
+                let conditioned = match &neighbor_reader.state {
+                    MapNodeState::Finalized(assignment) => condition_across(
+                        rules_at(self, regions, &neighbor_reader.position), tile_rules, assignment, &dist
+                    ),
+                    MapNodeState::Undecided(_) => None
+                };
+
+                if let Some(conditioned) = conditioned {
+                    if conditioned.total_weights() > 0. {
+                        dist = conditioned;
+                    }
+                }
+            }
+
+            tile.write().unwrap().state = MapNodeState::from(dist);
+        }
+    }
+}
+
+/// The rules in force at a position - those of its region, if any, or the default ones.
+fn rules_at<'a, K: DistributionKey, MP: MapPosition<2>>(
+    default: &'a MapColoringAssigner<K>,
+    regions: Option<&'a RegionMap<K, MP>>,
+    position: &MP
+) -> &'a MapColoringAssigner<K> {
+    regions.and_then(|regions| regions.rules_at(position)).unwrap_or(default)
+}
+
+/// Conditions the possibilities of a tile on a neighbor having been assigned a key.
+///
+/// Within a single region, this is just the transition rule for the key. Across a region border,
+/// the rule of the tile's own region for the key applies first, then the rule of the assigned tile's
+/// region on top of it; whichever of the two would rule out every possibility is skipped.
+///
+/// **Returns**: the new possibilities, or None if no rule applies.
+fn condition_across<K: DistributionKey>(
+    source_rules: &MapColoringAssigner<K>,
+    target_rules: &MapColoringAssigner<K>,
+    assignment: &K,
+    possibilities: &MultinomialDistribution<K>
+) -> Option<MultinomialDistribution<K>> {
+    if std::ptr::eq(source_rules, target_rules) {
+        return source_rules.transition_rules.get(assignment).map(|rules| rules.joint_probability(possibilities))
+    }
+
+    let mut conditioned = None;
+    for rules in [target_rules, source_rules].iter().filter_map(|rules| rules.transition_rules.get(assignment)) {
+        let joint = rules.joint_probability(conditioned.as_ref().unwrap_or(possibilities));
+        if joint.total_weights() > 0. {
+            conditioned = Some(joint);
+        }
+    }
+    conditioned
+}
+
+/// The possibilities of a tile, narrowed down to the keys its symmetric counterparts can take along with it.
+///
+/// Each key is weighted by how likely each counterpart is to take the key it maps to, and ruled out if
+/// any two of the tiles would end up next to each other with keys their rules do not allow together.
+/// If that would rule out every option, the possibilities are returned as they are.
+fn symmetric_possibilities<AG, K, MP>(
+    symmetry: &Symmetry<K>,
+    rules: &MapColoringAssigner<K>,
+    regions: Option<&RegionMap<K, MP>>,
+    map: &Map2D<AG, K, MP>,
+    position: MP,
+    possibilities: &MultinomialDistribution<K>,
+    bounds: [MP; 2]
+) -> MultinomialDistribution<K>
+where
+    AG: AdjacencyGenerator<2>,
+    AG::Input: Borrow<MP> + From<MP>,
+    K: DistributionKey,
+    MP: MapPosition<2>
+{
+    let joint = possibilities.reweighted(|key, weight| {
+        let mut collapsed = vec![(position, *key)];
+        collapsed.extend(symmetry.counterparts(position, *key, bounds));
+
+        let mut weight = weight;
+        for (counterpart_pos, counterpart_key) in collapsed.iter().skip(1) {
+            weight *= match map.position_index.get(counterpart_pos).map(|tile| tile.read().unwrap().state.to_owned()) {
+                Some(MapNodeState::Undecided(dist)) => dist.weight(counterpart_key),
+                Some(MapNodeState::Finalized(assigned)) if assigned != *counterpart_key => 0.,
+                _ => 1.
+            };
+        }
+
+        for (pos, pos_key) in collapsed.iter() {
+            let pos_rules = match rules_at(rules, regions, pos).transition_rules.get(pos_key) {
+                Some(pos_rules) => pos_rules,
+                None => continue
+            };
+            let neighbors: Vec<AG::Input> = AG::adjacents((*pos).into()).into_iter().collect();
+            let clashes = collapsed.iter().any(
+                |(other_pos, other_key)| neighbors.iter().any(|neighbor| neighbor.borrow() == other_pos) && pos_rules.weight(other_key) <= 0.
+            );
+            if clashes { return 0. }
+        }
+        weight
+    });
+
+    match joint.total_weights() > 0. {
+        true => joint,
+        false => possibilities.to_owned()
+    }
+}
+
+fn default_heuristic<MP: MapPosition<2>>() -> Arc<dyn SelectionHeuristic<MP>> {
+    Arc::new(CellSelection::default())
+}
+
+fn default_chunk_size() -> u32 {
+    64
+}
+
+fn fresh_rng() -> StdRng {
+    StdRng::from_entropy()
+}
+
+/// How many tiles away along either axis the adjacency reaches from the position.
+fn adjacency_reach<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(position: MP) -> i64 {
+    let [x, y] = position.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
+    AG::adjacents(position).into_iter().map(|neighbor| {
+        let [nx, ny] = neighbor.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
+        (nx - x).abs().max((ny - y).abs())
+    }).max().unwrap_or(0)
+}
+
+/// Queues a tile with the priority the heuristic gives it, based on its current possibilities.
+pub(crate) fn enqueue<AG, K, MP>(
+    queue: &mut BinaryHeap<PrioritizedNode<AG, K, MP>>,
+    heuristic: &dyn SelectionHeuristic<MP>,
+    metric: EntropyMetric,
+    rng: &mut StdRng,
+    bounds: [MP; 2],
+    tile: &ThreadsafeNodeRef<AG, K, MP>,
+) where
+    AG: AdjacencyGenerator<2>,
+    K: DistributionKey,
+    MP: MapPosition<2>
+{
+    let (position, entropy) = {
+        let tile_reader = tile.read().unwrap();
+        (tile_reader.position, f64::from(tile_reader.entropy_with(metric)))
+    };
+    let priority = heuristic.priority(position, entropy, bounds, rng);
+    queue.push(PrioritizedNode::new(priority, tile.to_owned(), rng));
 }
 
 #[derive(Serialize, Deserialize)]
@@ -43,7 +294,29 @@ pub struct MapColoringJob<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: Map
     rules: MapColoringAssigner<K>,
     pub map: Arc<RwLock<Map2D<AG, K, MP>>>,
     queue: Queue<AG, K, MP>,
-    queue_state: QueueState
+    queue_state: QueueState,
+    #[serde(skip, default = "default_heuristic")]
+    heuristic: Arc<dyn SelectionHeuristic<MP>>,
+    #[serde(skip, default = "fresh_rng")]
+    rng: StdRng,
+    #[serde(skip, default = "default_chunk_size")]
+    chunk_size: u32,
+    #[serde(skip)]
+    modulations: Vec<Arc<LoadedModulation<K>>>,
+    /// Bounds the modulating fields are laid out over, if not the bounds of the job's own map.
+    #[serde(skip)]
+    modulation_bounds: Option<[MP; 2]>,
+    /// Rules overriding the job's own ones in parts of the map.
+    #[serde(skip)]
+    regions: Option<Arc<RegionMap<K, MP>>>,
+    /// A hook to look at the map while it is being collapsed, and how many tiles to collapse between calls.
+    #[serde(skip)]
+    snapshots: Option<(usize, SnapshotHook<AG, K, MP>)>,
+    /// Counterparts of each collapsed tile to collapse along with it.
+    #[serde(skip)]
+    symmetry: Option<Symmetry<K>>,
+    #[serde(skip)]
+    collapsed: usize
 }
 
 impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> MapColoringJob<AG, K, MP>
@@ -59,25 +332,172 @@ where <AG as AdjacencyGenerator<2>>::Input: Borrow<MP> + From<MP>
             rules,
             map: wrapped_map,
             queue: wrapped_queue,
-            queue_state: QueueState::Uninitialized
+            queue_state: QueueState::Uninitialized,
+            heuristic: default_heuristic(),
+            rng: fresh_rng(),
+            chunk_size: default_chunk_size(),
+            modulations: Vec::new(),
+            modulation_bounds: None,
+            regions: None,
+            snapshots: None,
+            symmetry: None,
+            collapsed: 0
+        }
+    }
+
+    /// Seeds the job's random number generator; the same seed, rules and starting map
+    /// always produce the same map.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Swaps out the heuristic used to pick which tile gets collapsed next.
+    /// Tiles already in the queue keep their old priorities.
+    pub fn with_heuristic(mut self, heuristic: Box<dyn SelectionHeuristic<MP>>) -> Self {
+        self.heuristic = Arc::from(heuristic);
+        self
+    }
+
+    /// Sets the scalar fields that bias the weights of each tile right before it gets collapsed.
+    pub fn with_modulations(mut self, modulations: Vec<Arc<LoadedModulation<K>>>) -> Self {
+        self.modulations = modulations;
+        self
+    }
+
+    /// Lays the modulating fields out over the bounds instead of the bounds of the job's own map,
+    /// e.g. the bounds of the whole map for a job working on a part of it.
+    pub fn with_modulation_bounds(mut self, bounds: [MP; 2]) -> Self {
+        self.modulation_bounds = Some(bounds);
+        self
+    }
+
+    /// Makes parts of the map follow rules of their own instead of the job's rules.
+    ///
+    /// The undecided tiles of each region are narrowed down to the keys its rules know about,
+    /// unless that would leave them with no possibilities at all.
+    pub fn with_regions(mut self, regions: RegionMap<K, MP>) -> Self {
+        if !regions.is_empty() {
+            let map_reader = self.map.read().unwrap();
+            for (pos, tile) in map_reader.undecided_tiles.iter() {
+                let region_rules = match regions.rules_at(pos) {
+                    Some(rules) => rules,
+                    None => continue
+                };
+
+                let mut tile_writer = tile.write().unwrap();
+                let restricted = match &tile_writer.state {
+                    MapNodeState::Undecided(dist) => dist.reweighted(
+                        |key, weight| if region_rules.transition_rules.contains_key(key) { weight } else { 0. }
+                    ),
+                    MapNodeState::Finalized(_) => continue
+                };
+                if restricted.total_weights() > 0. {
+                    tile_writer.state = MapNodeState::from(restricted);
+                }
+            }
         }
+        self.regions = Some(Arc::new(regions));
+        self
+    }
+
+    /// Conditions the undecided tiles along the edges of the bounds on the virtual tiles just outside them.
+    /// Tiles are conditioned by the rules of their own region, and never narrowed down to nothing.
+    ///
+    /// The bounds are usually those of the job's own map, but a job working on a part of a bigger map
+    /// should use the bounds of the whole map, so that only its true edges are affected.
+    pub fn with_boundary(self, boundary: &BoundaryConditions<K>, bounds: [MP; 2]) -> Self {
+        if !boundary.is_empty() {
+            let map_reader = self.map.read().unwrap();
+            for (pos, tile) in map_reader.undecided_tiles.iter() {
+                let outside_keys = boundary.outside_keys(*pos, bounds);
+                if outside_keys.is_empty() { continue }
+
+                let mut tile_writer = tile.write().unwrap();
+                let mut dist = match &tile_writer.state {
+                    MapNodeState::Undecided(dist) => dist.to_owned(),
+                    MapNodeState::Finalized(_) => continue
+                };
+
+                let tile_rules = rules_at(&self.rules, self.regions.as_deref(), pos);
+                for outside_rules in outside_keys.iter().filter_map(|key| tile_rules.transition_rules.get(key)) {
+                    let conditioned = outside_rules.joint_probability(&dist);
+                    if conditioned.total_weights() > 0. {
+                        dist = conditioned;
+                    }
+                }
+                tile_writer.state = MapNodeState::from(dist);
+            }
+        }
+        self
+    }
+
+    /// Conditions the undecided tiles at the positions on their finalized neighbors, as found by the map's
+    /// adjacency, e.g. to fit a re-generated area of a map into the rest of it.
+    /// Tiles are conditioned by the rules of their own region, and never narrowed down to nothing.
+    pub fn with_conditioned_tiles<I: IntoIterator<Item = MP>>(self, positions: I) -> Self
+    where
+        AG: AdjacencyGenerator<2, Input = MP>
+    {
+        {
+            let map_reader = self.map.read().unwrap();
+            self.rules.condition_on_neighbors_in_regions(&map_reader, positions, self.regions.as_deref());
+        }
+        self
+    }
+
+    /// Calls the hook with the map every `every` collapsed tiles, e.g. to render
+    /// the uncertainty of the map as it gets collapsed with a HeatmapVisualizer.
+    ///
+    /// In parallel assignment, the hook is called after each of the four phases instead,
+    /// with the whole map.
+    pub fn with_snapshots(mut self, every: usize, hook: SnapshotHook<AG, K, MP>) -> Self {
+        self.snapshots = Some((every.max(1), hook));
+        self
+    }
+
+    /// Collapses the counterparts of each tile along with it, mirrored or rotated across the job's map.
+    ///
+    /// Each tile's key is sampled from the keys that its counterparts can take along with it. If the rules
+    /// leave no such key, counterparts are finalized with their key even if their neighbors rule it out,
+    /// so the rules should allow for symmetric maps. Symmetric jobs are always assigned sequentially.
+    pub fn with_symmetry(mut self, symmetry: Symmetry<K>) -> Self {
+        self.symmetry = Some(symmetry);
+        self
+    }
+
+    /// Sets the edge length of the chunks the map gets split into for parallel assignment.
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
     }
 
     fn build_queue(&mut self) -> &Queue<AG, K, MP> {
         let map_reader = self.map.read().unwrap();
         let wrapped_queue = &self.queue;
         let mut queue_writer = wrapped_queue.write().unwrap();
+        let bounds = [map_reader.min_pos, map_reader.max_pos];
 
-        for tile_lock in map_reader.undecided_tiles.values() {
-            let tile_reader = tile_lock.read().unwrap();
-            let is_assigned = tile_reader.state.is_assigned();
-            if is_assigned { continue };
+        let mut undecided: Vec<MP> = map_reader.undecided_tiles
+            .iter()
+            .filter(|(_, tile)| !tile.read().unwrap().state.is_assigned())
+            .map(|(pos, _)| pos.to_owned())
+            .collect();
+        // HashMap order is arbitrary; sorting keeps seeding reproducible for a given RNG
+        undecided.sort_by_key(|pos| pos.get_dims());
 
-            let tile = tile_reader.deref().to_owned();
-            let ord_tile = MapNodeEntropyOrdering::from(tile);
+        let mut seeds = self.heuristic.seeds(&undecided, bounds, &mut self.rng);
+        seeds.retain(|pos| undecided.binary_search_by_key(&pos.get_dims(), |u| u.get_dims()).is_ok());
+        if seeds.is_empty() {
+            // e.g. all the seed points have already been assigned
+            seeds.extend(undecided.first());
+        }
 
-            queue_writer.push(ord_tile);
-            break; // we only want the first uninitialized element
+        for pos in seeds {
+            if let Some(tile) = map_reader.undecided_tiles.get(&pos) {
+                let metric = rules_at(&self.rules, self.regions.as_deref(), &pos).priority_metric;
+                enqueue(&mut queue_writer, self.heuristic.as_ref(), metric, &mut self.rng, bounds, tile);
+            }
         }
         self.queue_state = QueueState::Initialized;
         wrapped_queue
@@ -94,20 +514,16 @@ where <AG as AdjacencyGenerator<2>>::Input: Borrow<MP> + From<MP>
         let mut queue_writer = self.queue.write().unwrap();
         let map = &self.map;
         let mut map_operator = map.write().unwrap();
+        let bounds = [map_operator.min_pos, map_operator.max_pos];
+        let requeue = self.heuristic.reprioritize_on_update();
 
-        let mut enqueued = HashSet::with_capacity(map_operator.undecided_tiles.len());
-        match queue_writer.peek() {
-            Some(enqueued_node) => enqueued.insert(enqueued_node.node.position()),
-            None => false
-        };
+        let mut enqueued: HashSet<MP> = queue_writer
+            .iter()
+            .map(|entry| entry.node.read().unwrap().position)
+            .collect();
 
-        while !queue_writer.is_empty() {
-            let assignee = &queue_writer.pop().unwrap().node;
-            let raw_node = match assignee {
-                MapNodeWrapper::Raw(node) => Arc::new(RwLock::new(node.to_owned())),
-                MapNodeWrapper::Arc(node) => node.to_owned(),
-            };
-            let mut node = raw_node.write().unwrap();
+        while let Some(assignee) = queue_writer.pop() {
+            let mut node = assignee.node.write().unwrap();
             let node_state = &node.state.to_owned();
             let curr_pos = node.position;
 
@@ -121,156 +537,214 @@ where <AG as AdjacencyGenerator<2>>::Input: Borrow<MP> + From<MP>
                 }
             };
 
-            let new_assignment = possibilities.sample_with_default_rng();
-            // println!("Assigning {:?} => {:?}", node.position, new_assignment);
+            let symmetric;
+            let possibilities = match &self.symmetry {
+                Some(symmetry) => {
+                    symmetric = symmetric_possibilities(symmetry, &self.rules, self.regions.as_deref(), &map_operator, curr_pos, possibilities, bounds);
+                    &symmetric
+                },
+                None => possibilities
+            };
 
-            let self_rule_probas = match self.rules.transition_rules.get(&new_assignment) {
-                Some(probas) => probas,
-                None => continue
+            let new_assignment = match self.modulations.is_empty() {
+                true => possibilities.sample(&mut self.rng),
+                false => self.modulations.iter().fold(
+                    possibilities.to_owned(),
+                    |dist, modulation| modulation.modulate(curr_pos, self.modulation_bounds.unwrap_or(bounds), &dist)
+                ).sample(&mut self.rng)
             };
+            // println!("Assigning {:?} => {:?}", node.position, new_assignment);
 
             node.state = MapNodeState::from(new_assignment);
-
-            let neighbors = map_operator.adjacent(node.deref());
             drop(node);
 
-            neighbors.iter().for_each(|neighbor| {
-                // println!("Acquiring lock for neighbor {:?}...", neighbor);
-                let maybe_neighbor_rule_probas;
-                {
-                    // sub-scope to free up the reader after use
-                    let neighbor_reader = neighbor.read().unwrap();
-                    maybe_neighbor_rule_probas = match &neighbor_reader.state {
-                        MapNodeState::Undecided(probas) => Some(probas.to_owned()),
-                        MapNodeState::Finalized(_) => None
+            let mut collapsed_tiles = vec![(curr_pos, new_assignment)];
+            if let Some(symmetry) = &self.symmetry {
+                for (counterpart_pos, counterpart_key) in symmetry.counterparts(curr_pos, new_assignment, bounds) {
+                    let counterpart = match map_operator.position_index.get(&counterpart_pos) {
+                        Some(tile) => tile.to_owned(),
+                        None => continue
                     };
-                }
+                    let mut counterpart_writer = counterpart.write().unwrap();
+                    if counterpart_writer.state.is_assigned() { continue }
 
-                if let Some(neighbor_rule_probas) = maybe_neighbor_rule_probas {
-                    let mut neighbor_writer = neighbor.write().unwrap();
-                    let new_possibilities = self_rule_probas.joint_probability(&neighbor_rule_probas);
-                    neighbor_writer.state = MapNodeState::from(new_possibilities);
-                    //println!("Assigned new probas for neighbor {:?}!", neighbor);
+                    counterpart_writer.state = MapNodeState::from(counterpart_key);
+                    map_operator.undecided_tiles.remove(&counterpart_pos);
+                    collapsed_tiles.push((counterpart_pos, counterpart_key));
+                }
+            }
 
-                    let neigh_pos = neighbor_writer.position;
-                    drop(neighbor_writer);
+            let collapsed_count = collapsed_tiles.len();
+            let regions = self.regions.as_deref();
+            for (curr_pos, new_assignment) in collapsed_tiles {
+                let source_rules = rules_at(&self.rules, regions, &curr_pos);
 
-                    let neigh_queued = enqueued.get(&neigh_pos).is_some();
+                for banded in source_rules.propagate_distance_rules(&map_operator, curr_pos, new_assignment) {
+                    let banded_pos = banded.read().unwrap().position;
+                    if requeue || enqueued.insert(banded_pos) {
+                        let metric = rules_at(&self.rules, regions, &banded_pos).priority_metric;
+                        enqueue(&mut queue_writer, self.heuristic.as_ref(), metric, &mut self.rng, bounds, &banded);
+                    }
+                }
 
-                    if !neigh_queued {
-                        let wrapped_neighbor = MapNodeEntropyOrdering::from(neighbor.to_owned());
-                        queue_writer.push(wrapped_neighbor);
+                let neighbors = map_operator.adjacent_from_pos(curr_pos.into());
+                for neighbor in neighbors.iter() {
+                    // println!("Acquiring lock for neighbor {:?}...", neighbor);
+                    let maybe_neighbor_rule_probas;
+                    let neigh_pos;
+                    {
+                        // sub-scope to free up the reader after use
+                        let neighbor_reader = neighbor.read().unwrap();
+                        neigh_pos = neighbor_reader.position;
+                        maybe_neighbor_rule_probas = match &neighbor_reader.state {
+                            MapNodeState::Undecided(probas) => Some(probas.to_owned()),
+                            MapNodeState::Finalized(_) => None
+                        };
                     }
 
+                    if let Some(neighbor_rule_probas) = maybe_neighbor_rule_probas {
+                        let neighbor_rules = rules_at(&self.rules, regions, &neigh_pos);
+                        let new_possibilities = match condition_across(source_rules, neighbor_rules, &new_assignment, &neighbor_rule_probas) {
+                            Some(conditioned) => conditioned,
+                            None => continue
+                        };
+
+                        let mut neighbor_writer = neighbor.write().unwrap();
+                        neighbor_writer.state = MapNodeState::from(new_possibilities);
+                        //println!("Assigned new probas for neighbor {:?}!", neighbor);
+                        drop(neighbor_writer);
+
+                        if requeue || enqueued.insert(neigh_pos) {
+                            enqueue(&mut queue_writer, self.heuristic.as_ref(), neighbor_rules.priority_metric, &mut self.rng, bounds, neighbor);
+                        }
+                    }
                 }
+            }
 
-                // no real return value
-            })
+            let collapsed_before = self.collapsed;
+            self.collapsed += collapsed_count;
+            if let Some((every, hook)) = &self.snapshots {
+                // counterparts can take the count past a multiple without landing on it
+                if collapsed_before / every != self.collapsed / every {
+                    hook(&map_operator, self.collapsed);
+                }
+            }
         }
 
         map
     }
 
+    /// Seeds the queue and collapses the map.
+    ///
+    /// Propagation only ever reaches tiles connected to the seed through undecided tiles,
+    /// so this re-seeds and keeps going until no undecided tiles are left to seed from
+    /// (e.g. after several disjoint pockets of the map have been un-assigned).
     pub fn queue_and_assign(&mut self) -> &Arc<RwLock<Map2D<AG, K, MP>>> {
-        self.build_queue();
-        self.assign_map()
+        loop {
+            self.build_queue();
+            if self.queue.read().unwrap().is_empty() { break }
+            self.assign_map();
+        }
+        &self.map
+    }
+
+    /// Consumes the job, returning the (possibly partially) assigned map.
+    pub fn into_map(self) -> Map2D<AG, K, MP> {
+        match Arc::try_unwrap(self.map) {
+            Ok(lock) => lock.into_inner().unwrap(),
+            Err(shared) => shared.read().unwrap().to_owned()
+        }
     }
 }
 
 
-impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> MapColoringJob<AG, K, MP>
+impl<AG, K, MP> MapColoringJob<AG, K, MP>
 where
-    AG: Send + Sync,
-    K: Send + Sync,
-    MP: Send + Sync,
-    <AG as AdjacencyGenerator<2>>::Input: Borrow<MP> + From<MP>
+    AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
+    K: DistributionKey + Send + Sync,
+    MP: MapPosition<2> + Send + Sync
 {
+    /// Collapses the map in parallel, chunk by chunk.
+    ///
+    /// The map is split into square chunks `chunk_size` tiles across, colored like a checkerboard
+    /// by the parity of their column and row. Chunks of the same color are a whole chunk apart, and the chunks
+    /// are made at least one tile wider than the adjacency reaches (which matters for the radius-based generators),
+    /// so they never touch. Each of the four colors is one phase in which all its chunks get collapsed at once
+    /// on the rayon pool, each by a job
+    /// of its own that only sees the chunk's tiles. Before a chunk is collapsed, its tiles are conditioned
+    /// on the finalized tiles around them, which stitches it onto the chunks collapsed in earlier phases.
+    ///
+    /// Each chunk gets its own seed drawn from the job's RNG, so a seeded job produces
+    /// the same map regardless of the number of threads.
+    ///
+    /// Symmetric jobs are assigned sequentially instead, as the counterparts of a tile lie in other chunks,
+    /// and so are jobs with distance rules, as propagation stops at the edge of the chunk.
     pub fn par_assign_map(&mut self) -> &Arc<RwLock<Map2D<AG, K, MP>>>
     {
-        let mut queue_writer = self.queue.write().unwrap();
+        let has_distance_rules = !self.rules.distance_rules.is_empty()
+            || self.regions.as_deref().is_some_and(RegionMap::has_distance_rules);
+        if self.symmetry.is_some() || has_distance_rules {
+            return self.queue_and_assign()
+        }
+
         let map = &self.map;
         let mut map_operator = map.write().unwrap();
+        let [min_x, min_y] = map_operator.min_pos.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
+        let [max_x, max_y] = map_operator.max_pos.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
 
-        let mut enqueued = HashSet::with_capacity(map_operator.undecided_tiles.len());
-        match queue_writer.peek() {
-            Some(enqueued_node) => enqueued.insert(enqueued_node.node.position()),
-            None => false
-        };
-
-        while !queue_writer.is_empty() {
-            let assignee = &queue_writer.pop().unwrap().node;
-            let raw_node = match assignee {
-                MapNodeWrapper::Raw(node) => Arc::new(RwLock::new(node.to_owned())),
-                MapNodeWrapper::Arc(node) => node.to_owned(),
-            };
-            let mut node = raw_node.write().unwrap();
-            let node_state = &node.state.to_owned();
-            let curr_pos = node.position;
-
-            enqueued.remove(&curr_pos);
-            map_operator.undecided_tiles.remove(&curr_pos);
-
-            let possibilities = match node_state {
-                MapNodeState::Undecided(probas) => probas,
-                MapNodeState::Finalized(_) => {
-                    continue
-                }
-            };
-
-            let new_assignment = possibilities.sample_with_default_rng();
-            // println!("Assigning {:?} => {:?}", node.position, new_assignment);
-
-            let self_rule_probas = match self.rules.transition_rules.get(&new_assignment) {
-                Some(probas) => probas,
-                None => continue
-            };
+        // measured in the middle of the map, where the map's edges cannot cut the neighbors off
+        let center = offset_position(map_operator.min_pos, (max_x - min_x) / 2, (max_y - min_y) / 2).unwrap_or(map_operator.min_pos);
+        let chunk_size = i64::from(self.chunk_size.max(1)).max(adjacency_reach::<AG, MP>(center) + 1);
 
-            node.state = MapNodeState::from(new_assignment);
-
-            let neighbors = map_operator.adjacent(node.deref());
-            let par_indices = 0..neighbors.len();
-            drop(node);
-
-            par_indices.into_par_iter().for_each(|idx| {
-                let neighbor = neighbors.get(idx).unwrap();
-                // println!("Acquiring lock for neighbor {:?}...", neighbor);
-                let maybe_neighbor_rule_probas;
-                {
-                    // sub-scope to free up the reader after use
-                    let neighbor_reader = neighbor.read().unwrap();
-                    maybe_neighbor_rule_probas = match &neighbor_reader.state {
-                        MapNodeState::Undecided(probas) => Some(probas.to_owned()),
-                        MapNodeState::Finalized(_) => None
-                    };
-                }
-
-                if let Some(neighbor_rule_probas) = maybe_neighbor_rule_probas {
-                    let mut neighbor_writer = neighbor.write().unwrap();
-                    let new_possibilities = self_rule_probas.joint_probability(&neighbor_rule_probas);
-                    neighbor_writer.state = MapNodeState::from(new_possibilities);
-                    //println!("Assigned new probas for neighbor {:?}!", neighbor);
+        let mut chunks: BTreeMap<(i64, i64), Vec<MP>> = BTreeMap::new();
+        for (pos, tile) in map_operator.undecided_tiles.iter() {
+            if tile.read().unwrap().state.is_assigned() { continue }
+            let [x, y] = pos.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
+            let chunk = ((x - min_x).div_euclid(chunk_size), (y - min_y).div_euclid(chunk_size));
+            chunks.entry(chunk).or_default().push(*pos);
+        }
 
-                    let neigh_pos = neighbor_writer.position;
-                    drop(neighbor_writer);
+        // drawn up-front and in a fixed order, so that the thread scheduling cannot affect the result
+        let seeded_chunks: Vec<((i64, i64), Vec<MP>, u64)> = chunks.into_iter().map(
+            |(chunk, mut positions)| {
+                positions.sort_by_key(|pos| pos.get_dims());
+                (chunk, positions, self.rng.gen())
+            }
+        ).collect();
 
-                    let neigh_queued = enqueued.get(&neigh_pos).is_some();
+        let full_map = map_operator.deref();
+        let full_bounds = [full_map.min_pos, full_map.max_pos];
+        for phase in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            seeded_chunks
+                .par_iter()
+                .filter(|((cx, cy), _, _)| (cx.rem_euclid(2), cy.rem_euclid(2)) == phase)
+                .for_each(|(_, positions, seed)| {
+                    self.rules.condition_on_neighbors_in_regions(full_map, positions.iter().copied(), self.regions.as_deref());
 
-                    if !neigh_queued {
-                        let wrapped_neighbor = MapNodeEntropyOrdering::from(neighbor.to_owned());
-                        let mut sub_queue_writer = self.queue.write().unwrap();
-                        sub_queue_writer.push(wrapped_neighbor);
-                    }
-                }
+                    let mut chunk_job = MapColoringJob::new(self.rules.to_owned(), full_map.submap(positions.iter()))
+                        .with_seed(*seed);
+                    chunk_job.heuristic = self.heuristic.to_owned();
+                    chunk_job.modulations = self.modulations.to_owned();
+                    chunk_job.modulation_bounds = Some(self.modulation_bounds.unwrap_or(full_bounds));
+                    chunk_job.regions = self.regions.to_owned();
+                    chunk_job.queue_and_assign();
+                });
 
-                // no real return value
-            });
-        };
+            if let Some((_, hook)) = &self.snapshots {
+                self.collapsed += seeded_chunks.iter()
+                    .filter(|((cx, cy), _, _)| (cx.rem_euclid(2), cy.rem_euclid(2)) == phase)
+                    .map(|(_, positions, _)| positions.len())
+                    .sum::<usize>();
+                hook(full_map, self.collapsed);
+            }
+        }
 
+        map_operator.undecided_tiles.retain(|_, tile| !tile.read().unwrap().state.is_assigned());
+        drop(map_operator);
         map
     }
 
     pub fn par_queue_and_assign(&mut self) -> &Arc<RwLock<Map2D<AG, K, MP>>> {
-        self.build_queue();
         self.par_assign_map()
     }
 }
@@ -278,27 +752,32 @@ where
 #[cfg(test)]
 mod tests {
     use itertools::Itertools;
+    use crate::adjacency::ChebyshevAdjacencyGenerator;
+    use crate::distance::DistanceMetric;
+    use crate::field::{FieldModulation, ScalarField, WeightCurve};
     use crate::map2dnode::Map2DNode;
     use crate::OctileAdjacencyGenerator;
     use crate::position2d::Position2D;
     use super::*;
 
-    #[test]
-    fn small_assignment() {
+    type TestMap = Map2D<OctileAdjacencyGenerator<Position2D<i64>>, i32, Position2D<i64>>;
+
+    fn test_map() -> TestMap {
         const TEST_MAP_SIZE: i64 = 10;
         let tile_positions = (0..TEST_MAP_SIZE).cartesian_product(0..TEST_MAP_SIZE);
         let test_tiles = tile_positions.map(
-            |(x, y)| Map2DNode::<
-                OctileAdjacencyGenerator<Position2D<i64>>, i32, Position2D<i64>
-            >::with_possibilities(
+            |(x, y)| Map2DNode::with_possibilities(
                 Position2D::new(
-                    i64::from(x),
-                    i64::from(y)
+                    x,
+                    y
                 ),
                 MultinomialDistribution::uniform_over(vec![1, 2, 3])
             )
         );
-        let testmap = Map2D::from_tiles(test_tiles);
+        Map2D::from_tiles(test_tiles)
+    }
+
+    fn test_rules() -> MapColoringAssigner<i32> {
         let rules = HashMap::from([
             (1, MultinomialDistribution::from(
                 HashMap::from([
@@ -319,13 +798,233 @@ mod tests {
                 ])
             )),
         ]);
+        MapColoringAssigner::with_rules(rules)
+    }
 
-        let assignment_rules = MapColoringAssigner::with_rules(rules);
-        let mut job = MapColoringJob::new_with_queue(assignment_rules, testmap);
+    #[test]
+    fn small_assignment() {
+        let mut job = MapColoringJob::new_with_queue(test_rules(), test_map());
         let pre_run_state = &job.map.read().unwrap().undecided_tiles.to_owned();
-        assert!(pre_run_state.len() > 0);
+        assert!(!pre_run_state.is_empty());
         job.queue_and_assign();
         let post_run_state = &job.map.read().unwrap().undecided_tiles.to_owned();
         assert_eq!(post_run_state.len(), 0);
     }
+
+    #[test]
+    fn assignment_with_heuristics() {
+        let heuristics = [
+            CellSelection::Scanline,
+            CellSelection::Random,
+            CellSelection::Spiral { origin: [4, 4] },
+            CellSelection::MultiSeed { seeds: vec![[0, 0], [9, 9]] },
+        ];
+        for heuristic in heuristics {
+            let mut job = MapColoringJob::new(test_rules(), test_map()).with_heuristic(Box::new(heuristic));
+            job.queue_and_assign();
+            let map = job.into_map();
+            assert!(map.undecided_tiles.is_empty());
+            assert!(map.tiles.iter().all(|tile| tile.read().unwrap().state.is_assigned()));
+        }
+    }
+
+    #[test]
+    fn assignment_with_priority_metrics() {
+        let metrics = [
+            EntropyMetric::RemainingOptions,
+            EntropyMetric::Collision,
+            EntropyMetric::Renyi { alpha: 0.5 },
+        ];
+        for metric in metrics {
+            let mut job = MapColoringJob::new(test_rules().with_priority_metric(metric), test_map());
+            job.queue_and_assign();
+            let map = job.into_map();
+            assert!(map.undecided_tiles.is_empty());
+        }
+    }
+
+    #[test]
+    fn seeded_assignment_is_reproducible() {
+        let generate = |seed| {
+            let mut job = MapColoringJob::new(test_rules(), test_map()).with_seed(seed);
+            job.queue_and_assign();
+            let map = job.into_map();
+            map.tiles.iter().map(|tile| match tile.read().unwrap().state {
+                MapNodeState::Finalized(assignment) => assignment,
+                MapNodeState::Undecided(_) => 0
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(generate(1234), generate(1234));
+    }
+
+    #[test]
+    fn distance_rules_reach_past_neighbors() {
+        let anything = MultinomialDistribution::uniform_over(vec![1, 2]);
+        let rules = MapColoringAssigner::with_rules(HashMap::from([(1, anything.to_owned()), (2, anything)]))
+            .with_distance_rules(vec![DistanceBand::new(
+                DistanceMetric::Chebyshev, 1, 3,
+                HashMap::from([(2, MultinomialDistribution::uniform_over(vec![1]))])
+            )]);
+
+        let mut job = MapColoringJob::new(rules, test_map()).with_seed(5);
+        job.queue_and_assign();
+        let map = job.into_map();
+
+        let twos: Vec<Position2D<i64>> = map.tiles.iter().filter_map(|tile| {
+            let reader = tile.read().unwrap();
+            matches!(reader.state, MapNodeState::Finalized(2)).then_some(reader.position)
+        }).collect();
+
+        assert!(!twos.is_empty());
+        for (a, b) in twos.iter().tuple_combinations() {
+            assert!((a.x - b.x).abs().max((a.y - b.y).abs()) > 3, "{:?} and {:?} are too close", a, b);
+        }
+    }
+
+    #[test]
+    fn distance_rules_reach_across_chunks() {
+        let anything = MultinomialDistribution::uniform_over(vec![1, 2]);
+        let rules = MapColoringAssigner::with_rules(HashMap::from([(1, anything.to_owned()), (2, anything)]))
+            .with_distance_rules(vec![DistanceBand::new(
+                DistanceMetric::Chebyshev, 1, 3,
+                HashMap::from([(2, MultinomialDistribution::uniform_over(vec![1]))])
+            )]);
+
+        for seed in 0..10 {
+            // chunks two tiles across, well within the band
+            let mut job = MapColoringJob::new(rules.to_owned(), test_map()).with_seed(seed).with_chunk_size(2);
+            job.par_queue_and_assign();
+            let map = job.into_map();
+
+            let twos: Vec<Position2D<i64>> = map.tiles.iter().filter_map(|tile| {
+                let reader = tile.read().unwrap();
+                matches!(reader.state, MapNodeState::Finalized(2)).then_some(reader.position)
+            }).collect();
+
+            for (a, b) in twos.iter().tuple_combinations() {
+                assert!((a.x - b.x).abs().max((a.y - b.y).abs()) > 3, "{:?} and {:?} are too close", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn chunked_assignment_is_thread_independent() {
+        let generate = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let mut job = MapColoringJob::new(test_rules(), test_map()).with_seed(99).with_chunk_size(3);
+            pool.install(|| { job.par_queue_and_assign(); });
+            let map = job.into_map();
+            assert!(map.undecided_tiles.is_empty());
+            map.tiles.iter().map(|tile| match tile.read().unwrap().state {
+                MapNodeState::Finalized(assignment) => assignment,
+                MapNodeState::Undecided(_) => 0
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(generate(1), generate(4));
+    }
+
+    #[test]
+    fn chunks_are_wider_than_the_adjacency_reach() {
+        type RadiusMap = Map2D<ChebyshevAdjacencyGenerator<Position2D<i64>, 2>, i32, Position2D<i64>>;
+
+        let generate = |threads| {
+            let map: RadiusMap = Map2D::from_tiles((0..10i64).cartesian_product(0..10i64).map(
+                |(x, y)| Map2DNode::with_possibilities(Position2D::new(x, y), MultinomialDistribution::uniform_over(vec![1, 2, 3]))
+            ));
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            // chunks one tile across would be well within reach of each other
+            let mut job = MapColoringJob::new(test_rules(), map).with_seed(7).with_chunk_size(1);
+            pool.install(|| { job.par_queue_and_assign(); });
+            let map = job.into_map();
+            assert!(map.undecided_tiles.is_empty());
+            map.tiles.iter().map(|tile| match tile.read().unwrap().state {
+                MapNodeState::Finalized(assignment) => assignment,
+                MapNodeState::Undecided(_) => 0
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(generate(1), generate(4));
+    }
+
+    #[test]
+    fn sampling_modulation_biases_assignment() {
+        let anything = MultinomialDistribution::uniform_over(vec![1, 2, 3]);
+        let rules = MapColoringAssigner::with_rules(HashMap::from([(1, anything.to_owned()), (2, anything.to_owned()), (3, anything)]));
+        let modulation = |keys: &[i32]| Arc::new(FieldModulation::new(
+            ScalarField::Value { seed: 0, frequency: 0.1, octaves: 1 },
+            keys.iter().map(|key| (*key, WeightCurve(vec![[0., 0.]]))).collect()
+        ).load().unwrap());
+
+        let generate = |modulation| {
+            let mut job = MapColoringJob::new(rules.to_owned(), test_map()).with_modulations(vec![modulation]);
+            job.queue_and_assign();
+            let map = job.into_map();
+            // never the default key, even where the field rules out every option
+            map.tiles.iter().map(|tile| match tile.read().unwrap().state {
+                MapNodeState::Finalized(assignment) => assignment,
+                MapNodeState::Undecided(_) => 0
+            }).collect::<Vec<_>>()
+        };
+
+        assert!(generate(modulation(&[1])).iter().all(|key| [2, 3].contains(key)));
+        assert!(generate(modulation(&[1, 2, 3])).iter().all(|key| [1, 2, 3].contains(key)));
+    }
+
+    #[test]
+    fn regions_follow_their_own_rules() {
+        let ones = MultinomialDistribution::uniform_over(vec![1]);
+        let default_rules = MapColoringAssigner::with_rules(HashMap::from([(1, ones.to_owned()), (2, ones)]));
+        let left_rules = MapColoringAssigner::with_rules(HashMap::from([(2, MultinomialDistribution::uniform_over(vec![2]))]));
+
+        let map = test_map();
+        map.tiles.iter().for_each(|tile| tile.write().unwrap().state = MapNodeState::from(MultinomialDistribution::uniform_over(vec![1, 2])));
+        let mut regions = RegionMap::default();
+        regions.add_region(left_rules, map.tiles.iter().map(|tile| tile.read().unwrap().position).filter(|pos| pos.x < 5));
+
+        let mut job = MapColoringJob::new(default_rules, map)
+            .with_seed(3)
+            .with_heuristic(Box::new(CellSelection::Scanline))
+            .with_regions(regions);
+        job.queue_and_assign();
+        let map = job.into_map();
+
+        for tile in map.tiles.iter() {
+            let reader = tile.read().unwrap();
+            let expected = if reader.position.x < 5 { 2 } else { 1 };
+            assert!(matches!(reader.state, MapNodeState::Finalized(key) if key == expected), "wrong key at {:?}", reader.position);
+        }
+    }
+
+    #[test]
+    fn boundary_conditions_border_tiles() {
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (1, MultinomialDistribution::uniform_over(vec![1])),
+            (2, MultinomialDistribution::uniform_over(vec![2])),
+            (3, MultinomialDistribution::uniform_over(vec![3])),
+        ]));
+        let map = test_map();
+        let bounds = [map.min_pos, map.max_pos];
+
+        let mut job = MapColoringJob::new(rules, map)
+            .with_heuristic(Box::new(CellSelection::Scanline))
+            .with_boundary(&BoundaryConditions { left: Some(2), ..Default::default() }, bounds);
+        job.queue_and_assign();
+        let map = job.into_map();
+
+        // the left edge is all 2s, and the rules make every tile copy the one before it
+        assert!(map.tiles.iter().all(|tile| matches!(tile.read().unwrap().state, MapNodeState::Finalized(2))));
+    }
+
+    #[test]
+    fn snapshots_see_the_map_mid_collapse() {
+        let seen = Arc::new(RwLock::new(Vec::new()));
+        let recorder = seen.to_owned();
+        let hook: SnapshotHook<_, _, _> = Arc::new(move |map: &TestMap, collapsed| {
+            let undecided = map.tiles.iter().filter(|tile| !tile.read().unwrap().state.is_assigned()).count();
+            recorder.write().unwrap().push((collapsed, undecided));
+        });
+
+        let mut job = MapColoringJob::new(test_rules(), test_map()).with_snapshots(25, hook);
+        job.queue_and_assign();
+        assert_eq!(seen.read().unwrap().as_slice(), &[(25, 75), (50, 50), (75, 25), (100, 0)]);
+    }
 }
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+/// Virtual tiles surrounding a map on each side.
+///
+/// Before the map is collapsed, the undecided tiles along each side are conditioned on the side's key
+/// as if a tile with that key had already been placed just outside the map, e.g. to keep land
+/// from running into the edge of a landmass map. Sides without a key are left alone.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoundaryConditions<K: DistributionKey> {
+    /// The side with the lowest X coordinates.
+    #[serde(default)]
+    pub left: Option<K>,
+    /// The side with the highest X coordinates.
+    #[serde(default)]
+    pub right: Option<K>,
+    /// The side with the lowest Y coordinates.
+    #[serde(default)]
+    pub top: Option<K>,
+    /// The side with the highest Y coordinates.
+    #[serde(default)]
+    pub bottom: Option<K>,
+}
+
+impl<K: DistributionKey> BoundaryConditions<K> {
+    /// The same key on every side.
+    pub fn all(key: K) -> Self {
+        Self {
+            left: Some(key),
+            right: Some(key),
+            top: Some(key),
+            bottom: Some(key)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left.is_none() && self.right.is_none() && self.top.is_none() && self.bottom.is_none()
+    }
+
+    /// The keys of the virtual tiles next to a position, for each side of the bounds it lies on.
+    /// Corner positions lie on two sides.
+    pub fn outside_keys<MP: MapPosition<2>>(&self, position: MP, bounds: [MP; 2]) -> Vec<K> {
+        let [x, y] = position.get_dims();
+        let [[min_x, min_y], [max_x, max_y]] = bounds.map(|bound| bound.get_dims());
+
+        [
+            (x == min_x, self.left),
+            (x == max_x, self.right),
+            (y == min_y, self.top),
+            (y == max_y, self.bottom),
+        ].into_iter().filter_map(|(on_side, key)| key.filter(|_| on_side)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::position2d::Position2D;
+    use super::*;
+
+    #[test]
+    fn corners_get_both_sides() {
+        let boundary = BoundaryConditions { left: Some(1), top: Some(2), ..Default::default() };
+        let bounds = [Position2D::new(0u8, 0), Position2D::new(9, 9)];
+
+        assert_eq!(boundary.outside_keys(Position2D::new(0, 0), bounds), vec![1, 2]);
+        assert_eq!(boundary.outside_keys(Position2D::new(0, 5), bounds), vec![1]);
+        assert!(boundary.outside_keys(Position2D::new(9, 9), bounds).is_empty());
+        assert!(boundary.outside_keys(Position2D::new(5, 5), bounds).is_empty());
+    }
+}
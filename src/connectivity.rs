@@ -0,0 +1,285 @@
+use std::collections::{HashSet, VecDeque};
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::map2d::Map2D;
+use crate::map2dnode::MapNodeState;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+fn default_max_attempts() -> u32 { 10 }
+
+fn default_margin() -> u32 { 1 }
+
+/// Declares a set of tile keys as 'walkable'; all walkable tiles on the map
+/// should form a single connected component under the map's adjacency.
+///
+/// The constraint is enforced after the map is collapsed, by un-assigning every
+/// component except the largest one (plus a margin around it) and re-generating it.
+/// If that does not join things up within `max_attempts` rounds, the leftover strays
+/// are re-generated using only the non-walkable tiles.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectivityConstraint<K: DistributionKey> {
+    pub walkable: HashSet<K>,
+    /// How many re-generation rounds to try before giving up.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// How many steps around each stray component are re-generated along with it;
+    /// grows with each failed attempt to give the region more room to join up.
+    #[serde(default = "default_margin")]
+    pub margin: u32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectivityReport<MP: MapPosition<2>> {
+    /// Walkable components found on the final map, largest first.
+    pub components: Vec<Vec<MP>>,
+    /// Number of components found before any re-generation took place.
+    pub initial_component_count: usize,
+    /// Number of re-generation rounds performed.
+    pub attempts: u32,
+    /// Number of stray tiles that ended up non-walkable when filled in after the re-generation attempts ran out.
+    pub filled_tiles: usize,
+}
+
+impl<MP: MapPosition<2>> ConnectivityReport<MP> {
+    pub fn is_connected(&self) -> bool {
+        self.components.len() <= 1
+    }
+
+    pub fn component_sizes(&self) -> Vec<usize> {
+        self.components.iter().map(|c| c.len()).collect()
+    }
+}
+
+impl<K: DistributionKey> ConnectivityConstraint<K> {
+    pub fn new<I: IntoIterator<Item=K>>(walkable: I) -> Self {
+        Self {
+            walkable: walkable.into_iter().collect(),
+            max_attempts: default_max_attempts(),
+            margin: default_margin()
+        }
+    }
+
+    fn is_walkable<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: &Map2D<AG, K, MP>, pos: &MP) -> bool {
+        map.get(pos).map(
+            |tile| match tile.read().unwrap().state {
+                MapNodeState::Finalized(assignment) => self.walkable.contains(&assignment),
+                MapNodeState::Undecided(_) => false
+            }
+        ).unwrap_or(false)
+    }
+
+    /// Finds all connected components of walkable tiles, sorted largest first.
+    pub fn find_components<AG, MP>(&self, map: &Map2D<AG, K, MP>) -> Vec<Vec<MP>>
+    where
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>
+    {
+        let mut visited = HashSet::new();
+        let mut components = Vec::new();
+
+        for tile in &map.tiles {
+            let start = tile.read().unwrap().get_position();
+            if visited.contains(&start) || !self.is_walkable(map, &start) { continue }
+
+            let mut component = Vec::new();
+            let mut frontier = VecDeque::from([start]);
+            visited.insert(start);
+
+            while let Some(pos) = frontier.pop_front() {
+                component.push(pos);
+                for neighbor in map.adjacent_from_pos(pos) {
+                    let neighbor_pos = neighbor.read().unwrap().get_position();
+                    if !visited.contains(&neighbor_pos) && self.is_walkable(map, &neighbor_pos) {
+                        visited.insert(neighbor_pos);
+                        frontier.push_back(neighbor_pos);
+                    }
+                }
+            }
+            components.push(component);
+        }
+
+        components.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        components
+    }
+
+    /// Re-generates stray walkable components until the map is connected
+    /// or the attempt budget runs out. Strays still left after that are filled in
+    /// with non-walkable tiles, so the constraint holds on the returned map - unless every key
+    /// is walkable, in which case there is nothing to fill them in with and they are left as they are.
+    ///
+    ///  **Arguments**:
+    /// * `map` - a fully collapsed map.
+    /// * `keys` - all the keys the map may be made of.
+    /// * `regenerate` - re-generates an area of the map using only the specified keys,
+    ///   e.g. with a job set up by the Ruleset; should draw a new seed on each call.
+    ///
+    /// **Returns**: the updated map and a report of the components found.
+    ///
+    pub fn enforce<AG, MP, F>(&self, map: Map2D<AG, K, MP>, keys: &HashSet<K>, mut regenerate: F) -> (Map2D<AG, K, MP>, ConnectivityReport<MP>)
+    where
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>,
+        F: FnMut(Map2D<AG, K, MP>, &HashSet<MP>, &HashSet<K>) -> Map2D<AG, K, MP>
+    {
+        let mut map = map;
+        let mut components = self.find_components(&map);
+        let initial_component_count = components.len();
+        let mut attempts = 0;
+
+        while components.len() > 1 && attempts < self.max_attempts {
+            attempts += 1;
+            let margin = self.margin * attempts;
+
+            // the main component stays put; everything else around the strays is fair game
+            let main_component: HashSet<MP> = components[0].iter().copied().collect();
+            let mut region: HashSet<MP> = components.iter().skip(1).flatten().copied().collect();
+            let mut ring: Vec<MP> = region.iter().copied().collect();
+
+            for _ in 0..margin {
+                let mut next_ring = Vec::new();
+                for pos in ring {
+                    for neighbor in map.adjacent_from_pos(pos) {
+                        let neighbor_pos = neighbor.read().unwrap().get_position();
+                        if !main_component.contains(&neighbor_pos) && region.insert(neighbor_pos) {
+                            next_ring.push(neighbor_pos);
+                        }
+                    }
+                }
+                ring = next_ring;
+            }
+
+            map = regenerate(map, &region, keys);
+            components = self.find_components(&map);
+        }
+
+        let mut filled_tiles = 0;
+        if components.len() > 1 {
+            let region: HashSet<MP> = components.iter().skip(1).flatten().copied().collect();
+            let blocking_keys: HashSet<K> = keys.difference(&self.walkable).copied().collect();
+
+            if blocking_keys.is_empty() {
+                eprintln!("WARNING: every key is walkable, leaving {} stray tile(s) disconnected", region.len());
+            } else {
+                map = regenerate(map, &region, &blocking_keys);
+                filled_tiles = region.iter().filter(|pos| !self.is_walkable(&map, pos)).count();
+                components = self.find_components(&map);
+            }
+        }
+
+        let report = ConnectivityReport {
+            components,
+            initial_component_count,
+            attempts,
+            filled_tiles
+        };
+        (map, report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::assigner::{MapColoringAssigner, MapColoringJob};
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use crate::sampler::MultinomialDistribution;
+    use super::*;
+
+    const FLOOR: i32 = 1;
+    const WALL: i32 = 2;
+
+    type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<i64>>, i32, Position2D<i64>>;
+
+    /// A 7x3 strip of wall with two separate floor pockets in the middle row.
+    fn two_pocket_map() -> TestMap {
+        let tiles = (0..7i64).flat_map(|x| (0..3i64).map(move |y| (x, y))).map(
+            |(x, y)| {
+                let key = match (x, y) {
+                    (1, 1) | (2, 1) | (5, 1) => FLOOR,
+                    _ => WALL
+                };
+                Map2DNode::with_assignment(Position2D::new(x, y), key)
+            }
+        );
+        Map2D::from_tiles(tiles)
+    }
+
+    /// Re-generates an area like the Ruleset does, minus the seeds, regions and so on.
+    fn regenerate_with(rules: &MapColoringAssigner<i32>) -> impl FnMut(TestMap, &HashSet<Position2D<i64>>, &HashSet<i32>) -> TestMap + '_ {
+        |mut map, region, keys| {
+            let region_tiles: Vec<_> = region.iter().filter_map(|pos| map.get(pos).cloned()).collect();
+            map.unassign_tiles(region_tiles.iter(), MultinomialDistribution::uniform_over(keys.iter().copied()));
+            rules.condition_on_neighbors(&map, region.iter().copied());
+
+            let mut job = MapColoringJob::new(rules.to_owned(), map);
+            job.queue_and_assign();
+            job.into_map()
+        }
+    }
+
+    #[test]
+    fn finds_separate_components() {
+        let constraint = ConnectivityConstraint::new([FLOOR]);
+        let components = constraint.find_components(&two_pocket_map());
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].len(), 2);
+        assert_eq!(components[1].len(), 1);
+    }
+
+    #[test]
+    fn enforce_regenerates_stray_pockets() {
+        // walls only ever neighbor walls, so a re-rolled pocket must fill in with walls
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (FLOOR, MultinomialDistribution::from(HashMap::from([(FLOOR, 1.), (WALL, 1.)]))),
+            (WALL, MultinomialDistribution::from(HashMap::from([(WALL, 1.)]))),
+        ]));
+        let mut constraint = ConnectivityConstraint::new([FLOOR]);
+        constraint.margin = 0;
+
+        let keys = HashSet::from([FLOOR, WALL]);
+        let (map, report) = constraint.enforce(two_pocket_map(), &keys, regenerate_with(&rules));
+        assert_eq!(report.initial_component_count, 2);
+        assert!(report.is_connected());
+        assert_eq!(report.component_sizes(), vec![2]);
+        assert_eq!(report.filled_tiles, 0);
+        assert!(map.undecided_tiles.is_empty());
+    }
+
+    #[test]
+    fn strays_are_kept_without_blocking_keys() {
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (FLOOR, MultinomialDistribution::from(HashMap::from([(FLOOR, 1.)]))),
+        ]));
+        let mut constraint = ConnectivityConstraint::new([FLOOR]);
+        constraint.max_attempts = 0;
+
+        // with nothing to fill the stray pocket in with, it has to stay as it is
+        let keys = HashSet::from([FLOOR]);
+        let (map, report) = constraint.enforce(two_pocket_map(), &keys, regenerate_with(&rules));
+        assert_eq!(report.component_sizes(), vec![2, 1]);
+        assert_eq!(report.filled_tiles, 0);
+        assert!(map.tiles.iter().all(|tile| matches!(tile.read().unwrap().state, MapNodeState::Finalized(FLOOR | WALL))));
+    }
+
+    #[test]
+    fn filled_tiles_only_counts_tiles_that_were_blocked() {
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (FLOOR, MultinomialDistribution::from(HashMap::from([(FLOOR, 1.), (WALL, 1.)]))),
+            (WALL, MultinomialDistribution::from(HashMap::from([(FLOOR, 1.), (WALL, 1.)]))),
+        ]));
+        let mut constraint = ConnectivityConstraint::new([FLOOR]);
+        constraint.max_attempts = 0;
+        let keys = HashSet::from([FLOOR, WALL]);
+
+        let (_, report) = constraint.enforce(two_pocket_map(), &keys, regenerate_with(&rules));
+        assert!(report.is_connected());
+        assert_eq!(report.filled_tiles, 1);
+
+        // a fill that leaves the stray as it was has not blocked anything
+        let (_, report) = constraint.enforce(two_pocket_map(), &keys, |map, _, _| map);
+        assert_eq!(report.component_sizes(), vec![2, 1]);
+        assert_eq!(report.filled_tiles, 0);
+    }
+}
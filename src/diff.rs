@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::map2d::Map2D;
+use crate::map2dnode::MapNodeState;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+/// A tile that differs between two maps. Undecided tiles, and positions missing from a map, have no key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileChange<K: DistributionKey, MP: MapPosition<2>> {
+    pub position: MP,
+    pub before: Option<K>,
+    pub after: Option<K>,
+}
+
+/// The differences between two maps of the same extent, e.g. generated from two rulesets or two seeds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapDiff<K: DistributionKey, MP: MapPosition<2>> {
+    /// Number of positions on either map.
+    pub tile_count: usize,
+    /// The changed tiles, ordered by position.
+    pub changes: Vec<TileChange<K, MP>>,
+}
+
+impl<K: DistributionKey, MP: MapPosition<2>> Default for MapDiff<K, MP> {
+    fn default() -> Self {
+        Self {
+            tile_count: 0,
+            changes: Vec::new()
+        }
+    }
+}
+
+impl<K: DistributionKey, MP: MapPosition<2>> MapDiff<K, MP> {
+    /// Compares two maps tile by tile.
+    pub fn between<AG: AdjacencyGenerator<2>>(before: &Map2D<AG, K, MP>, after: &Map2D<AG, K, MP>) -> Self {
+        let key_at = |map: &Map2D<AG, K, MP>, pos: &MP| map.position_index.get(pos).and_then(
+            |tile| match tile.read().unwrap().state {
+                MapNodeState::Finalized(key) => Some(key),
+                MapNodeState::Undecided(_) => None
+            }
+        );
+
+        let positions: HashSet<MP> = before.position_index.keys().chain(after.position_index.keys()).copied().collect();
+        let mut changes: Vec<TileChange<K, MP>> = positions.iter().filter_map(|pos| {
+            let (old_key, new_key) = (key_at(before, pos), key_at(after, pos));
+            let unchanged = old_key == new_key
+                && before.position_index.contains_key(pos) == after.position_index.contains_key(pos);
+            match unchanged {
+                true => None,
+                false => Some(TileChange { position: *pos, before: old_key, after: new_key })
+            }
+        }).collect();
+        changes.sort_by_key(|change| change.position.get_dims());
+
+        Self {
+            tile_count: positions.len(),
+            changes
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Share of the tiles that changed, between 0 and 1.
+    pub fn changed_share(&self) -> f64 {
+        match self.tile_count {
+            0 => 0.,
+            count => self.changes.len() as f64 / count as f64
+        }
+    }
+
+    pub fn changed_positions(&self) -> HashSet<MP> {
+        self.changes.iter().map(|change| change.position).collect()
+    }
+
+    /// How many tiles changed from each key to each other key.
+    pub fn transitions(&self) -> BTreeMap<(Option<K>, Option<K>), usize> {
+        let mut transitions = BTreeMap::new();
+        for change in self.changes.iter() {
+            *transitions.entry((change.before, change.after)).or_default() += 1;
+        }
+        transitions
+    }
+
+    /// The changes by position, for quick lookups.
+    pub fn by_position(&self) -> HashMap<MP, &TileChange<K, MP>> {
+        self.changes.iter().map(|change| (change.position, change)).collect()
+    }
+}
+
+impl<K: DistributionKey, MP: MapPosition<2>> Display for MapDiff<K, MP> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Map diff: {} of {} tile(s) changed ({:.2}%)",
+            self.changes.len(),
+            self.tile_count,
+            100. * self.changed_share()
+        )?;
+        let key_name = |key: &Option<K>| match key {
+            Some(key) => format!("{:?}", key),
+            None => "-".to_string()
+        };
+        for ((before, after), count) in self.transitions() {
+            writeln!(f, "  {} -> {}: {}", key_name(&before), key_name(&after), count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The positions on either side of the edge of a mask, up to `width` steps (as given by the map's adjacency)
+/// away from it; e.g. the seam left after pasting the masked part of one map into another.
+pub fn seam_around<AG, K, MP>(map: &Map2D<AG, K, MP>, mask: &HashSet<MP>, width: u32) -> HashSet<MP>
+where
+    AG: AdjacencyGenerator<2, Input = MP>,
+    K: DistributionKey,
+    MP: MapPosition<2>
+{
+    if width == 0 { return HashSet::new() }
+
+    let neighbors_of = |pos: MP| map.adjacent_from_pos(pos).into_iter().map(|tile| tile.read().unwrap().position).collect::<Vec<_>>();
+    let mut seam: HashSet<MP> = map.position_index.keys().filter(
+        |pos| neighbors_of(**pos).iter().any(|neighbor| mask.contains(neighbor) != mask.contains(pos))
+    ).copied().collect();
+
+    let mut ring: Vec<MP> = seam.iter().copied().collect();
+    for _ in 1..width {
+        let mut next_ring = Vec::new();
+        for pos in ring {
+            for neighbor in neighbors_of(pos) {
+                if seam.insert(neighbor) {
+                    next_ring.push(neighbor);
+                }
+            }
+        }
+        ring = next_ring;
+    }
+    seam
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use super::*;
+
+    type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+
+    fn filled(key_at: impl Fn(u8, u8) -> u8) -> TestMap {
+        Map2D::from_tiles((0..4).flat_map(|x| (0..4).map(move |y| (x, y))).map(
+            |(x, y)| Map2DNode::with_assignment(Position2D::new(x, y), key_at(x, y))
+        ))
+    }
+
+    #[test]
+    fn diffs_list_changed_tiles() {
+        let before = filled(|_, _| 1);
+        let after = filled(|x, y| if x == 0 && y < 2 { 2 } else { 1 });
+
+        let diff = MapDiff::between(&before, &after);
+        assert_eq!(diff.tile_count, 16);
+        assert_eq!(diff.changed_positions(), HashSet::from([Position2D::new(0, 0), Position2D::new(0, 1)]));
+        assert_eq!(diff.changed_share(), 2. / 16.);
+        assert_eq!(diff.transitions(), BTreeMap::from([((Some(1), Some(2)), 2)]));
+        assert!(MapDiff::between(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn seams_straddle_the_mask_edge() {
+        let map = filled(|_, _| 1);
+        let mask: HashSet<_> = (0..4).map(|y| Position2D::new(0, y)).collect();
+
+        assert_eq!(seam_around(&map, &mask, 1).len(), 8);
+        assert_eq!(seam_around(&map, &mask, 2).len(), 12);
+        assert!(seam_around(&map, &mask, 0).is_empty());
+    }
+}
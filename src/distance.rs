@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use num::{NumCast, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use crate::position::MapPosition;
+use crate::sampler::{DistributionKey, MultinomialDistribution};
+
+/// How the distance between two tiles is measured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DistanceMetric {
+    /// Number of king moves - disks are squares.
+    #[default]
+    Chebyshev,
+    /// Number of rook steps - disks are diamonds.
+    Manhattan,
+    /// Straight-line distance - disks are (pixelated) circles.
+    Euclidean,
+}
+
+impl DistanceMetric {
+    pub fn distance(&self, dx: i64, dy: i64) -> f64 {
+        let (dx, dy) = (dx.unsigned_abs() as f64, dy.unsigned_abs() as f64);
+        match self {
+            Self::Chebyshev => dx.max(dy),
+            Self::Manhattan => dx + dy,
+            Self::Euclidean => dx.hypot(dy),
+        }
+    }
+
+    /// All offsets whose distance from the origin falls within `[min_distance, max_distance]`,
+    /// row by row. The origin itself is never included.
+    pub fn offsets(&self, min_distance: u32, max_distance: u32) -> Vec<(i64, i64)> {
+        let radius = max_distance as i64;
+        let (min_distance, max_distance) = (min_distance as f64, max_distance as f64);
+        let mut offsets = Vec::new();
+
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                if dx == 0 && dy == 0 { continue }
+                let distance = self.distance(dx, dy);
+                if distance >= min_distance && distance <= max_distance {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        offsets
+    }
+
+    /// Positions of all tiles within the distance band around a position.
+    /// Offsets that do not fit the position type (e.g. negative coordinates for unsigned keys) are skipped.
+    pub fn positions_around<MP: MapPosition<2>>(&self, position: MP, min_distance: u32, max_distance: u32) -> Vec<MP> {
+        self.offsets(min_distance, max_distance).into_iter().filter_map(
+            |(dx, dy)| offset_position(position, dx, dy)
+        ).collect()
+    }
+}
+
+pub fn offset_position<MP: MapPosition<2>>(position: MP, dx: i64, dy: i64) -> Option<MP> {
+    let [x, y] = position.get_dims();
+    let new_x = <MP::Key as NumCast>::from(x.to_i64()?.checked_add(dx)?)?;
+    let new_y = <MP::Key as NumCast>::from(y.to_i64()?.checked_add(dy)?)?;
+    Some(MP::from_dims([new_x, new_y]))
+}
+
+/// Transition rules that reach beyond the direct neighbors of a tile.
+///
+/// When a tile gets assigned, the possibilities of all undecided tiles between `min_distance`
+/// and `max_distance` away are multiplied by the band's rule for the assigned key, just like
+/// the direct neighbors are with the regular transition rules. Keys missing from a band's
+/// rule are ruled out for the tiles in the band; keys without a rule leave the band alone.
+///
+/// Unlike regular transitions, a band never narrows a tile down to nothing - if it would,
+/// the tile is left as it was.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DistanceBand<K: DistributionKey> {
+    #[serde(default)]
+    pub metric: DistanceMetric,
+    pub min_distance: u32,
+    pub max_distance: u32,
+    pub rules: HashMap<K, MultinomialDistribution<K>>,
+}
+
+impl<K: DistributionKey> DistanceBand<K> {
+    pub fn new(metric: DistanceMetric, min_distance: u32, max_distance: u32, rules: HashMap<K, MultinomialDistribution<K>>) -> Self {
+        Self {
+            metric,
+            min_distance,
+            max_distance,
+            rules
+        }
+    }
+
+    pub fn positions_around<MP: MapPosition<2>>(&self, position: MP) -> Vec<MP> {
+        self.metric.positions_around(position, self.min_distance, self.max_distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::position2d::Position2D;
+    use super::*;
+
+    #[test]
+    fn disk_shapes() {
+        assert_eq!(DistanceMetric::Chebyshev.offsets(1, 2).len(), 24);
+        assert_eq!(DistanceMetric::Manhattan.offsets(1, 2).len(), 12);
+        assert_eq!(DistanceMetric::Euclidean.offsets(1, 2).len(), 12);
+        assert_eq!(DistanceMetric::Chebyshev.offsets(2, 2).len(), 16);
+    }
+
+    #[test]
+    fn positions_skip_unrepresentable_offsets() {
+        let around_origin = DistanceMetric::Manhattan.positions_around(Position2D::<u8>::new(0, 0), 1, 2);
+        assert_eq!(around_origin.len(), 5);
+        assert!(around_origin.contains(&Position2D::new(1, 1)));
+    }
+}
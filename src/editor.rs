@@ -0,0 +1,249 @@
+use std::collections::HashSet;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::distance::DistanceMetric;
+use crate::map2d::Map2D;
+use crate::position::MapPosition;
+use crate::ruleset::{begin_regeneration, GeneratorRuleset};
+use crate::sampler::DistributionKey;
+
+/// The area around a position that an edit affects.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Brush {
+    pub radius: u32,
+    #[serde(default)]
+    pub metric: DistanceMetric,
+}
+
+impl Brush {
+    pub fn new(radius: u32, metric: DistanceMetric) -> Self {
+        Self {
+            radius,
+            metric
+        }
+    }
+
+    /// The position itself and every position within the radius of it.
+    pub fn positions<MP: MapPosition<2>>(&self, center: MP) -> Vec<MP> {
+        let mut positions = vec![center];
+        positions.extend(self.metric.positions_around(center, 1, self.radius));
+        positions
+    }
+}
+
+/// High-level edits of a generated map, e.g. for an in-game editor.
+///
+/// Tiles can be locked, so that no edit ever changes them; unlocked tiles get re-generated around every edit,
+/// following the rules of the Ruleset and fitting in with the tiles around them.
+/// Every edit is recorded in the map's history as a single step, so it can be undone.
+pub struct MapEditor<'r, AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> {
+    ruleset: &'r GeneratorRuleset<K>,
+    map: Map2D<AG, K, MP>,
+    locked: HashSet<MP>,
+    rng: StdRng,
+}
+
+impl<'r, AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<2>> MapEditor<'r, AG, K, MP> {
+    pub fn new(ruleset: &'r GeneratorRuleset<K>, map: Map2D<AG, K, MP>) -> Self {
+        Self {
+            ruleset,
+            map,
+            locked: HashSet::new(),
+            rng: StdRng::from_entropy()
+        }
+    }
+
+    /// Seeds the editor's random number generator; the same seed and edits always produce the same map.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn map(&self) -> &Map2D<AG, K, MP> {
+        &self.map
+    }
+
+    pub fn into_map(self) -> Map2D<AG, K, MP> {
+        self.map
+    }
+
+    /// Keeps the tiles at the positions from being changed by any edit.
+    pub fn lock<I: IntoIterator<Item = MP>>(&mut self, positions: I) {
+        self.locked.extend(positions);
+    }
+
+    pub fn unlock<I: IntoIterator<Item = MP>>(&mut self, positions: I) {
+        for pos in positions {
+            self.locked.remove(&pos);
+        }
+    }
+
+    pub fn is_locked(&self, position: &MP) -> bool {
+        self.locked.contains(position)
+    }
+
+    pub fn locked(&self) -> &HashSet<MP> {
+        &self.locked
+    }
+
+    /// Sets the tile at a position to a key, then re-generates the unlocked tiles within the brush
+    /// around it, so that they fit in with the new tile.
+    ///
+    /// **Returns**: false (and changes nothing) if the position is off the map or locked.
+    ///
+    pub fn paint(&mut self, position: MP, key: K, brush: Brush) -> bool {
+        if self.is_locked(&position) { return false }
+        let tile = match self.map.get(position) {
+            Some(tile) => tile.to_owned(),
+            None => return false
+        };
+        let brushed = brush.positions(position);
+        let edit = begin_regeneration(&self.map, &brushed);
+        self.map.finalize_tile(&tile, key);
+
+        let surroundings = self.unlocked(brushed.into_iter().filter(|pos| *pos != position));
+        self.regenerate(surroundings);
+        self.map.commit_edit(edit);
+        true
+    }
+
+    /// Resets the unlocked tiles at the positions to undecided, narrowed down to what their neighbors allow.
+    /// They stay undecided until the next edit that re-generates anything, or until `fill()`.
+    pub fn erase<I: IntoIterator<Item = MP>>(&mut self, positions: I) {
+        let area = self.unlocked(positions);
+        self.map = self.ruleset.erase_region(self.take_map(), area);
+    }
+
+    /// Re-generates the unlocked tiles at the positions, along with any tiles left undecided by earlier edits.
+    pub fn reroll<I: IntoIterator<Item = MP>>(&mut self, positions: I) {
+        let area = self.unlocked(positions);
+        let edit = begin_regeneration(&self.map, &area);
+        self.regenerate(area);
+        self.map.commit_edit(edit);
+    }
+
+    /// Re-generates the unlocked tiles within the brush around a position.
+    pub fn reroll_around(&mut self, position: MP, brush: Brush) {
+        self.reroll(brush.positions(position));
+    }
+
+    /// Collapses any tiles left undecided by earlier edits.
+    pub fn fill(&mut self) {
+        self.reroll(None);
+    }
+
+    /// Reverts the most recent edit, whether made through the editor or not. Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        self.map.undo()
+    }
+
+    /// Re-applies the most recently undone edit. Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        self.map.redo()
+    }
+
+    /// Re-generates the area without recording it in the history.
+    fn regenerate(&mut self, area: Vec<MP>) {
+        let mut job = self.ruleset.regeneration_job(self.take_map(), area).with_seed(self.rng.gen());
+        job.queue_and_assign();
+        self.map = job.into_map();
+    }
+
+    fn unlocked<I: IntoIterator<Item = MP>>(&self, positions: I) -> Vec<MP> {
+        positions.into_iter().filter(|pos| !self.is_locked(pos)).collect()
+    }
+
+    fn take_map(&mut self) -> Map2D<AG, K, MP> {
+        std::mem::replace(&mut self.map, Map2D::from_tiles(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::analysis::MapStatistics;
+    use crate::assigner::MapColoringAssigner;
+    use crate::map2dnode::{Map2DNode, MapNodeState};
+    use crate::position2d::Position2D;
+    use crate::sampler::MultinomialDistribution;
+    use super::*;
+
+    type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+
+    const GRASS: u8 = 1;
+    const SAND: u8 = 2;
+    const WATER: u8 = 3;
+
+    fn rules() -> MapColoringAssigner<u8> {
+        // water needs sand between it and the grass
+        MapColoringAssigner::with_rules(HashMap::from([
+            (GRASS, MultinomialDistribution::uniform_over([GRASS, SAND])),
+            (SAND, MultinomialDistribution::uniform_over([GRASS, SAND, WATER])),
+            (WATER, MultinomialDistribution::uniform_over([SAND, WATER])),
+        ]))
+    }
+
+    fn meadow() -> TestMap {
+        Map2D::from_tiles((0..9).flat_map(|x| (0..9).map(move |y| Map2DNode::with_assignment(Position2D::new(x, y), GRASS))))
+    }
+
+    fn key_at(map: &TestMap, x: u8, y: u8) -> Option<u8> {
+        match map.get(Position2D::new(x, y))?.read().unwrap().state {
+            MapNodeState::Finalized(key) => Some(key),
+            MapNodeState::Undecided(_) => None
+        }
+    }
+
+    #[test]
+    fn painting_fixes_up_the_surroundings() {
+        let ruleset = GeneratorRuleset::new(rules(), HashMap::new(), Some(9), None);
+        let mut editor = MapEditor::new(&ruleset, meadow()).with_seed(3);
+
+        assert!(editor.paint(Position2D::new(4, 4), WATER, Brush::new(2, DistanceMetric::Chebyshev)));
+        let map = editor.into_map();
+        assert_eq!(key_at(&map, 4, 4), Some(WATER));
+        assert_eq!(MapStatistics::analyze(&map, &rules()).violations, 0);
+        // outside of the brush, nothing changed
+        assert_eq!(key_at(&map, 0, 0), Some(GRASS));
+        assert_eq!(key_at(&map, 4, 7), Some(GRASS));
+    }
+
+    #[test]
+    fn locked_tiles_survive_edits() {
+        let ruleset = GeneratorRuleset::new(rules(), HashMap::new(), Some(9), None);
+        let mut editor = MapEditor::new(&ruleset, meadow()).with_seed(5);
+        let keep = Position2D::new(2, 2);
+        editor.lock([keep]);
+
+        assert!(!editor.paint(keep, WATER, Brush::default()));
+        editor.erase(Brush::new(3, DistanceMetric::Manhattan).positions(keep));
+        assert_eq!(key_at(editor.map(), 2, 2), Some(GRASS));
+        assert_eq!(key_at(editor.map(), 2, 3), None);
+
+        editor.fill();
+        assert!(editor.map().undecided_tiles.is_empty());
+        assert_eq!(key_at(editor.map(), 2, 2), Some(GRASS));
+        assert_eq!(MapStatistics::analyze(editor.map(), &rules()).violations, 0);
+    }
+
+    #[test]
+    fn edits_are_undone_in_one_step() {
+        let ruleset = GeneratorRuleset::new(rules(), HashMap::new(), Some(9), None);
+        let mut editor = MapEditor::new(&ruleset, meadow()).with_seed(11);
+
+        editor.paint(Position2D::new(4, 4), WATER, Brush::new(2, DistanceMetric::Euclidean));
+        editor.erase(Brush::new(1, DistanceMetric::Manhattan).positions(Position2D::new(0, 0)));
+        assert!(editor.undo());
+        assert!(editor.map().undecided_tiles.is_empty());
+        assert_eq!(key_at(editor.map(), 4, 4), Some(WATER));
+
+        assert!(editor.undo());
+        assert!(!editor.undo());
+        assert!((0..9).all(|x| (0..9).all(|y| key_at(editor.map(), x, y) == Some(GRASS))));
+
+        assert!(editor.redo());
+        assert_eq!(key_at(editor.map(), 4, 4), Some(WATER));
+    }
+}
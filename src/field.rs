@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use crate::map2d::Map2D;
+use crate::adjacency::AdjacencyGenerator;
+use crate::map2dnode::MapNodeState;
+use crate::position::MapPosition;
+use crate::sampler::{DistributionKey, MultinomialDistribution};
+
+fn default_octaves() -> u32 { 1 }
+
+/// A source of values between 0 and 1 across the map, e.g. elevation or moisture.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ScalarField {
+    /// A grayscale image stretched over the whole map; black is 0, white is 1.
+    Image { path: String },
+    /// Value noise - random values on a grid, smoothly interpolated. Blockier than Perlin noise.
+    Value {
+        seed: u64,
+        /// Grid cells per tile; e.g. 0.05 gives features roughly 20 tiles across.
+        frequency: f64,
+        /// Number of layers of finer and finer detail added on top.
+        #[serde(default = "default_octaves")]
+        octaves: u32
+    },
+    /// Perlin (gradient) noise.
+    Perlin {
+        seed: u64,
+        /// Grid cells per tile; e.g. 0.05 gives features roughly 20 tiles across.
+        frequency: f64,
+        /// Number of layers of finer and finer detail added on top.
+        #[serde(default = "default_octaves")]
+        octaves: u32
+    },
+}
+
+/// A ScalarField ready for sampling (i.e. with any image already loaded).
+#[derive(Clone, Debug)]
+pub enum LoadedField {
+    Image { width: u32, height: u32, values: Vec<f32> },
+    Value { seed: u64, frequency: f64, octaves: u32 },
+    Perlin { seed: u64, frequency: f64, octaves: u32 },
+}
+
+impl ScalarField {
+    pub fn load(&self) -> Result<LoadedField, ril::Error> {
+        Ok(match self {
+            Self::Image { path } => {
+                let image = ril::Image::<ril::Rgb>::open(path)?;
+                let values = image.pixels().flatten().map(
+                    |pixel| (0.299 * pixel.r as f32 + 0.587 * pixel.g as f32 + 0.114 * pixel.b as f32) / 255.
+                ).collect();
+                LoadedField::Image { width: image.width(), height: image.height(), values }
+            },
+            Self::Value { seed, frequency, octaves } => LoadedField::Value { seed: *seed, frequency: *frequency, octaves: *octaves },
+            Self::Perlin { seed, frequency, octaves } => LoadedField::Perlin { seed: *seed, frequency: *frequency, octaves: *octaves },
+        })
+    }
+}
+
+/// Hashes a lattice point into a pseudo-random value between 0 and 1.
+fn lattice_hash(seed: u64, x: i64, y: i64) -> f64 {
+    // splitmix64 finalizer over the combined coordinates
+    let mut z = seed
+        .wrapping_add((x as u64).wrapping_mul(0x9E3779B97F4A7C15))
+        .wrapping_add((y as u64).wrapping_mul(0xC2B2AE3D27D4EB4F));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+fn value_noise(seed: u64, x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (fade(x - x0), fade(y - y0));
+    let (ix, iy) = (x0 as i64, y0 as i64);
+
+    let top = lerp(lattice_hash(seed, ix, iy), lattice_hash(seed, ix + 1, iy), tx);
+    let bottom = lerp(lattice_hash(seed, ix, iy + 1), lattice_hash(seed, ix + 1, iy + 1), tx);
+    lerp(top, bottom, ty)
+}
+
+fn perlin_noise(seed: u64, x: f64, y: f64) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i64, y0 as i64);
+
+    let gradient_dot = |cx: i64, cy: i64, dx: f64, dy: f64| {
+        let angle = lattice_hash(seed, cx, cy) * TAU;
+        angle.cos() * dx + angle.sin() * dy
+    };
+
+    let top = lerp(gradient_dot(ix, iy, fx, fy), gradient_dot(ix + 1, iy, fx - 1., fy), fade(fx));
+    let bottom = lerp(gradient_dot(ix, iy + 1, fx, fy - 1.), gradient_dot(ix + 1, iy + 1, fx - 1., fy - 1.), fade(fx));
+    // raw Perlin noise stays within +/- sqrt(0.5)
+    (lerp(top, bottom, fade(fy)) * std::f64::consts::SQRT_2 + 1.) / 2.
+}
+
+/// Sums octaves of noise, each at double the frequency and half the amplitude of the last.
+fn fractal<F: Fn(u64, f64, f64) -> f64>(noise: F, seed: u64, frequency: f64, octaves: u32, x: f64, y: f64) -> f64 {
+    let (mut total, mut amplitude_sum, mut amplitude, mut scale) = (0., 0., 1., frequency);
+    for octave in 0..octaves.max(1) {
+        total += amplitude * noise(seed.wrapping_add(u64::from(octave)), x * scale, y * scale);
+        amplitude_sum += amplitude;
+        amplitude /= 2.;
+        scale *= 2.;
+    }
+    total / amplitude_sum
+}
+
+impl LoadedField {
+    /// The value of the field at a position, between 0 and 1.
+    ///
+    ///  **Arguments**:
+    /// * `position` - position of the tile.
+    /// * `bounds` - the min & max positions of the map; images are stretched to fit these.
+    ///
+    pub fn value_at<MP: MapPosition<2>>(&self, position: MP, bounds: [MP; 2]) -> f32 {
+        let [x, y] = position.get_dims().map(|dim| dim.to_f64().unwrap_or(0.));
+
+        let value = match self {
+            Self::Image { width, height, values } => {
+                let [min_x, min_y] = bounds[0].get_dims().map(|dim| dim.to_f64().unwrap_or(0.));
+                let [max_x, max_y] = bounds[1].get_dims().map(|dim| dim.to_f64().unwrap_or(0.));
+                let rel_x = (x - min_x) / (max_x - min_x).max(1.);
+                let rel_y = (y - min_y) / (max_y - min_y).max(1.);
+
+                let pixel_x = ((rel_x * f64::from(width.saturating_sub(1))).round() as u32).min(width.saturating_sub(1));
+                let pixel_y = ((rel_y * f64::from(height.saturating_sub(1))).round() as u32).min(height.saturating_sub(1));
+                values.get((pixel_y * width + pixel_x) as usize).copied().unwrap_or(0.) as f64
+            },
+            Self::Value { seed, frequency, octaves } => fractal(value_noise, *seed, *frequency, *octaves, x, y),
+            Self::Perlin { seed, frequency, octaves } => fractal(perlin_noise, *seed, *frequency, *octaves, x, y),
+        };
+        value.clamp(0., 1.) as f32
+    }
+}
+
+/// A piecewise-linear curve mapping field values to weight multipliers,
+/// given as `[field value, multiplier]` points sorted by field value.
+/// Beyond the first and last point, the curve stays flat.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WeightCurve(pub Vec<[f32; 2]>);
+
+impl WeightCurve {
+    pub fn multiplier(&self, value: f32) -> f32 {
+        let points = &self.0;
+        let (first, last) = match (points.first(), points.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 1.
+        };
+        if value <= first[0] { return first[1] }
+        if value >= last[0] { return last[1] }
+
+        for segment in points.windows(2) {
+            let ([x0, y0], [x1, y1]) = (segment[0], segment[1]);
+            if value <= x1 {
+                if x1 <= x0 { return y1 }
+                return y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+            }
+        }
+        last[1]
+    }
+}
+
+/// When the field gets to bias the weights.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModulationStage {
+    /// Biases the starting possibilities of each tile. As the possibilities
+    /// of a tile get narrowed down by its neighbors, the bias carries over.
+    #[default]
+    Initial,
+    /// Biases the weights right before a tile gets collapsed.
+    Sampling,
+    /// Both of the above; the bias is effectively applied twice.
+    Both,
+}
+
+/// Biases the weights of tile types by the value of a scalar field at each position, e.g.
+/// to make water likelier in the lowlands and snow likelier on the peaks of a heightmap.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FieldModulation<K: DistributionKey> {
+    pub field: ScalarField,
+    /// Weight multipliers for each tile type; tile types without a curve are left as they are.
+    pub curves: HashMap<K, WeightCurve>,
+    #[serde(default)]
+    pub stage: ModulationStage,
+}
+
+impl<K: DistributionKey> FieldModulation<K> {
+    pub fn new(field: ScalarField, curves: HashMap<K, WeightCurve>) -> Self {
+        Self {
+            field,
+            curves,
+            stage: ModulationStage::default()
+        }
+    }
+
+    pub fn load(&self) -> Result<LoadedModulation<K>, ril::Error> {
+        Ok(LoadedModulation {
+            field: self.field.load()?,
+            curves: self.curves.to_owned(),
+            stage: self.stage
+        })
+    }
+}
+
+/// A FieldModulation ready for use (i.e. with its field loaded).
+#[derive(Clone, Debug)]
+pub struct LoadedModulation<K: DistributionKey> {
+    pub field: LoadedField,
+    pub curves: HashMap<K, WeightCurve>,
+    pub stage: ModulationStage,
+}
+
+impl<K: DistributionKey> LoadedModulation<K> {
+    pub fn applies_initially(&self) -> bool {
+        matches!(self.stage, ModulationStage::Initial | ModulationStage::Both)
+    }
+
+    pub fn applies_on_sampling(&self) -> bool {
+        matches!(self.stage, ModulationStage::Sampling | ModulationStage::Both)
+    }
+
+    /// Multiplies each weight of a distribution by its tile type's curve at the position.
+    /// If that would leave no options at all, the distribution is returned as it is,
+    /// so that the field never rules out every option the tile has.
+    pub fn modulate<MP: MapPosition<2>>(&self, position: MP, bounds: [MP; 2], distribution: &MultinomialDistribution<K>) -> MultinomialDistribution<K> {
+        let value = self.field.value_at(position, bounds);
+        let modulated = distribution.reweighted(|key, weight| match self.curves.get(key) {
+            Some(curve) => weight * curve.multiplier(value).max(0.),
+            None => weight
+        });
+        match modulated.total_weights() > 0. {
+            true => modulated,
+            false => distribution.to_owned()
+        }
+    }
+
+    /// Biases the possibilities of all undecided tiles on a map, with the field laid out over the bounds;
+    /// for a part of a bigger map, those are the bounds of the whole map.
+    pub fn modulate_map<AG: AdjacencyGenerator<2>, MP: MapPosition<2>>(&self, map: &Map2D<AG, K, MP>, bounds: [MP; 2]) {
+        for tile in map.undecided_tiles.values() {
+            let mut writer = tile.write().unwrap();
+            let position = writer.position;
+            if let MapNodeState::Undecided(dist) = &writer.state {
+                let modulated = self.modulate(position, bounds, dist);
+                writer.state = MapNodeState::from(modulated);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use super::*;
+
+    const BOUNDS: [Position2D<i32>; 2] = [Position2D { x: 0, y: 0 }, Position2D { x: 99, y: 99 }];
+
+    #[test]
+    fn noise_is_seeded_and_bounded() {
+        for field in [
+            ScalarField::Value { seed: 3, frequency: 0.1, octaves: 3 },
+            ScalarField::Perlin { seed: 3, frequency: 0.1, octaves: 3 },
+        ] {
+            let loaded = field.load().unwrap();
+            let values: Vec<f32> = (0..100).map(|i| loaded.value_at(Position2D::new(i, i * 7 % 100), BOUNDS)).collect();
+            let again: Vec<f32> = (0..100).map(|i| loaded.value_at(Position2D::new(i, i * 7 % 100), BOUNDS)).collect();
+
+            assert_eq!(values, again);
+            assert!(values.iter().all(|v| (0. ..=1.).contains(v)));
+            assert!(values.iter().any(|v| (v - values[0]).abs() > 0.05), "{:?} is flat", field);
+        }
+    }
+
+    #[test]
+    fn curves_interpolate() {
+        let curve = WeightCurve(vec![[0.2, 0.], [0.6, 4.], [1., 2.]]);
+        assert_eq!(curve.multiplier(0.), 0.);
+        assert!((curve.multiplier(0.4) - 2.).abs() < 1e-5);
+        assert!((curve.multiplier(0.8) - 3.).abs() < 1e-5);
+        assert_eq!(curve.multiplier(1.5), 2.);
+        assert_eq!(WeightCurve(vec![]).multiplier(0.5), 1.);
+    }
+
+    #[test]
+    fn modulation_biases_weights() {
+        let modulation = FieldModulation::new(
+            ScalarField::Value { seed: 1, frequency: 0.5, octaves: 1 },
+            HashMap::from([(1, WeightCurve(vec![[0., 0.], [1., 0.]]))])
+        ).load().unwrap();
+
+        let dist = MultinomialDistribution::uniform_over([1, 2]);
+        let modulated = modulation.modulate(Position2D::new(5, 5), BOUNDS, &dist);
+        assert_eq!(modulated.remaining_options(), 1);
+        assert_eq!(modulated.total_weights(), 1.);
+    }
+
+    #[test]
+    fn submaps_are_modulated_like_the_whole_map() {
+        type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<i32>>, i32, Position2D<i32>>;
+        let build = |min: i32| -> TestMap {
+            Map2D::from_tiles((min..100).flat_map(|x| (min..100).map(
+                move |y| Map2DNode::with_possibilities(Position2D::new(x, y), MultinomialDistribution::uniform_over([1, 2]))
+            )))
+        };
+        // a left-to-right ramp; unlike noise, images are stretched over the bounds
+        let modulation = LoadedModulation {
+            field: LoadedField::Image { width: 10, height: 1, values: (0..10).map(|x| x as f32 / 9.).collect() },
+            curves: HashMap::from([(1, WeightCurve(vec![[0., 0.], [1., 1.]]))]),
+            stage: ModulationStage::Initial
+        };
+
+        let whole = build(0);
+        modulation.modulate_map(&whole, BOUNDS);
+        let part = build(60);
+        modulation.modulate_map(&part, BOUNDS);
+
+        for (pos, tile) in part.undecided_tiles.iter() {
+            let weight_of_1 = |map: &TestMap| match &map.get(pos).unwrap().read().unwrap().state {
+                MapNodeState::Undecided(dist) => dist.weight(&1),
+                MapNodeState::Finalized(_) => unreachable!()
+            };
+            assert_eq!(weight_of_1(&part), weight_of_1(&whole), "{:?} differs", tile.read().unwrap().position);
+        }
+    }
+}
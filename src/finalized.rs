@@ -0,0 +1,222 @@
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::ops::Index;
+use num::{NumCast, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::map2d::Map2D;
+use crate::map2dnode::{Map2DNode, MapNodeState};
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+/// A fully collapsed map as a plain, owned grid of tile keys - no locks, no distributions.
+///
+/// Tiles are stored row by row, starting from the origin (the lowest X and Y on the map).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FinalizedMap<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> {
+    width: usize,
+    height: usize,
+    origin: MP,
+    tiles: Vec<K>,
+    #[serde(skip)]
+    adjacency: PhantomData<AG>,
+}
+
+impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<2>> FinalizedMap<AG, K, MP> {
+    /// Builds a grid out of rows of keys, with the first key of the first row at the origin.
+    /// Returns None if the rows are not all the same length.
+    pub fn from_rows<R: IntoIterator<Item = Vec<K>>>(origin: MP, rows: R) -> Option<Self> {
+        let rows: Vec<Vec<K>> = rows.into_iter().collect();
+        let width = rows.first().map(Vec::len).unwrap_or(0);
+        if rows.iter().any(|row| row.len() != width) { return None }
+
+        Some(Self {
+            width,
+            height: rows.len(),
+            origin,
+            tiles: rows.into_iter().flatten().collect(),
+            adjacency: PhantomData
+        })
+    }
+
+    /// Copies the keys out of a collapsed map.
+    /// Returns None if any tile is still undecided, or if the map is not a full rectangle.
+    pub fn from_map(map: &Map2D<AG, K, MP>) -> Option<Self> {
+        if !map.undecided_tiles.is_empty() { return None }
+
+        let [min_x, min_y] = to_dims(map.min_pos)?;
+        let [max_x, max_y] = to_dims(map.max_pos)?;
+        let (width, height) = ((max_x - min_x + 1).max(0) as usize, (max_y - min_y + 1).max(0) as usize);
+        if map.tiles.is_empty() || map.position_index.len() != width * height { return None }
+
+        let mut tiles = vec![K::default(); width * height];
+        for (pos, tile) in map.position_index.iter() {
+            let [x, y] = to_dims(*pos)?;
+            tiles[(y - min_y) as usize * width + (x - min_x) as usize] = match tile.read().unwrap().state {
+                MapNodeState::Finalized(assignment) => assignment,
+                MapNodeState::Undecided(_) => return None
+            };
+        }
+
+        Some(Self {
+            width,
+            height,
+            origin: map.min_pos,
+            tiles,
+            adjacency: PhantomData
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Position of the first tile, i.e. the lowest X and Y on the map.
+    pub fn origin(&self) -> MP {
+        self.origin
+    }
+
+    /// All the keys, row by row.
+    pub fn tiles(&self) -> &[K] {
+        &self.tiles
+    }
+
+    /// Index of a position in `tiles()`, or None if it is off the map.
+    pub fn index_of(&self, pos: MP) -> Option<usize> {
+        let [x, y] = to_dims(pos)?;
+        let [origin_x, origin_y] = to_dims(self.origin)?;
+        let (dx, dy) = (x - origin_x, y - origin_y);
+        if dx < 0 || dy < 0 || dx as usize >= self.width || dy as usize >= self.height { return None }
+        Some(dy as usize * self.width + dx as usize)
+    }
+
+    /// Position of the tile at an index in `tiles()`.
+    pub fn position_of(&self, index: usize) -> Option<MP> {
+        if index >= self.tiles.len() { return None }
+        let [origin_x, origin_y] = to_dims(self.origin)?;
+        let x = NumCast::from(origin_x + (index % self.width) as i64)?;
+        let y = NumCast::from(origin_y + (index / self.width) as i64)?;
+        Some(MP::from_dims([x, y]))
+    }
+
+    pub fn get(&self, pos: MP) -> Option<&K> {
+        self.index_of(pos).map(|index| &self.tiles[index])
+    }
+
+    pub fn get_mut(&mut self, pos: MP) -> Option<&mut K> {
+        self.index_of(pos).map(|index| &mut self.tiles[index])
+    }
+
+    /// The keys, one row (of fixed Y) at a time, from the lowest Y up.
+    pub fn rows(&self) -> std::slice::Chunks<'_, K> {
+        self.tiles.chunks(self.width.max(1))
+    }
+
+    /// Every position on the map along with its key, row by row.
+    pub fn iter(&self) -> impl Iterator<Item = (MP, &K)> + '_ {
+        self.tiles.iter().enumerate().filter_map(|(index, key)| Some((self.position_of(index)?, key)))
+    }
+
+    /// The neighbors of a position that are on the map, as given by the map's adjacency.
+    pub fn neighbors(&self, pos: MP) -> impl Iterator<Item = (MP, K)> + '_ {
+        AG::adjacents(pos).into_iter().filter_map(
+            move |neighbor_pos| self.get(neighbor_pos).map(|key| (neighbor_pos, *key))
+        )
+    }
+
+    /// Converts the grid back into a (new, unshared) map, e.g. to edit and re-generate parts of it.
+    pub fn to_map(&self) -> Map2D<AG, K, MP> {
+        Map2D::from_tiles(self.iter().map(|(pos, key)| Map2DNode::with_assignment(pos, *key)))
+    }
+}
+
+impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<2>> Index<MP> for FinalizedMap<AG, K, MP> {
+    type Output = K;
+
+    fn index(&self, pos: MP) -> &Self::Output {
+        self.get(pos).expect("Position is off the map!")
+    }
+}
+
+impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<2>> From<FinalizedMap<AG, K, MP>> for Map2D<AG, K, MP> {
+    fn from(value: FinalizedMap<AG, K, MP>) -> Self {
+        value.to_map()
+    }
+}
+
+// manual impls, so that the adjacency (a marker type) does not need to be comparable or printable
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> PartialEq for FinalizedMap<AG, K, MP> {
+    fn eq(&self, other: &Self) -> bool {
+        self.width == other.width && self.height == other.height && self.origin == other.origin && self.tiles == other.tiles
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> Eq for FinalizedMap<AG, K, MP> {}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2> + Debug> Debug for FinalizedMap<AG, K, MP> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FinalizedMap")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("origin", &self.origin)
+            .field("tiles", &self.tiles)
+            .finish()
+    }
+}
+
+fn to_dims<MP: MapPosition<2>>(pos: MP) -> Option<[i64; 2]> {
+    let [x, y] = pos.get_dims();
+    Some([x.to_i64()?, y.to_i64()?])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::position2d::Position2D;
+    use super::*;
+
+    type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+    type TestGrid = FinalizedMap<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+
+    fn offset_map() -> TestMap {
+        // 3 wide, 2 high, starting at (1, 5); each key encodes its position
+        Map2D::from_tiles((1..4).flat_map(|x| (5..7).map(move |y| {
+            Map2DNode::with_assignment(Position2D::new(x, y), 10 * x + y)
+        })))
+    }
+
+    #[test]
+    fn finalizes_collapsed_maps() {
+        let grid = TestGrid::from_map(&offset_map()).unwrap();
+        assert_eq!((grid.width(), grid.height(), grid.origin()), (3, 2, Position2D::new(1, 5)));
+        assert_eq!(grid.rows().collect::<Vec<_>>(), vec![&[15, 25, 35][..], &[16, 26, 36][..]]);
+        assert_eq!(grid[Position2D::new(3, 6)], 36);
+        assert_eq!(grid.get(Position2D::new(0, 5)), None);
+
+        let mut neighbors: Vec<u8> = grid.neighbors(Position2D::new(1, 5)).map(|(_, key)| key).collect();
+        neighbors.sort();
+        assert_eq!(neighbors, vec![16, 25]);
+
+        let map = grid.to_map();
+        assert_eq!(map.tiles.len(), 6);
+        assert_eq!(TestGrid::from_map(&map), Some(grid));
+    }
+
+    #[test]
+    fn rejects_unfinished_maps() {
+        let mut map = offset_map();
+        let tile = map.get(Position2D::new(2, 5)).unwrap().to_owned();
+        map.unassign_tile(&tile, &crate::sampler::MultinomialDistribution::uniform_over([1]));
+        assert_eq!(TestGrid::from_map(&map), None);
+
+        let holey = TestMap::from_tiles([
+            Map2DNode::with_assignment(Position2D::new(0, 0), 1),
+            Map2DNode::with_assignment(Position2D::new(1, 1), 1),
+        ]);
+        assert_eq!(TestGrid::from_map(&holey), None);
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::analysis::MapStatistics;
+use crate::assigner::MapColoringAssigner;
+use crate::connectivity::ConnectivityConstraint;
+use crate::map2d::Map2D;
+use crate::map2dnode::MapNodeState;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+fn default_weight() -> f64 { 1. }
+
+fn default_keep() -> usize { 1 }
+
+/// A property of a generated map, scored from 0 (worst) to 1 (best).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FitnessMeasure<K: DistributionKey> {
+    /// How close the share of the map covered by the keys is to the target share.
+    TileRatio { keys: HashSet<K>, target: f64 },
+    /// Share of the tiles with the keys that are in the largest connected area of them;
+    /// 1 if they are all connected (or there are none).
+    Connectivity { keys: HashSet<K> },
+    /// How close the largest connected area of the keys, as a share of the map, is to the target share.
+    LargestRegion { keys: HashSet<K>, target: f64 },
+    /// Share of neighboring tiles that follow the layout rules.
+    RuleAdherence,
+}
+
+/// A weighted part of a fitness function.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FitnessCriterion<K: DistributionKey> {
+    pub measure: FitnessMeasure<K>,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+impl<K: DistributionKey> FitnessCriterion<K> {
+    pub fn new(measure: FitnessMeasure<K>, weight: f64) -> Self {
+        Self {
+            measure,
+            weight
+        }
+    }
+
+    /// Scores a map from 0 to 1; the weight is not applied.
+    pub fn evaluate<AG, MP>(&self, map: &Map2D<AG, K, MP>, rules: &MapColoringAssigner<K>) -> f64
+    where
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>
+    {
+        let tile_count = map.tiles.len().max(1) as f64;
+        let largest_area = |keys: &HashSet<K>| {
+            let components = ConnectivityConstraint::new(keys.iter().copied()).find_components(map);
+            let total: usize = components.iter().map(Vec::len).sum();
+            (components.first().map(Vec::len).unwrap_or(0), total)
+        };
+
+        match &self.measure {
+            FitnessMeasure::TileRatio { keys, target } => {
+                let matching = map.tiles.iter().filter(
+                    |tile| matches!(tile.read().unwrap().state, MapNodeState::Finalized(key) if keys.contains(&key))
+                ).count();
+                1. - (matching as f64 / tile_count - target).abs().min(1.)
+            },
+            FitnessMeasure::Connectivity { keys } => match largest_area(keys) {
+                (_, 0) => 1.,
+                (largest, total) => largest as f64 / total as f64
+            },
+            FitnessMeasure::LargestRegion { keys, target } => {
+                let (largest, _) = largest_area(keys);
+                1. - (largest as f64 / tile_count - target).abs().min(1.)
+            },
+            FitnessMeasure::RuleAdherence => 1. - MapStatistics::analyze(map, rules).violation_rate(),
+        }
+    }
+}
+
+/// Scores a map by the weighted average of the criteria.
+/// Without any criteria, the map is scored by how well it follows the rules.
+pub fn fitness_score<AG, K, MP>(criteria: &[FitnessCriterion<K>], map: &Map2D<AG, K, MP>, rules: &MapColoringAssigner<K>) -> f64
+where
+    AG: AdjacencyGenerator<2, Input = MP>,
+    K: DistributionKey,
+    MP: MapPosition<2>
+{
+    if criteria.is_empty() {
+        return FitnessCriterion::new(FitnessMeasure::RuleAdherence, 1.).evaluate(map, rules)
+    }
+
+    let total_weight: f64 = criteria.iter().map(|criterion| criterion.weight).sum();
+    if total_weight <= 0. { return 0. }
+
+    criteria.iter().map(
+        |criterion| criterion.weight * criterion.evaluate(map, rules)
+    ).sum::<f64>() / total_weight
+}
+
+/// How many maps to generate in one batch and how many of the best ones to keep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BestOf {
+    pub runs: usize,
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+}
+
+impl BestOf {
+    pub fn new(runs: usize, keep: usize) -> Self {
+        Self {
+            runs,
+            keep
+        }
+    }
+}
+
+/// A generated map along with its fitness score and the seed that generated it.
+pub struct ScoredMap<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> {
+    pub seed: u64,
+    pub score: f64,
+    pub map: Map2D<AG, K, MP>,
+}
+
+/// The results of a best-of-N batch.
+pub struct BatchResult<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> {
+    /// Seeds and scores of every run in the batch, best first.
+    pub scores: Vec<(u64, f64)>,
+    /// The best maps, best first.
+    pub best: Vec<ScoredMap<AG, K, MP>>,
+}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> BatchResult<AG, K, MP> {
+    /// Ranks the scored maps, keeping the best `keep` of them. Ties keep their original order.
+    pub fn from_runs(mut runs: Vec<ScoredMap<AG, K, MP>>, keep: usize) -> Self {
+        runs.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let scores = runs.iter().map(|run| (run.seed, run.score)).collect();
+        runs.truncate(keep);
+
+        Self {
+            scores,
+            best: runs
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use crate::sampler::MultinomialDistribution;
+    use super::*;
+
+    type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+
+    fn two_islands() -> TestMap {
+        // a 4x4 sea with a 2-tile island in one corner and a 1-tile island in the other
+        let land = [(0, 0), (1, 0), (3, 3)];
+        Map2D::from_tiles((0..4).flat_map(|x| (0..4).map(move |y| {
+            Map2DNode::with_assignment(Position2D::new(x, y), if land.contains(&(x, y)) { 1 } else { 2 })
+        })))
+    }
+
+    #[test]
+    fn measures_score_maps() {
+        let map = two_islands();
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (1, MultinomialDistribution::uniform_over([1, 2])),
+            (2, MultinomialDistribution::uniform_over([1, 2])),
+        ]));
+        let land = HashSet::from([1]);
+        let score = |measure| FitnessCriterion::new(measure, 1.).evaluate(&map, &rules);
+
+        assert_eq!(score(FitnessMeasure::TileRatio { keys: land.to_owned(), target: 3. / 16. }), 1.);
+        assert!((score(FitnessMeasure::Connectivity { keys: land.to_owned() }) - 2. / 3.).abs() < 1e-9);
+        assert_eq!(score(FitnessMeasure::LargestRegion { keys: land, target: 1. }), 2. / 16.);
+        assert_eq!(score(FitnessMeasure::RuleAdherence), 1.);
+
+        let criteria = [
+            FitnessCriterion::new(FitnessMeasure::RuleAdherence, 3.),
+            FitnessCriterion::new(FitnessMeasure::TileRatio { keys: HashSet::from([2]), target: 0. }, 1.),
+        ];
+        assert_eq!(fitness_score(&criteria, &map, &rules), (3. + 3. / 16.) / 4.);
+    }
+
+    #[test]
+    fn batches_keep_the_best() {
+        let runs = [(1, 0.5), (2, 0.9), (3, 0.1)].map(|(seed, score)| ScoredMap { seed, score, map: two_islands() });
+        let batch = BatchResult::from_runs(runs.into_iter().collect(), 2);
+
+        assert_eq!(batch.scores, vec![(2, 0.9), (1, 0.5), (3, 0.1)]);
+        assert_eq!(batch.best.iter().map(|run| run.seed).collect::<Vec<_>>(), vec![2, 1]);
+    }
+}
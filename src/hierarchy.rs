@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, HashMap};
+use num::{NumCast, ToPrimitive};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::assigner::MapColoringAssigner;
+use crate::map2d::Map2D;
+use crate::map2dnode::{Map2DNode, MapNodeState};
+use crate::position::MapPosition;
+use crate::ruleset::GeneratorRuleset;
+use crate::sampler::{DistributionKey, MultinomialDistribution};
+use crate::visualizers::TerminalVisualizer;
+
+/// A two-level generator: a coarse Ruleset lays out a map of biomes, then every biome tile
+/// gets expanded into a `cell_width` x `cell_height` block of fine tiles generated with that
+/// biome's own Ruleset.
+///
+/// Blocks are generated one by one, row by row; before a block is generated, its tiles are conditioned
+/// on the already-generated tiles around them, so that neighboring blocks line up at the edges.
+/// The edges use the rules of the block being generated, falling back to the rules of the other
+/// biomes for fine tile types the block's own biome has no rules for.
+///
+/// For more than two levels, feed the fine map of one NestedRuleset into
+/// `generate_from_biomes()` of another.
+#[derive(Serialize, Deserialize)]
+pub struct NestedRuleset<B: DistributionKey, K: DistributionKey> {
+    pub biomes: GeneratorRuleset<B>,
+    pub biome_rulesets: HashMap<B, GeneratorRuleset<K>>,
+    pub cell_width: u32,
+    pub cell_height: u32,
+    /// Seed for the fine level; each block gets its own seed drawn from it.
+    /// The coarse level uses the seed of the biome Ruleset.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl<B: DistributionKey, K: DistributionKey> NestedRuleset<B, K> {
+    pub fn new(biomes: GeneratorRuleset<B>, biome_rulesets: HashMap<B, GeneratorRuleset<K>>, cell_width: u32, cell_height: u32) -> Self {
+        Self {
+            biomes,
+            biome_rulesets,
+            cell_width: cell_width.max(1),
+            cell_height: cell_height.max(1),
+            seed: None
+        }
+    }
+
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Rules used to condition a block of a biome on its surroundings - the biome's own rules,
+    /// plus the rules of other biomes for any tile types it does not cover.
+    fn border_rules(&self, biome: &B) -> MapColoringAssigner<K> {
+        let own_rules = self.biome_rulesets[biome].layout_rules();
+        let mut transition_rules = own_rules.transition_rules.to_owned();
+
+        // sorted, so that overlapping rules always resolve the same way
+        let other_biomes: BTreeMap<&B, &GeneratorRuleset<K>> = self.biome_rulesets.iter().collect();
+        for (_, ruleset) in other_biomes.into_iter().filter(|(other, _)| *other != biome) {
+            for (key, rule) in ruleset.layout_rules().transition_rules.iter() {
+                transition_rules.entry(*key).or_insert_with(|| rule.to_owned());
+            }
+        }
+        MapColoringAssigner::with_rules(transition_rules)
+    }
+
+    /// Generates a biome map with the coarse Ruleset, then fills it in.
+    ///
+    /// **Returns**: the fine map.
+    ///
+    pub fn generate<CAG, CMP, AG, MP>(&self) -> Map2D<AG, K, MP>
+    where
+        CAG: AdjacencyGenerator<2, Input = CMP>,
+        CMP: MapPosition<2>,
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>
+    {
+        // the visualizer only picks the builder's bounds; unlike the pixel one, this one takes any position type
+        let empty_biomes = self.biomes.default_map_builder::<CAG, CMP, TerminalVisualizer<B>>(self.biomes.map_size);
+        let biome_map = self.biomes.collapse(empty_biomes);
+        self.generate_from_biomes(&biome_map)
+    }
+
+    /// Fills in an existing biome map, expanding each biome tile into a block of fine tiles.
+    /// Blocks of biomes without a Ruleset (and tiles the biome map left undecided) are filled with `K::default()`.
+    ///
+    /// **Returns**: the fine map; its origin is at the origin of the biome map times the block size.
+    ///
+    pub fn generate_from_biomes<CAG, CMP, AG, MP>(&self, biome_map: &Map2D<CAG, B, CMP>) -> Map2D<AG, K, MP>
+    where
+        CAG: AdjacencyGenerator<2>,
+        CMP: MapPosition<2>,
+        AG: AdjacencyGenerator<2, Input = MP>,
+        MP: MapPosition<2>
+    {
+        let (cell_width, cell_height) = (self.cell_width as u64, self.cell_height as u64);
+
+        let mut biome_tiles: Vec<(CMP, Option<B>)> = biome_map.tiles.iter().map(|tile| {
+            let reader = tile.read().unwrap();
+            let biome = match reader.state {
+                MapNodeState::Finalized(biome) => Some(biome),
+                MapNodeState::Undecided(_) => None
+            };
+            (reader.position, biome)
+        }).collect();
+        // row by row, so that each block has finished neighbors above and to the left
+        biome_tiles.sort_by_key(|(pos, _)| {
+            let [x, y] = pos.get_dims();
+            (y, x)
+        });
+
+        let mut blocks: Vec<(Option<B>, Vec<MP>)> = Vec::with_capacity(biome_tiles.len());
+        let mut fine_tiles = Vec::with_capacity(biome_tiles.len() * (cell_width * cell_height) as usize);
+
+        for (biome_pos, biome) in biome_tiles {
+            let ruleset = biome.and_then(|b| self.biome_rulesets.get(&b));
+            let initial_state = match ruleset {
+                Some(rules) => MapNodeState::undecided(MultinomialDistribution::uniform_over(rules.tile_keys())),
+                None => MapNodeState::Finalized(K::default())
+            };
+
+            let [biome_x, biome_y] = biome_pos.get_dims().map(|dim| dim.to_u64().unwrap_or(0));
+            let mut block_positions = Vec::with_capacity((cell_width * cell_height) as usize);
+
+            for sub_y in 0..cell_height {
+                for sub_x in 0..cell_width {
+                    let fine_pos = match (
+                        <MP::Key as NumCast>::from(biome_x * cell_width + sub_x),
+                        <MP::Key as NumCast>::from(biome_y * cell_height + sub_y)
+                    ) {
+                        (Some(x), Some(y)) => MP::from_dims([x, y]),
+                        _ => continue
+                    };
+                    block_positions.push(fine_pos);
+
+                    fine_tiles.push(Map2DNode::with_state(fine_pos, initial_state.to_owned()));
+                }
+            }
+            blocks.push((biome.filter(|_| ruleset.is_some()), block_positions));
+        }
+
+        let fine_map: Map2D<AG, K, MP> = Map2D::from_tiles(fine_tiles);
+        let fine_bounds = [fine_map.min_pos, fine_map.max_pos];
+
+        let mut border_rules: HashMap<B, MapColoringAssigner<K>> = HashMap::new();
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy()
+        };
+
+        for (biome, positions) in blocks.iter() {
+            let biome = match biome {
+                Some(biome) => biome,
+                None => continue
+            };
+
+            border_rules
+                .entry(*biome)
+                .or_insert_with(|| self.border_rules(biome))
+                .condition_on_neighbors(&fine_map, positions.iter().copied());
+
+            let mut job = self.biome_rulesets[biome]
+                .coloring_job_within(fine_map.submap(positions.iter()), fine_bounds)
+                .with_seed(rng.gen());
+            job.queue_and_assign();
+        }
+
+        let mut fine_map = fine_map;
+        fine_map.undecided_tiles.retain(|_, tile| !tile.read().unwrap().state.is_assigned());
+        fine_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::position2d::Position2D;
+    use crate::visualizers::MapColor;
+    use super::*;
+
+    type FineMap = Map2D<CardinalAdjacencyGenerator<Position2D<u16>>, u8, Position2D<u16>>;
+
+    const LAND: u8 = 1;
+    const SEA: u8 = 2;
+
+    fn single_tile_ruleset(key: u8) -> GeneratorRuleset<u8> {
+        GeneratorRuleset::new(
+            MapColoringAssigner::with_rules(HashMap::from([
+                (key, MultinomialDistribution::uniform_over([key]))
+            ])),
+            HashMap::from([(key, MapColor::Rgb(key, key, key))]),
+            None,
+            None
+        )
+    }
+
+    fn nested() -> NestedRuleset<u8, u8> {
+        let biomes = GeneratorRuleset::new(
+            MapColoringAssigner::with_rules(HashMap::from([
+                (LAND, MultinomialDistribution::uniform_over([LAND, SEA])),
+                (SEA, MultinomialDistribution::uniform_over([LAND, SEA])),
+            ])),
+            HashMap::from([(LAND, MapColor::Rgb(0, 255, 0)), (SEA, MapColor::Rgb(0, 0, 255))]),
+            Some(4),
+            None
+        );
+        // fine tiles 10 & 20 stand for 'land' & 'sea' tiles; each biome only ever uses its own
+        let biome_rulesets = HashMap::from([
+            (LAND, single_tile_ruleset(10)),
+            (SEA, single_tile_ruleset(20)),
+        ]);
+        NestedRuleset::new(biomes, biome_rulesets, 3, 2).with_seed(Some(7))
+    }
+
+    #[test]
+    fn blocks_follow_biomes() {
+        let nested = nested();
+        let biome_map = nested.biomes.collapse(
+            nested.biomes.default_map_builder::<CardinalAdjacencyGenerator<Position2D<u8>>, Position2D<u8>, TerminalVisualizer<u8>>(4)
+        );
+        let fine_map: FineMap = nested.generate_from_biomes(&biome_map);
+
+        assert_eq!(fine_map.tiles.len(), 4 * 4 * 3 * 2);
+        assert!(fine_map.undecided_tiles.is_empty());
+
+        for tile in fine_map.tiles.iter() {
+            let reader = tile.read().unwrap();
+            let biome_pos = Position2D::new((reader.position.x / 3) as u8, (reader.position.y / 2) as u8);
+            let biome = match biome_map.get(biome_pos).unwrap().read().unwrap().state {
+                MapNodeState::Finalized(biome) => biome,
+                MapNodeState::Undecided(_) => panic!("Biome map left undecided!")
+            };
+            let expected = if biome == LAND { 10 } else { 20 };
+            assert!(matches!(reader.state, MapNodeState::Finalized(key) if key == expected));
+        }
+    }
+
+    #[test]
+    fn generates_both_levels() {
+        let fine_map: FineMap = nested().generate::<
+            CardinalAdjacencyGenerator<Position2D<u8>>, Position2D<u8>,
+            CardinalAdjacencyGenerator<Position2D<u16>>, Position2D<u16>
+        >();
+        assert_eq!(fine_map.tiles.len(), 4 * 4 * 3 * 2);
+        assert!(fine_map.undecided_tiles.is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use crate::map2dnode::MapNodeState;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+/// The states of the tiles touched by one edit, from before and after it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapEdit<K: DistributionKey, MP: MapPosition<2>> {
+    pub before: Vec<(MP, MapNodeState<K>)>,
+    pub after: Vec<(MP, MapNodeState<K>)>,
+}
+
+/// An edit in progress; the states of the tiles it may touch, from before it started.
+/// Turned into a `MapEdit` by `Map2D::commit_edit()` once the edit is done.
+#[derive(Clone, Debug)]
+pub struct PendingEdit<K: DistributionKey, MP: MapPosition<2>> {
+    pub(crate) before: Vec<(MP, MapNodeState<K>)>,
+}
+
+/// Edits that can be undone, and undone edits that can be redone, most recent last.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EditHistory<K: DistributionKey, MP: MapPosition<2>> {
+    undo: Vec<MapEdit<K, MP>>,
+    redo: Vec<MapEdit<K, MP>>,
+}
+
+impl<K: DistributionKey, MP: MapPosition<2>> Default for EditHistory<K, MP> {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new()
+        }
+    }
+}
+
+impl<K: DistributionKey, MP: MapPosition<2>> EditHistory<K, MP> {
+    /// Records a new edit; edits undone before it can no longer be redone.
+    pub fn push(&mut self, edit: MapEdit<K, MP>) {
+        self.undo.push(edit);
+        self.redo.clear();
+    }
+
+    /// Takes the most recent edit to undo, moving it over to the edits to redo.
+    pub(crate) fn undo(&mut self) -> Option<&MapEdit<K, MP>> {
+        let edit = self.undo.pop()?;
+        self.redo.push(edit);
+        self.redo.last()
+    }
+
+    /// Takes the most recently undone edit to redo, moving it back to the edits to undo.
+    pub(crate) fn redo(&mut self) -> Option<&MapEdit<K, MP>> {
+        let edit = self.redo.pop()?;
+        self.undo.push(edit);
+        self.undo.last()
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// The edits that can be undone, oldest first.
+    pub fn edits(&self) -> &[MapEdit<K, MP>] {
+        &self.undo
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
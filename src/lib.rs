@@ -14,6 +14,23 @@ pub mod position;
 pub mod position2d;
 pub mod map2dnode;
 pub mod adjacency;
+pub mod connectivity;
+pub mod selection;
+pub mod distance;
+pub mod hierarchy;
+pub mod field;
+pub mod region;
+pub mod boundary;
+pub mod analysis;
+pub mod fitness;
+pub mod sink;
+pub mod finalized;
+pub mod editor;
+pub mod history;
+pub mod diff;
+pub mod symmetry;
+pub mod transform;
+pub mod overlapping;
 
 const COLORMAP_FILENAME: &str = "coloring_rules.json";
 const RULESET_FILENAME: &str = "layout_rules.json";
@@ -29,9 +46,9 @@ fn generate(colormap_path: Option<&str>, rule_path: Option<&str>, map_size: Opti
     ));
     rules.save(COMBINED_RULESET_FILENAME);
     match rules.map_size {
-        0..=254 => rules.generate::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(),
-        255..=65534 => rules.generate::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(),
-        _ => rules.generate::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>()
+        0..=254 => { rules.generate_and_render::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(); },
+        255..=65534 => { rules.generate_and_render::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(); },
+        _ => { rules.generate_and_render::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>(); }
     };
 }
 
@@ -43,9 +60,9 @@ fn generate_par(colormap_path: Option<&str>, rule_path: Option<&str>, map_size:
     ));
     rules.save(COMBINED_RULESET_FILENAME);
     match rules.map_size {
-        0..=254 => rules.generate_par::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(),
-        255..=65534 => rules.generate_par::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(),
-        _ => rules.generate_par::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>()
+        0..=254 => { rules.generate_and_render_par::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(); },
+        255..=65534 => { rules.generate_and_render_par::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(); },
+        _ => { rules.generate_and_render_par::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>(); }
     };
 }
 
@@ -57,35 +74,33 @@ fn generate_from_ruleset<T: DistributionKey>(ruleset: &GeneratorRuleset<T>) {
 
     match normalized_adjacency.as_str() {
         "cardinal" => match ruleset.map_size {
-            0..=254 => ruleset.generate::<CardinalAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(),
-            255..=65534 => ruleset.generate::<CardinalAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(),
-            _ => ruleset.generate::<CardinalAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>()
+            0..=254 => { ruleset.generate_and_render::<CardinalAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(); },
+            255..=65534 => { ruleset.generate_and_render::<CardinalAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(); },
+            _ => { ruleset.generate_and_render::<CardinalAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>(); }
         },
-        "octile" | _ => match ruleset.map_size {
-            0..=254 => ruleset.generate::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(),
-            255..=65534 => ruleset.generate::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(),
-            _ => ruleset.generate::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>()
+        _ => match ruleset.map_size {
+            0..=254 => { ruleset.generate_and_render::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(); },
+            255..=65534 => { ruleset.generate_and_render::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(); },
+            _ => { ruleset.generate_and_render::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>(); }
         },
     }
 }
 
-fn generate_from_ruleset_par<T: DistributionKey>(ruleset: &GeneratorRuleset<T>) where
-    T: Send + Sync
-{
+fn generate_from_ruleset_par<T: DistributionKey + Send + Sync>(ruleset: &GeneratorRuleset<T>) {
     let normalized_adjacency = ruleset.adjacency.as_ref().map(
         |s| s.as_str().trim().to_lowercase()
     ).unwrap_or_default();
 
     match normalized_adjacency.as_str() {
         "cardinal" => match ruleset.map_size {
-            0..=254 => ruleset.generate_par::<CardinalAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(),
-            255..=65534 => ruleset.generate_par::<CardinalAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(),
-            _ => ruleset.generate_par::<CardinalAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>()
+            0..=254 => { ruleset.generate_and_render_par::<CardinalAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(); },
+            255..=65534 => { ruleset.generate_and_render_par::<CardinalAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(); },
+            _ => { ruleset.generate_and_render_par::<CardinalAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>(); }
         },
-        "octile" | _ => match ruleset.map_size {
-            0..=254 => ruleset.generate_par::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(),
-            255..=65534 => ruleset.generate_par::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(),
-            _ => ruleset.generate_par::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>()
+        _ => match ruleset.map_size {
+            0..=254 => { ruleset.generate_and_render_par::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>(); },
+            255..=65534 => { ruleset.generate_and_render_par::<OctileAdjacencyGenerator<Position2D<u16>>, Position2D<u16>>(); },
+            _ => { ruleset.generate_and_render_par::<OctileAdjacencyGenerator<Position2D<u32>>, Position2D<u32>>(); }
         },
     }
 }
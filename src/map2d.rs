@@ -1,25 +1,35 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
 use smallvec::SmallVec;
 use num::{Bounded, Zero};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
 use crate::adjacency::{AdjacencyGenerator};
+use crate::history::{EditHistory, MapEdit, PendingEdit};
 use crate::sampler::{DistributionKey, MultinomialDistribution};
 use crate::map2dnode::{Map2DNode, MapNodeState, ThreadsafeNodeRef};
 use crate::position::{MapPosition};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Clone)]
 pub struct Map2D<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> {
     pub tiles: Vec<ThreadsafeNodeRef<AG, K, MP>>,
-    position_index: HashMap<MP, ThreadsafeNodeRef<AG, K, MP>>,
+    pub(crate) position_index: HashMap<MP, ThreadsafeNodeRef<AG, K, MP>>,
     pub undecided_tiles: HashMap<MP, ThreadsafeNodeRef<AG, K, MP>>,
     pub(crate) min_pos: MP,
     pub(crate) max_pos: MP,
+    /// Edits made with `pin()`, `unassign()` and region re-generation, for undoing them.
+    pub history: EditHistory<K, MP>,
 }
 
-impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<2>> Map2D<AG, K, MP> {
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> Map2D<AG, K, MP> {
     pub fn from_tiles<I: IntoIterator<Item=Map2DNode<AG, K, MP>>>(tiles: I) -> Map2D<AG, K, MP> {
+        Self::from_shared_tiles(tiles.into_iter().map(|tile| Arc::new(RwLock::new(tile))))
+    }
+
+    /// Builds a map out of existing tile references; the tiles are shared with whatever else holds them,
+    /// so assigning a tile on the new map assigns it everywhere else too.
+    pub fn from_shared_tiles<I: IntoIterator<Item=ThreadsafeNodeRef<AG, K, MP>>>(tiles: I) -> Map2D<AG, K, MP> {
         let iterator = tiles.into_iter();
         let size_estimate = iterator.size_hint().0;
 
@@ -31,16 +41,13 @@ impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<
         let mut maxx = None;
         let mut maxy = None;
 
-        for tile in iterator {
-            let cast_tile: Map2DNode<AG, K, MP> = tile;
-            let tile_pos = cast_tile.position.get_dims();
-
-            let tile_pos_x = tile_pos.get(0).unwrap().to_owned();
-            let tile_pos_y = tile_pos.get(1).unwrap().to_owned();
-
-            let tile_arc = Arc::new(RwLock::new(cast_tile));
+        for tile_arc in iterator {
             let tile_arc_reader = tile_arc.read().unwrap();
             let tile_pos = tile_arc_reader.position;
+            let tile_dims = tile_pos.get_dims();
+
+            let tile_pos_x = tile_dims.first().unwrap().to_owned();
+            let tile_pos_y = tile_dims.get(1).unwrap().to_owned();
 
             if tile_pos_x < minx.unwrap_or(MP::Key::max_value()) { minx = Some(tile_pos_x)};
             if tile_pos_y < miny.unwrap_or(MP::Key::max_value()) { miny = Some(tile_pos_y)};
@@ -66,15 +73,26 @@ impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<
             max_pos: MP::from_dims([
                 maxx.unwrap_or(minx.unwrap_or(MP::Key::zero())).to_owned(),
                 maxy.unwrap_or(miny.unwrap_or(MP::Key::zero())).to_owned()
-            ])
+            ]),
+            history: EditHistory::default()
         }
     }
+}
+
+impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<2>> Map2D<AG, K, MP> {
+    /// A view of the tiles at the specified positions, sharing the tiles with this map.
+    /// Positions that are not on the map are skipped.
+    pub fn submap<'a, I: IntoIterator<Item=&'a MP>>(&self, positions: I) -> Map2D<AG, K, MP> where MP: 'a {
+        Self::from_shared_tiles(
+            positions.into_iter().filter_map(|pos| self.position_index.get(pos).cloned())
+        )
+    }
 
     pub fn get<BMP: Borrow<MP>>(&self, key: BMP) -> Option<&ThreadsafeNodeRef<AG, K, MP>> {
         self.position_index.get(key.borrow())
     }
 
-    pub fn finalize_tile<'n>(&'n mut self, tile: &'n ThreadsafeNodeRef<AG, K, MP>, assignment: K) -> Option<&ThreadsafeNodeRef<AG, K, MP>> {
+    pub fn finalize_tile<'n>(&'n mut self, tile: &'n ThreadsafeNodeRef<AG, K, MP>, assignment: K) -> Option<&'n ThreadsafeNodeRef<AG, K, MP>> {
         let tile_writer = tile.write();
         match tile_writer {
             Ok(mut writeable) => {
@@ -89,17 +107,15 @@ impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<
         }
     }
 
-    pub fn unassign_tile<'n>(&'n mut self, tile: &'n ThreadsafeNodeRef<AG, K, MP>, distribution: &'n MultinomialDistribution<K>) -> Option<&ThreadsafeNodeRef<AG, K, MP>> {
+    pub fn unassign_tile<'n>(&'n mut self, tile: &'n ThreadsafeNodeRef<AG, K, MP>, distribution: &'n MultinomialDistribution<K>) -> Option<&'n ThreadsafeNodeRef<AG, K, MP>> {
         let tile_writer = tile.write();
 
         match tile_writer {
             Ok(mut writeable) => {
                 writeable.state = MapNodeState::Undecided(distribution.to_owned());
-                let inserted = self.undecided_tiles.insert(writeable.position, tile.to_owned());
-                match inserted {
-                    Some(_) => Some(tile),
-                    None => panic!("Failed to insert a tile into the undecided tiles map!")
-                }
+                // NOTE: insert() returns the previous value, so None just means the tile was decided before.
+                self.undecided_tiles.insert(writeable.position, tile.to_owned());
+                Some(tile)
             },
             Err(_) => panic!("Failed to obtain a write lock on tile to un-assign!")
         }
@@ -112,6 +128,127 @@ impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<
         });
         Some(())
     }
+
+    /// Sets the tile at a position to a key, recording the edit in the history.
+    ///
+    /// **Returns**: false (and changes nothing) if the position is off the map.
+    ///
+    pub fn pin(&mut self, position: MP, assignment: K) -> bool {
+        let tile = match self.get(position) {
+            Some(tile) => tile.to_owned(),
+            None => return false
+        };
+        let edit = self.begin_edit([position]);
+        self.finalize_tile(&tile, assignment);
+        self.commit_edit(edit);
+        true
+    }
+
+    /// Resets the tiles at the positions to undecided with the distribution, recording the edit in the history.
+    /// Positions that are not on the map are skipped.
+    pub fn unassign<I: IntoIterator<Item = MP>>(&mut self, positions: I, distribution: MultinomialDistribution<K>) {
+        let positions: Vec<MP> = positions.into_iter().collect();
+        let tiles: Vec<_> = positions.iter().filter_map(|pos| self.get(pos).cloned()).collect();
+        let edit = self.begin_edit(positions);
+        self.unassign_tiles(tiles.iter(), distribution);
+        self.commit_edit(edit);
+    }
+
+    /// Starts recording an edit that may touch the tiles at the positions.
+    /// Changes made to other tiles before the edit gets committed will not be undone with it.
+    pub fn begin_edit<I: IntoIterator<Item = MP>>(&self, positions: I) -> PendingEdit<K, MP> {
+        PendingEdit {
+            before: self.tile_states(positions)
+        }
+    }
+
+    /// Finishes recording an edit, adding it to the history.
+    pub fn commit_edit(&mut self, edit: PendingEdit<K, MP>) {
+        let after = self.tile_states(edit.before.iter().map(|(pos, _)| *pos));
+        self.history.push(MapEdit {
+            before: edit.before,
+            after
+        });
+    }
+
+    /// Reverts the most recent edit in the history.
+    ///
+    /// **Returns**: false if there was nothing to undo.
+    ///
+    pub fn undo(&mut self) -> bool {
+        let states = match self.history.undo() {
+            Some(edit) => edit.before.to_owned(),
+            None => return false
+        };
+        self.restore_states(states);
+        true
+    }
+
+    /// Re-applies the most recently undone edit.
+    ///
+    /// **Returns**: false if there was nothing to redo.
+    ///
+    pub fn redo(&mut self) -> bool {
+        let states = match self.history.redo() {
+            Some(edit) => edit.after.to_owned(),
+            None => return false
+        };
+        self.restore_states(states);
+        true
+    }
+
+    fn tile_states<I: IntoIterator<Item = MP>>(&self, positions: I) -> Vec<(MP, MapNodeState<K>)> {
+        let mut seen = HashSet::new();
+        positions.into_iter().filter(|pos| seen.insert(*pos)).filter_map(
+            |pos| self.get(pos).map(|tile| (pos, tile.read().unwrap().state.to_owned()))
+        ).collect()
+    }
+
+    fn restore_states(&mut self, states: Vec<(MP, MapNodeState<K>)>) {
+        for (pos, state) in states {
+            let tile = match self.position_index.get(&pos) {
+                Some(tile) => tile.to_owned(),
+                None => continue
+            };
+            match state.is_assigned() {
+                true => self.undecided_tiles.remove(&pos),
+                false => self.undecided_tiles.insert(pos, tile.to_owned())
+            };
+            tile.write().unwrap().state = state;
+        }
+    }
+}
+
+/// How maps get stored - the state of each tile, plus the history; the indexes get rebuilt on load.
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "K: DistributionKey + Deserialize<'de>, MP: Deserialize<'de>"))]
+struct StoredMap2D<K: DistributionKey, MP: MapPosition<2>> {
+    tiles: Vec<(MP, MapNodeState<K>)>,
+    #[serde(default)]
+    history: EditHistory<K, MP>,
+}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey + Serialize, MP: MapPosition<2> + Serialize> Serialize for Map2D<AG, K, MP> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tiles: Vec<(MP, MapNodeState<K>)> = self.tiles.iter().map(|tile| {
+            let reader = tile.read().unwrap();
+            (reader.position, reader.state.to_owned())
+        }).collect();
+
+        let mut stored = serializer.serialize_struct("Map2D", 2)?;
+        stored.serialize_field("tiles", &tiles)?;
+        stored.serialize_field("history", &self.history)?;
+        stored.end()
+    }
+}
+
+impl<'de, AG: AdjacencyGenerator<2>, K: DistributionKey + Deserialize<'de>, MP: MapPosition<2> + Deserialize<'de>> Deserialize<'de> for Map2D<AG, K, MP> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let stored = StoredMap2D::<K, MP>::deserialize(deserializer)?;
+        let mut map = Self::from_tiles(stored.tiles.into_iter().map(|(position, state)| Map2DNode::with_state(position, state)));
+        map.history = stored.history;
+        Ok(map)
+    }
 }
 
 impl<K: DistributionKey, MP: MapPosition<2>, RMP: Borrow<MP> + From<MP>, AG: AdjacencyGenerator<2, Input=RMP>> Map2D<AG, K, MP> {
@@ -144,7 +281,7 @@ impl<K: DistributionKey, MP: MapPosition<2>, RMP: Borrow<MP> + From<MP>, AG: Adj
 
 #[cfg(test)]
 mod tests {
-    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::adjacency::{CardinalAdjacencyGenerator, ChebyshevAdjacencyGenerator, EuclideanAdjacencyGenerator};
     use super::*;
     use crate::position2d::Position2D;
 
@@ -176,10 +313,57 @@ mod tests {
         assert_eq!(results[3], Position2D { x: 2i32, y: 7i32 });
     }
 
+    #[test]
+    fn adjacents_radius_sane() {
+        let pos = Position2D { x: 2, y: 6 };
+        let results = Position2D::adjacents::<Position2D<i32>, EuclideanAdjacencyGenerator<Position2D<i32>, 2>>(pos);
+        assert_eq!(results.len(), 12);
+        assert!(results.contains(&Position2D { x: 0, y: 6 }));
+        assert!(!results.contains(&Position2D { x: 0, y: 4 }));
+
+        let edge = Position2D::<u8> { x: 0, y: 1 };
+        let results = Position2D::adjacents::<Position2D<u8>, ChebyshevAdjacencyGenerator<Position2D<u8>, 1>>(edge);
+        assert_eq!(results.len(), 5);
+    }
+
     #[test]
     fn serde_pos() {
         let pos = Position2D { x: 2, y: 6 };
         let results = serde_json::to_string(&pos).unwrap();
-        assert!(results.len() > 0)
+        assert!(!results.is_empty())
+    }
+
+    #[test]
+    fn edits_can_be_undone_and_redone() {
+        type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+        let mut map: TestMap = Map2D::from_tiles((0..3).flat_map(
+            |x| (0..3).map(move |y| Map2DNode::with_assignment(Position2D::new(x, y), 1))
+        ));
+        let key_at = |map: &TestMap, x, y| match map.get(Position2D::new(x, y)).unwrap().read().unwrap().state {
+            MapNodeState::Finalized(key) => Some(key),
+            MapNodeState::Undecided(_) => None
+        };
+
+        assert!(map.pin(Position2D::new(1, 1), 2));
+        map.unassign([Position2D::new(0, 0), Position2D::new(0, 1)], MultinomialDistribution::uniform_over([1, 2]));
+        assert!(!map.pin(Position2D::new(5, 5), 2));
+        assert_eq!(map.history.edits().len(), 2);
+        assert_eq!(map.undecided_tiles.len(), 2);
+
+        // the history travels with the map
+        let mut map: TestMap = serde_json::from_str(&serde_json::to_string(&map).unwrap()).unwrap();
+
+        assert!(map.undo());
+        assert_eq!(key_at(&map, 0, 0), Some(1));
+        assert!(map.undecided_tiles.is_empty());
+        assert!(map.undo());
+        assert_eq!(key_at(&map, 1, 1), Some(1));
+        assert!(!map.undo());
+
+        assert!(map.redo());
+        assert_eq!(key_at(&map, 1, 1), Some(2));
+        map.pin(Position2D::new(2, 2), 2);
+        assert!(!map.redo());
+        assert_eq!(key_at(&map, 0, 0), Some(1));
     }
 }
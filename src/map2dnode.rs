@@ -4,7 +4,7 @@ use std::marker::PhantomData;
 use serde;
 use crate::adjacency::AdjacencyGenerator;
 use crate::position::{MapPosition};
-use crate::sampler::{DistributionKey, MultinomialDistribution};
+use crate::sampler::{DistributionKey, EntropyMetric, MultinomialDistribution};
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub enum MapNodeState<K: DistributionKey> {
@@ -67,10 +67,23 @@ impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> Map2DNod
         }
     }
 
+    pub fn with_state(position: MP, state: MapNodeState<K>) -> Self {
+        Self {
+            position,
+            state,
+            adjacency_phantom: PhantomData
+        }
+    }
+
+    /// Shannon entropy of the tile's possibilities; zero once the tile is finalized.
     pub fn entropy(&self) -> f32 {
+        self.entropy_with(EntropyMetric::Shannon)
+    }
+
+    pub fn entropy_with(&self, metric: EntropyMetric) -> f32 {
         match &self.state {
-            MapNodeState::Finalized(_) => f32::INFINITY,
-            MapNodeState::Undecided(possibilities) => possibilities.entropy()
+            MapNodeState::Finalized(_) => 0.,
+            MapNodeState::Undecided(possibilities) => metric.measure(possibilities)
         }
     }
 
@@ -116,19 +129,24 @@ impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> From<Arc
     }
 }
 
-impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> PartialEq<Self> for MapNodeEntropyOrdering<AG, K, MP> {
-    fn eq(&self, other: &Self) -> bool {
-        let my_entropy = match &self.node {
-            MapNodeWrapper::Raw(node_data) => node_data.entropy(),
-            MapNodeWrapper::Arc(node_data) => node_data.read().unwrap().entropy(),
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> MapNodeEntropyOrdering<AG, K, MP> {
+    /// The tile's entropy, or None if it has already been finalized.
+    fn undecided_entropy(&self) -> Option<f32> {
+        let read_entropy = |node_data: &Map2DNode<AG, K, MP>| match node_data.state {
+            MapNodeState::Undecided(_) => Some(node_data.entropy()),
+            MapNodeState::Finalized(_) => None
         };
 
-        let other_entropy = match &other.node {
-            MapNodeWrapper::Raw(node_data) => node_data.entropy(),
-            MapNodeWrapper::Arc(node_data) => node_data.read().unwrap().entropy(),
-        };
+        match &self.node {
+            MapNodeWrapper::Raw(node_data) => read_entropy(node_data),
+            MapNodeWrapper::Arc(node_data) => read_entropy(&node_data.read().unwrap()),
+        }
+    }
+}
 
-        my_entropy == other_entropy
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> PartialEq<Self> for MapNodeEntropyOrdering<AG, K, MP> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -136,30 +154,47 @@ impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> Eq for M
 
 impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> PartialOrd for MapNodeEntropyOrdering<AG, K, MP> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        let my_entropy = match &self.node {
-            MapNodeWrapper::Raw(node_data) => node_data.entropy(),
-            MapNodeWrapper::Arc(node_data) => node_data.read().unwrap().entropy(),
-        };
-
-        let other_entropy = match &other.node {
-            MapNodeWrapper::Raw(node_data) => node_data.entropy(),
-            MapNodeWrapper::Arc(node_data) => node_data.read().unwrap().entropy(),
-        };
-
-        match my_entropy == other_entropy {
-            true => Some(Ordering::Equal),
-            false => match my_entropy > other_entropy {
-                true => Some(Ordering::Less),
-                false => Some(Ordering::Greater)
-            }
-        }
+        Some(self.cmp(other))
     }
 }
 
+/// Orders tiles by *priority* for a max-heap like `BinaryHeap`: the tile with the lowest
+/// entropy is the greatest, and finalized tiles are lesser than any undecided tile.
 impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> Ord for MapNodeEntropyOrdering<AG, K, MP> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        match (self.undecided_entropy(), other.undecided_entropy()) {
+            (Some(my_entropy), Some(other_entropy)) => other_entropy.total_cmp(&my_entropy),
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (None, None) => Ordering::Equal
+        }
     }
 }
 
 pub type ThreadsafeNodeRef<AG, K, MP> = Arc<RwLock<Map2DNode<AG, K, MP>>>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BinaryHeap, HashMap};
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::position2d::Position2D;
+    use super::*;
+
+    type TestNode = Map2DNode<CardinalAdjacencyGenerator<Position2D<i32>>, i32, Position2D<i32>>;
+
+    #[test]
+    fn entropy_ordering_pops_least_uncertain_first() {
+        let uncertain = TestNode::with_possibilities(Position2D::new(0, 0), MultinomialDistribution::uniform_over(vec![1, 2, 3]));
+        let likely = TestNode::with_possibilities(Position2D::new(1, 0), MultinomialDistribution::from(HashMap::from([(1, 9.), (2, 1.)])));
+        let finalized = TestNode::with_assignment(Position2D::new(2, 0), 1);
+
+        let mut heap: BinaryHeap<MapNodeEntropyOrdering<_, _, _>> = [uncertain, finalized, likely]
+            .into_iter()
+            .map(MapNodeEntropyOrdering::from)
+            .collect();
+
+        assert_eq!(heap.pop().unwrap().node.position(), Position2D::new(1, 0));
+        assert_eq!(heap.pop().unwrap().node.position(), Position2D::new(0, 0));
+        assert_eq!(heap.pop().unwrap().node.position(), Position2D::new(2, 0));
+    }
+}
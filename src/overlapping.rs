@@ -0,0 +1,527 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::assigner::enqueue;
+use crate::distance::offset_position;
+use crate::map2d::Map2D;
+use crate::map2dnode::{Map2DNode, MapNodeState};
+use crate::position::MapPosition;
+use crate::sampler::{DistributionKey, EntropyMetric, MultinomialDistribution};
+use crate::selection::{CellSelection, PrioritizedNode, SelectionHeuristic};
+use crate::transform::Transform;
+use crate::visualizers::MapColor;
+
+/// Offsets to the neighbors whose patterns overlap a tile's: left, right, up and down.
+const DIRECTIONS: [(i64, i64); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+fn default_pattern_size() -> usize {
+    3
+}
+
+fn default_max_attempts() -> u32 {
+    10
+}
+
+/// How patterns get extracted from a sample.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatternOptions {
+    /// Edge length of the patterns; bigger patterns copy bigger structures from the sample, but vary less.
+    #[serde(default = "default_pattern_size")]
+    pub pattern_size: usize,
+    /// If true, patterns wrap around the edges of the sample.
+    #[serde(default)]
+    pub periodic: bool,
+    /// If true, each pattern also counts in all its rotations and reflections.
+    #[serde(default)]
+    pub transforms: bool,
+}
+
+impl Default for PatternOptions {
+    fn default() -> Self {
+        Self {
+            pattern_size: default_pattern_size(),
+            periodic: false,
+            transforms: false
+        }
+    }
+}
+
+/// A sample image to extract patterns from; each pixel stands for the key with the closest color.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatternSample {
+    pub path: String,
+    #[serde(flatten)]
+    pub options: PatternOptions,
+    /// How many times to collapse the map from scratch before settling for a map with contradictions.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+impl PatternSample {
+    pub fn load<K: DistributionKey>(&self, colors: &HashMap<K, MapColor>) -> Result<OverlappingModel<K>, ril::Error> {
+        OverlappingModel::from_image(&self.path, colors, &self.options)
+    }
+}
+
+/// The overlapping model of Wave Function Collapse: every NxN window of a sample is a pattern, and the map is
+/// collapsed one pattern per tile, so that the patterns of neighboring tiles agree wherever they overlap.
+///
+/// Unlike the layout rules, which only look at pairs of neighbors, patterns capture structures as big as
+/// themselves (e.g. lines, bends or textures) straight from the sample.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OverlappingModel<K: DistributionKey> {
+    pattern_size: usize,
+    /// Keys of each pattern, row by row.
+    patterns: Vec<Vec<K>>,
+    /// How many times each pattern occurs in the sample.
+    weights: Vec<f32>,
+    /// For each pattern and direction, the patterns that may sit one tile over in that direction.
+    compatible: Vec<[Vec<usize>; 4]>,
+}
+
+impl<K: DistributionKey> OverlappingModel<K> {
+    /// Extracts the patterns from rows of keys. Samples that are ragged, or (unless periodic) smaller
+    /// than a pattern, give no patterns.
+    pub fn from_sample(sample: &[Vec<K>], options: &PatternOptions) -> Self {
+        let size = options.pattern_size.max(1);
+        let (height, width) = (sample.len(), sample.first().map(Vec::len).unwrap_or(0));
+        let mut model = Self {
+            pattern_size: size,
+            patterns: Vec::new(),
+            weights: Vec::new(),
+            compatible: Vec::new()
+        };
+        if width == 0 || sample.iter().any(|row| row.len() != width) { return model }
+
+        let (last_x, last_y) = match options.periodic {
+            true => (width, height),
+            false => ((width + 1).saturating_sub(size), (height + 1).saturating_sub(size))
+        };
+        let transforms: Vec<Transform> = match options.transforms {
+            true => Transform::all().collect(),
+            false => vec![Transform::default()]
+        };
+
+        let mut pattern_index: HashMap<Vec<K>, usize> = HashMap::new();
+        for y in 0..last_y {
+            for x in 0..last_x {
+                let window: Vec<K> = (0..size * size).map(
+                    |cell| sample[(y + cell / size) % height][(x + cell % size) % width]
+                ).collect();
+
+                for transform in transforms.iter() {
+                    let pattern = transform_pattern(&window, size, *transform);
+                    let index = *pattern_index.entry(pattern.to_owned()).or_insert_with(|| {
+                        model.patterns.push(pattern);
+                        model.weights.push(0.);
+                        model.patterns.len() - 1
+                    });
+                    model.weights[index] += 1.;
+                }
+            }
+        }
+
+        model.compatible = (0..model.patterns.len()).map(
+            |pattern| DIRECTIONS.map(|offset| (0..model.patterns.len()).filter(
+                |other| model.overlap_agrees(pattern, *other, offset)
+            ).collect())
+        ).collect();
+        model
+    }
+
+    /// Extracts the patterns from an image, with each pixel standing for the key with the closest color.
+    pub fn from_image(path: &str, colors: &HashMap<K, MapColor>, options: &PatternOptions) -> Result<Self, ril::Error> {
+        let image = ril::Image::<ril::Rgb>::open(path)?;
+        let mut palette: Vec<(K, [i32; 3])> = colors.iter().map(
+            |(key, MapColor::Rgb(r, g, b))| (*key, [*r, *g, *b].map(|channel| channel as i32))
+        ).collect();
+        palette.sort_by_key(|(key, _)| *key);
+
+        let closest_key = |pixel: &ril::Rgb| palette.iter().min_by_key(
+            |(_, color)| color.iter().zip([pixel.r, pixel.g, pixel.b]).map(|(a, b)| (a - b as i32).pow(2)).sum::<i32>()
+        ).map(|(key, _)| *key);
+
+        let sample: Option<Vec<Vec<K>>> = image.pixels().map(|row| row.iter().map(closest_key).collect()).collect();
+        Ok(Self::from_sample(&sample.unwrap_or_default(), options))
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn pattern_size(&self) -> usize {
+        self.pattern_size
+    }
+
+    /// The keys of a pattern, row by row.
+    pub fn pattern(&self, index: usize) -> Option<&[K]> {
+        self.patterns.get(index).map(Vec::as_slice)
+    }
+
+    /// The key a pattern puts on its tile, i.e. the one in its top left corner.
+    pub fn key_of(&self, pattern: usize) -> K {
+        self.patterns[pattern][0]
+    }
+
+    /// The possible patterns of a tile with some possible keys, weighted by how often they occur in the sample.
+    /// If none of the patterns has any of the keys on its tile, any pattern goes.
+    pub fn patterns_for(&self, state: &MapNodeState<K>) -> MultinomialDistribution<usize> {
+        let fits = |key: &K| match state {
+            MapNodeState::Finalized(assignment) => key == assignment,
+            MapNodeState::Undecided(dist) => dist.weight(key) > 0.
+        };
+        let mut weights: HashMap<usize, f32> = (0..self.len())
+            .filter(|pattern| fits(&self.key_of(*pattern)))
+            .map(|pattern| (pattern, self.weights[pattern]))
+            .collect();
+        if weights.is_empty() {
+            weights = (0..self.len()).map(|pattern| (pattern, self.weights[pattern])).collect();
+        }
+        MultinomialDistribution::from(weights)
+    }
+
+    /// Whether the other pattern can sit at the offset from the pattern, i.e. they have the same keys where they overlap.
+    fn overlap_agrees(&self, pattern: usize, other: usize, (dx, dy): (i64, i64)) -> bool {
+        let size = self.pattern_size as i64;
+        let (first, second) = (&self.patterns[pattern], &self.patterns[other]);
+        (dy.max(0)..size.min(size + dy)).all(|y| (dx.max(0)..size.min(size + dx)).all(
+            |x| first[(y * size + x) as usize] == second[((y - dy) * size + x - dx) as usize]
+        ))
+    }
+}
+
+/// A pattern turned as the transform says: mirrored left to right first (if reflected), then turned clockwise.
+fn transform_pattern<K: DistributionKey>(pattern: &[K], size: usize, transform: Transform) -> Vec<K> {
+    let mut transformed = pattern.to_vec();
+    for (cell, key) in pattern.iter().enumerate() {
+        let (mut x, mut y) = (cell % size, cell / size);
+        if transform.reflected {
+            x = size - 1 - x;
+        }
+        for _ in 0..transform.quarter_turns % 4 {
+            (x, y) = (size - 1 - y, x);
+        }
+        transformed[y * size + x] = *key;
+    }
+    transformed
+}
+
+/// Collapses a map with an OverlappingModel.
+///
+/// The job works on a wave - a map of the same shape whose keys are pattern indices - and uses the same
+/// queue and selection heuristics as a MapColoringJob. Whenever the possible patterns of a tile shrink, those
+/// of its neighbors get narrowed down to the ones that still agree with any of them, and so on outwards.
+/// Tiles are never narrowed down to nothing; such contradictions are counted and the tile is left as it was.
+pub struct OverlappingJob<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> {
+    model: Arc<OverlappingModel<K>>,
+    wave: Map2D<AG, usize, MP>,
+    /// The patterns each tile started out with, to start over from after a contradiction.
+    start: Vec<(MP, MapNodeState<usize>)>,
+    heuristic: Arc<dyn SelectionHeuristic<MP>>,
+    rng: StdRng,
+    max_attempts: u32,
+    attempts: u32,
+    contradictions: usize,
+}
+
+impl<AG: AdjacencyGenerator<2, Input = MP>, K: DistributionKey, MP: MapPosition<2>> OverlappingJob<AG, K, MP> {
+    /// Sets up a wave for the map; finalized tiles keep their key, and undecided ones are limited to their possible keys.
+    pub fn new(model: Arc<OverlappingModel<K>>, map: &Map2D<AG, K, MP>) -> Self {
+        let start: Vec<(MP, MapNodeState<usize>)> = map.tiles.iter().map(|tile| {
+            let reader = tile.read().unwrap();
+            (reader.position, MapNodeState::from(model.patterns_for(&reader.state)))
+        }).collect();
+
+        Self {
+            model,
+            wave: Self::wave_from(&start),
+            start,
+            heuristic: Arc::new(CellSelection::default()),
+            rng: StdRng::from_entropy(),
+            max_attempts: default_max_attempts(),
+            attempts: 0,
+            contradictions: 0
+        }
+    }
+
+    fn wave_from(start: &[(MP, MapNodeState<usize>)]) -> Map2D<AG, usize, MP> {
+        Map2D::from_tiles(start.iter().map(|(position, state)| Map2DNode::with_state(*position, state.to_owned())))
+    }
+
+    /// Seeds the job's random number generator; the same seed, model and starting map always produce the same map.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Swaps out the heuristic used to pick which tile gets collapsed next.
+    pub fn with_heuristic(mut self, heuristic: Box<dyn SelectionHeuristic<MP>>) -> Self {
+        self.heuristic = Arc::from(heuristic);
+        self
+    }
+
+    /// Sets how many times `assign()` collapses the map from scratch before settling for a map with contradictions.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// The patterns of the tiles so far, e.g. to render their uncertainty with a HeatmapVisualizer.
+    pub fn wave(&self) -> &Map2D<AG, usize, MP> {
+        &self.wave
+    }
+
+    /// How many times propagation would have left a tile with no possible patterns in the last attempt;
+    /// those tiles were left as they were, so their patterns may not agree with their neighbors'.
+    pub fn contradictions(&self) -> usize {
+        self.contradictions
+    }
+
+    /// How many times the map has been collapsed from scratch.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Collapses every tile of the wave to a pattern. Whenever that runs into a contradiction, the wave
+    /// starts over with a new seed drawn from the job's RNG, up to the job's maximum number of attempts.
+    pub fn assign(&mut self) -> &Map2D<AG, usize, MP> {
+        for attempt in 1..=self.max_attempts {
+            if attempt > 1 {
+                self.wave = Self::wave_from(&self.start);
+                self.rng = StdRng::seed_from_u64(self.rng.gen());
+            }
+            self.attempts = attempt;
+            self.contradictions = 0;
+            self.assign_once();
+            if self.contradictions == 0 { break }
+        }
+        &self.wave
+    }
+
+    fn assign_once(&mut self) {
+        let bounds = [self.wave.min_pos, self.wave.max_pos];
+        let mut queue = BinaryHeap::new();
+        let mut enqueued: HashSet<MP> = HashSet::new();
+
+        // tiles limited by the starting map limit their neighbors in turn
+        let mut limited: Vec<MP> = self.wave.undecided_tiles.iter().filter(
+            |(_, tile)| match &tile.read().unwrap().state {
+                MapNodeState::Undecided(dist) => dist.remaining_options() < self.model.len(),
+                MapNodeState::Finalized(_) => true
+            }
+        ).map(|(pos, _)| *pos).collect();
+        limited.sort_by_key(|pos| pos.get_dims());
+        self.propagate(limited, &mut queue, &mut enqueued, bounds);
+
+        loop {
+            let mut undecided: Vec<MP> = self.wave.undecided_tiles.keys().copied().collect();
+            if undecided.is_empty() { break }
+            // HashMap order is arbitrary; sorting keeps seeding reproducible for a given RNG
+            undecided.sort_by_key(|pos| pos.get_dims());
+
+            let mut seeds = self.heuristic.seeds(&undecided, bounds, &mut self.rng);
+            seeds.retain(|pos| self.wave.undecided_tiles.contains_key(pos));
+            if seeds.is_empty() {
+                seeds.extend(undecided.first());
+            }
+            for pos in seeds {
+                if let Some(tile) = self.wave.undecided_tiles.get(&pos) {
+                    enqueued.insert(pos);
+                    enqueue(&mut queue, self.heuristic.as_ref(), EntropyMetric::default(), &mut self.rng, bounds, tile);
+                }
+            }
+
+            while let Some(assignee) = queue.pop() {
+                let curr_pos = {
+                    let mut node = assignee.node.write().unwrap();
+                    let pattern = match &node.state {
+                        MapNodeState::Undecided(dist) => dist.sample(&mut self.rng),
+                        MapNodeState::Finalized(_) => continue
+                    };
+                    node.state = MapNodeState::from(pattern);
+                    node.position
+                };
+                enqueued.remove(&curr_pos);
+                self.wave.undecided_tiles.remove(&curr_pos);
+                self.propagate(vec![curr_pos], &mut queue, &mut enqueued, bounds);
+
+                // keep growing from the tile even where its pattern ruled nothing out
+                for (dx, dy) in DIRECTIONS {
+                    let neighbor = offset_position(curr_pos, dx, dy).and_then(|pos| self.wave.undecided_tiles.get(&pos));
+                    if let Some(neighbor) = neighbor {
+                        let neighbor_pos = neighbor.read().unwrap().position;
+                        if enqueued.insert(neighbor_pos) {
+                            enqueue(&mut queue, self.heuristic.as_ref(), EntropyMetric::default(), &mut self.rng, bounds, neighbor);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Narrows down the patterns around the tiles at the positions, and around any tiles narrowed down in turn.
+    fn propagate(
+        &mut self,
+        positions: Vec<MP>,
+        queue: &mut BinaryHeap<PrioritizedNode<AG, usize, MP>>,
+        enqueued: &mut HashSet<MP>,
+        bounds: [MP; 2]
+    ) {
+        let requeue = self.heuristic.reprioritize_on_update();
+        let mut stack = positions;
+
+        while let Some(pos) = stack.pop() {
+            let remaining: Vec<usize> = match self.wave.position_index.get(&pos) {
+                Some(tile) => match &tile.read().unwrap().state {
+                    MapNodeState::Finalized(pattern) => vec![*pattern],
+                    MapNodeState::Undecided(dist) => dist.keys().iter().filter(|pattern| dist.weight(pattern) > 0.).copied().collect()
+                },
+                None => continue
+            };
+
+            for (direction, (dx, dy)) in DIRECTIONS.into_iter().enumerate() {
+                let neighbor = match offset_position(pos, dx, dy).and_then(|neighbor_pos| self.wave.position_index.get(&neighbor_pos)) {
+                    Some(tile) => tile.to_owned(),
+                    None => continue
+                };
+
+                let mut allowed = vec![false; self.model.len()];
+                for pattern in remaining.iter() {
+                    for other in self.model.compatible[*pattern][direction].iter() {
+                        allowed[*other] = true;
+                    }
+                }
+
+                let mut neighbor_writer = neighbor.write().unwrap();
+                let kept: HashMap<usize, f32> = match &neighbor_writer.state {
+                    MapNodeState::Undecided(dist) => {
+                        let kept: HashMap<usize, f32> = dist.keys().iter()
+                            .filter(|pattern| allowed[**pattern] && dist.weight(pattern) > 0.)
+                            .map(|pattern| (*pattern, dist.weight(pattern)))
+                            .collect();
+                        if kept.len() == dist.remaining_options() { continue }
+                        kept
+                    },
+                    MapNodeState::Finalized(_) => continue
+                };
+                if kept.is_empty() {
+                    self.contradictions += 1;
+                    continue
+                }
+
+                neighbor_writer.state = MapNodeState::from(MultinomialDistribution::from(kept));
+                let neighbor_pos = neighbor_writer.position;
+                drop(neighbor_writer);
+
+                stack.push(neighbor_pos);
+                if requeue || enqueued.insert(neighbor_pos) {
+                    enqueue(queue, self.heuristic.as_ref(), EntropyMetric::default(), &mut self.rng, bounds, &neighbor);
+                }
+            }
+        }
+    }
+
+    /// Consumes the job, returning a map with the key of each tile's pattern; tiles the job has not collapsed
+    /// yet can have the keys of any of their patterns.
+    pub fn into_map(self) -> Map2D<AG, K, MP> {
+        Map2D::from_tiles(self.wave.tiles.iter().map(|tile| {
+            let reader = tile.read().unwrap();
+            let state = match &reader.state {
+                MapNodeState::Finalized(pattern) => MapNodeState::from(self.model.key_of(*pattern)),
+                MapNodeState::Undecided(dist) => {
+                    let mut weights: HashMap<K, f32> = HashMap::new();
+                    for pattern in dist.keys() {
+                        *weights.entry(self.model.key_of(*pattern)).or_default() += dist.weight(pattern);
+                    }
+                    MapNodeState::from(MultinomialDistribution::from(weights))
+                }
+            };
+            Map2DNode::with_state(reader.position, state)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::finalized::FinalizedMap;
+    use crate::position2d::Position2D;
+    use super::*;
+
+    type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+
+    fn diagonal_stripes() -> Vec<Vec<u8>> {
+        // stripes running from the top right to the bottom left, two tiles of 1 to every tile of 2
+        (0..6).map(|y| (0..6).map(|x| if (x + y) % 3 == 0 { 2 } else { 1 }).collect()).collect()
+    }
+
+    #[test]
+    fn patterns_overlap_their_neighbors() {
+        let options = PatternOptions { pattern_size: 2, periodic: true, transforms: false };
+        let model = OverlappingModel::from_sample(&diagonal_stripes(), &options);
+        assert_eq!(model.len(), 3);
+        assert_eq!(model.weights.iter().sum::<f32>(), 36.);
+
+        for pattern in 0..model.len() {
+            // each stripe pattern is followed by exactly one other to the right
+            assert_eq!(model.compatible[pattern][1].len(), 1);
+            let right = model.compatible[pattern][1][0];
+            assert!(model.compatible[right][0].contains(&pattern));
+        }
+
+        let turned = PatternOptions { transforms: true, ..options };
+        assert_eq!(OverlappingModel::from_sample(&diagonal_stripes(), &turned).len(), 6);
+        assert!(OverlappingModel::from_sample(&[vec![1u8]], &PatternOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn collapsed_maps_are_made_of_sample_patterns() {
+        let options = PatternOptions { pattern_size: 3, periodic: true, transforms: false };
+        let model = Arc::new(OverlappingModel::from_sample(&diagonal_stripes(), &options));
+        let map: TestMap = Map2D::from_tiles((0..10).flat_map(|x| (0..10).map(
+            move |y| Map2DNode::with_possibilities(Position2D::new(x, y), MultinomialDistribution::uniform_over([1, 2]))
+        )));
+
+        let mut job = OverlappingJob::new(model.to_owned(), &map).with_seed(4);
+        job.assign();
+        assert_eq!(job.contradictions(), 0);
+        assert_eq!(job.attempts(), 1);
+        let grid = FinalizedMap::from_map(&job.into_map()).unwrap();
+
+        // the pairwise view (2s next to 1s, 1s next to both) would allow clumps of 2s; the patterns never do
+        for (pos, key) in grid.iter() {
+            let (x, y) = (pos.x as usize, pos.y as usize);
+            let next_on_stripe = grid.get(Position2D::new((x + 1) as u8, y.wrapping_sub(1) as u8));
+            if let Some(next) = next_on_stripe {
+                assert_eq!(next, key);
+            }
+            let window: Vec<u8> = (0..9).filter_map(|cell| grid.get(Position2D::new((x + cell % 3) as u8, (y + cell / 3) as u8)).copied()).collect();
+            if window.len() == 9 {
+                assert!(model.patterns.contains(&window));
+            }
+        }
+    }
+
+    #[test]
+    fn contradictions_are_retried_and_reported() {
+        // a lone pattern that does not overlap itself when shifted; no two tiles can ever sit side by side
+        let sample = vec![vec![1u8, 2], vec![2, 2]];
+        let options = PatternOptions { pattern_size: 2, periodic: false, transforms: false };
+        let model = Arc::new(OverlappingModel::from_sample(&sample, &options));
+        let map: TestMap = Map2D::from_tiles((0..4).flat_map(|x| (0..4).map(
+            move |y| Map2DNode::with_possibilities(Position2D::new(x, y), MultinomialDistribution::uniform_over([1, 2]))
+        )));
+
+        let mut job = OverlappingJob::new(model, &map).with_seed(1).with_max_attempts(3);
+        job.assign();
+        assert_eq!(job.attempts(), 3);
+        assert!(job.contradictions() > 0);
+        assert!(job.wave().undecided_tiles.is_empty());
+    }
+}
@@ -43,7 +43,7 @@ impl<PA: PositionKey + Add<Output = PA>> Add for Position2D<PA> {
     }
 }
 
-impl<'a, P: PositionKey + 'a> MapPosition<2> for Position2D<P> {
+impl<P: PositionKey> MapPosition<2> for Position2D<P> {
     type Key = P;
 
     fn get_dims(&self) -> [Self::Key; 2] {
@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::assigner::MapColoringAssigner;
+use crate::field::ScalarField;
+use crate::map2d::Map2D;
+use crate::map2dnode::MapNodeState;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+fn default_mask_min() -> f32 { 0.5 }
+
+fn default_mask_max() -> f32 { 1. }
+
+/// An area of a map.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RegionShape {
+    /// A rectangle between two corners, inclusive.
+    Rect { min: [i64; 2], max: [i64; 2] },
+    /// All tiles whose centers are within the radius of the center.
+    Circle { center: [i64; 2], radius: f64 },
+    /// All tiles whose centers are between the two radii from the center, inclusive.
+    Ring { center: [i64; 2], inner_radius: f64, outer_radius: f64 },
+    /// All tiles where the value of a field (e.g. a black & white mask image) is between `min` and `max`.
+    /// By default, that is the brighter half of the field.
+    Mask {
+        field: ScalarField,
+        #[serde(default = "default_mask_min")]
+        min: f32,
+        #[serde(default = "default_mask_max")]
+        max: f32
+    },
+}
+
+impl RegionShape {
+    fn distance_from(center: &[i64; 2], pos: [f64; 2]) -> f64 {
+        (pos[0] - center[0] as f64).hypot(pos[1] - center[1] as f64)
+    }
+
+    /// Finds the positions of all tiles of a map within the region.
+    ///
+    /// **Returns**: the positions, or the error encountered while loading the mask, if any.
+    ///
+    pub fn select<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>>(&self, map: &Map2D<AG, K, MP>) -> Result<HashSet<MP>, ril::Error> {
+        let positions = map.position_index.keys().copied();
+        let as_floats = |pos: &MP| pos.get_dims().map(|dim| dim.to_f64().unwrap_or(0.));
+
+        Ok(match self {
+            Self::Rect { min, max } => positions.filter(|pos| {
+                let [x, y] = as_floats(pos);
+                x >= min[0] as f64 && x <= max[0] as f64 && y >= min[1] as f64 && y <= max[1] as f64
+            }).collect(),
+            Self::Circle { center, radius } => positions.filter(
+                |pos| Self::distance_from(center, as_floats(pos)) <= *radius
+            ).collect(),
+            Self::Ring { center, inner_radius, outer_radius } => positions.filter(|pos| {
+                let distance = Self::distance_from(center, as_floats(pos));
+                distance >= *inner_radius && distance <= *outer_radius
+            }).collect(),
+            Self::Mask { field, min, max } => {
+                let loaded = field.load()?;
+                let bounds = [map.min_pos, map.max_pos];
+                positions.filter(|pos| {
+                    let value = loaded.value_at(*pos, bounds);
+                    value >= *min && value <= *max
+                }).collect()
+            },
+        })
+    }
+}
+
+/// Finds the area of connected tiles (as given by the map's adjacency) that have the same key as the tile at the start,
+/// e.g. a whole lake to re-generate. Undecided tiles count as having the same key as each other.
+///
+/// **Returns**: the positions of the area, or nothing if the start is not on the map.
+///
+pub fn flood_fill<AG, K, MP>(map: &Map2D<AG, K, MP>, start: MP) -> HashSet<MP>
+where
+    AG: AdjacencyGenerator<2, Input = MP>,
+    K: DistributionKey,
+    MP: MapPosition<2>
+{
+    let key_of = |pos: &MP| map.get(pos).map(|tile| match tile.read().unwrap().state {
+        MapNodeState::Finalized(key) => Some(key),
+        MapNodeState::Undecided(_) => None
+    });
+    let start_key = match key_of(&start) {
+        Some(start_key) => start_key,
+        None => return HashSet::new()
+    };
+
+    let mut area = HashSet::from([start]);
+    let mut frontier = VecDeque::from([start]);
+    while let Some(pos) = frontier.pop_front() {
+        for neighbor in map.adjacent_from_pos(pos) {
+            let neighbor_pos = neighbor.read().unwrap().position;
+            if key_of(&neighbor_pos) == Some(start_key) && area.insert(neighbor_pos) {
+                frontier.push_back(neighbor_pos);
+            }
+        }
+    }
+    area
+}
+
+/// A set of rules that only applies within a region of the map.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RegionalRules<K: DistributionKey> {
+    pub shape: RegionShape,
+    pub rules: MapColoringAssigner<K>,
+}
+
+/// The rules in force at each position of a particular map,
+/// for positions that do not follow the default rules of the job.
+#[derive(Clone)]
+pub struct RegionMap<K: DistributionKey, MP: MapPosition<2>> {
+    assigners: Vec<MapColoringAssigner<K>>,
+    index: HashMap<MP, usize>,
+}
+
+impl<K: DistributionKey, MP: MapPosition<2>> Default for RegionMap<K, MP> {
+    fn default() -> Self {
+        Self {
+            assigners: Vec::new(),
+            index: HashMap::new()
+        }
+    }
+}
+
+impl<K: DistributionKey, MP: MapPosition<2>> RegionMap<K, MP> {
+    /// Makes the rules apply at the positions given, overriding any earlier region at those positions.
+    pub fn add_region<I: IntoIterator<Item = MP>>(&mut self, rules: MapColoringAssigner<K>, positions: I) {
+        let region_idx = self.assigners.len();
+        self.assigners.push(rules);
+        self.index.extend(positions.into_iter().map(|pos| (pos, region_idx)));
+    }
+
+    /// The rules in force at a position, or None if it does not belong to any region.
+    pub fn rules_at(&self, position: &MP) -> Option<&MapColoringAssigner<K>> {
+        self.index.get(position).map(|idx| &self.assigners[*idx])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Whether any of the regions' rules have distance bands.
+    pub fn has_distance_rules(&self) -> bool {
+        self.assigners.iter().any(|rules| !rules.distance_rules.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use super::*;
+
+    fn test_map() -> Map2D<CardinalAdjacencyGenerator<Position2D<i32>>, i32, Position2D<i32>> {
+        Map2D::from_tiles((0..10).flat_map(|x| (0..10).map(move |y| Map2DNode::with_assignment(Position2D::new(x, y), 1))))
+    }
+
+    #[test]
+    fn shapes_select_tiles() {
+        let map = test_map();
+        let rect = RegionShape::Rect { min: [2, 2], max: [4, 3] }.select(&map).unwrap();
+        assert_eq!(rect.len(), 6);
+
+        let circle = RegionShape::Circle { center: [5, 5], radius: 1. }.select(&map).unwrap();
+        assert_eq!(circle.len(), 5);
+
+        let ring = RegionShape::Ring { center: [5, 5], inner_radius: 0.5, outer_radius: 1.5 }.select(&map).unwrap();
+        assert_eq!(ring.len(), 8);
+        assert!(!ring.contains(&Position2D::new(5, 5)));
+    }
+
+    #[test]
+    fn later_regions_take_precedence() {
+        let map = test_map();
+        let rules = |key: i32| MapColoringAssigner::with_rules(HashMap::from([(key, crate::sampler::MultinomialDistribution::uniform_over([key]))]));
+        let regions = [
+            RegionalRules { shape: RegionShape::Rect { min: [0, 0], max: [9, 4] }, rules: rules(2) },
+            RegionalRules { shape: RegionShape::Rect { min: [0, 4], max: [9, 9] }, rules: rules(3) },
+        ];
+        let mut region_map = RegionMap::default();
+        for region in regions {
+            region_map.add_region(region.rules, region.shape.select(&map).unwrap());
+        }
+
+        let key_at = |x, y| *region_map.rules_at(&Position2D::new(x, y)).unwrap().transition_rules.keys().next().unwrap();
+        assert_eq!(key_at(0, 3), 2);
+        assert_eq!(key_at(0, 4), 3);
+    }
+
+    #[test]
+    fn flood_fill_stays_within_the_key() {
+        let map = test_map();
+        // a wall of 2s splitting the map at x = 3
+        for y in 0..10 {
+            let wall = map.get(Position2D::new(3, y)).unwrap();
+            wall.write().unwrap().state = MapNodeState::Finalized(2);
+        }
+
+        assert_eq!(flood_fill(&map, Position2D::new(0, 0)).len(), 30);
+        assert_eq!(flood_fill(&map, Position2D::new(3, 5)).len(), 10);
+        assert!(flood_fill(&map, Position2D::new(20, 20)).is_empty());
+    }
+}
@@ -1,27 +1,66 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::Error;
-use std::ops::{Div, Mul};
 use std::path::Path;
-use std::sync::{Arc, RwLock};
-use std::usize;
-use itertools::Itertools;
-use num::{Bounded, NumCast, range_inclusive, Zero};
+use std::sync::{Arc, OnceLock, RwLock};
+use rand::prelude::*;
+use num::{Bounded, NumCast, ToPrimitive, Zero};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use crate::adjacency::AdjacencyGenerator;
 
+use crate::analysis::MapStatistics;
 use crate::assigner::{MapColoringAssigner, MapColoringJob};
+use crate::connectivity::ConnectivityConstraint;
+use crate::diff::seam_around;
+use crate::boundary::BoundaryConditions;
+use crate::symmetry::Symmetry;
+use crate::transform::{TileGroup, TileTransforms};
+use crate::overlapping::{OverlappingJob, OverlappingModel, PatternSample};
+use crate::fitness::{BatchResult, BestOf, fitness_score, FitnessCriterion, ScoredMap};
+use crate::field::{FieldModulation, LoadedModulation};
+use crate::history::PendingEdit;
+use crate::region::{RegionalRules, RegionMap, RegionShape};
+use crate::selection::CellSelection;
+use crate::sink::{EncodableOutput, FileSink, MapSink, render_to, StdoutSink};
 use crate::map2d::Map2D;
-use crate::map2dnode::{Map2DNode, MapNodeState};
+use crate::map2dnode::Map2DNode;
 use crate::mapgen_presets;
 use crate::position::{MapPosition, PositionKey};
 use crate::sampler::{DistributionKey, MultinomialDistribution};
-use crate::visualizers::{MapColor, MapVisualizer, RilPixelVisualizer};
+use crate::visualizers::{DiffVisualizer, MapColor, MapVisualizer, RilPixelVisualizer, SvgOptions, SvgVisualizer, TerminalOptions, TerminalVisualizer};
 
+/// A user-supplied function building an unassigned map of a given size.
+pub type MapBuilderFn<AG, DK, MP> = Box<dyn Fn(u32) -> Map2D<AG, DK, MP>>;
+
+/// Starts recording a re-generation of an area of a map in its history; besides the area itself,
+/// re-generating collapses any tiles that were still undecided.
+pub(crate) fn begin_regeneration<AG, DK, MP>(map: &Map2D<AG, DK, MP>, positions: &[MP]) -> PendingEdit<DK, MP>
+where
+    AG: AdjacencyGenerator<2, Input = MP>,
+    DK: DistributionKey,
+    MP: MapPosition<2>
+{
+    map.begin_edit(positions.iter().chain(map.undecided_tiles.keys()).copied())
+}
+
+/// Renders a map and saves it to a file named after the stem, with the visualizer's usual extension.
+fn save_render<AG, DK, MP, V>(visualiser: &V, map: &Map2D<AG, DK, MP>, stem: &str)
+where
+    AG: AdjacencyGenerator<2>,
+    DK: DistributionKey,
+    MP: MapPosition<2>,
+    V: MapVisualizer<AG, DK, MP>,
+    V::Output: EncodableOutput
+{
+    let path = format!("{}.{}", stem, visualiser.file_extension());
+    if let Err(err) = render_to(visualiser, map, &FileSink::new(path)) {
+        eprintln!("{}", err);
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct GeneratorRuleset<A: DistributionKey> {
@@ -29,6 +68,56 @@ pub struct GeneratorRuleset<A: DistributionKey> {
     coloring_rules: HashMap<A, MapColor>,
     pub(crate) map_size: u32,
     pub(crate) adjacency: Option<String>,
+    #[serde(default)]
+    connectivity: Option<ConnectivityConstraint<A>>,
+    #[serde(default)]
+    cell_selection: CellSelection,
+    /// Seed for the random number generator; if unset, every run produces a different map.
+    #[serde(default)]
+    seed: Option<u64>,
+    /// Edge length of the chunks the map is split into for parallel generation.
+    #[serde(default)]
+    chunk_size: Option<u32>,
+    /// Scalar fields (e.g. heightmaps) biasing the weights of tile types across the map.
+    #[serde(default)]
+    field_modulation: Vec<FieldModulation<A>>,
+    #[serde(skip)]
+    loaded_modulation: OnceLock<Vec<Arc<LoadedModulation<A>>>>,
+    /// Layout rules overriding the main ones in parts of the map; later regions win where they overlap.
+    #[serde(default)]
+    regions: Vec<RegionalRules<A>>,
+    /// Keys of the virtual tiles just outside each side of the map.
+    #[serde(default)]
+    boundary: BoundaryConditions<A>,
+    /// If set, maps are mirrored or rotated onto themselves, e.g. to make them fair to all players.
+    #[serde(default)]
+    symmetry: Option<Symmetry<A>>,
+    /// If true, the statistics of each generated map are printed out.
+    #[serde(default)]
+    print_statistics: bool,
+    /// Characters to draw each tile type with in the terminal.
+    #[serde(default)]
+    glyphs: HashMap<A, char>,
+    /// Rotated and reflected variants of the tiles; rules, colors and glyphs given for some
+    /// of the variants of a tile are expanded to all of them.
+    #[serde(default)]
+    tile_groups: Vec<TileGroup<A>>,
+    /// If set, maps are collapsed with NxN patterns from this sample instead of the layout rules;
+    /// re-generating parts of a map still follows the layout rules. Regions, boundary, field modulation,
+    /// symmetry and connectivity are ignored for pattern maps.
+    #[serde(default)]
+    pattern_sample: Option<PatternSample>,
+    #[serde(skip)]
+    loaded_patterns: OnceLock<Option<Arc<OverlappingModel<A>>>>,
+    /// If set, each generated map is also drawn in the terminal.
+    #[serde(default)]
+    terminal_preview: Option<TerminalOptions>,
+    /// Criteria for scoring generated maps; if empty, maps are scored by how well they follow the layout rules.
+    #[serde(default)]
+    fitness: Vec<FitnessCriterion<A>>,
+    /// If set, the app generates a batch of maps and keeps the best-scoring ones.
+    #[serde(default)]
+    best_of: Option<BestOf>,
     comments: Option<String>
 }
 
@@ -45,10 +134,270 @@ impl<A: DistributionKey> GeneratorRuleset<A> {
             coloring_rules: coloring,
             map_size: map_size.unwrap_or(60u32),
             adjacency,
+            connectivity: None,
+            cell_selection: CellSelection::default(),
+            seed: None,
+            chunk_size: None,
+            field_modulation: Vec::new(),
+            loaded_modulation: OnceLock::new(),
+            regions: Vec::new(),
+            boundary: BoundaryConditions::default(),
+            symmetry: None,
+            print_statistics: false,
+            glyphs: HashMap::new(),
+            tile_groups: Vec::new(),
+            pattern_sample: None,
+            loaded_patterns: OnceLock::new(),
+            terminal_preview: None,
+            fitness: Vec::new(),
+            best_of: None,
             comments: None
         }
     }
 
+    pub fn with_connectivity(mut self, connectivity: Option<ConnectivityConstraint<A>>) -> Self {
+        self.connectivity = connectivity;
+        self
+    }
+
+    pub fn with_cell_selection(mut self, cell_selection: CellSelection) -> Self {
+        self.cell_selection = cell_selection;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: Option<u32>) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn with_field_modulation(mut self, field_modulation: Vec<FieldModulation<A>>) -> Self {
+        self.field_modulation = field_modulation;
+        self.loaded_modulation = OnceLock::new();
+        self
+    }
+
+    pub fn with_regions(mut self, regions: Vec<RegionalRules<A>>) -> Self {
+        self.regions = regions;
+        self
+    }
+
+    pub fn with_boundary(mut self, boundary: BoundaryConditions<A>) -> Self {
+        self.boundary = boundary;
+        self
+    }
+
+    pub fn with_symmetry(mut self, symmetry: Option<Symmetry<A>>) -> Self {
+        self.symmetry = symmetry;
+        self
+    }
+
+    pub fn with_print_statistics(mut self, print_statistics: bool) -> Self {
+        self.print_statistics = print_statistics;
+        self
+    }
+
+    pub fn with_glyphs(mut self, glyphs: HashMap<A, char>) -> Self {
+        self.glyphs = glyphs;
+        self
+    }
+
+    /// Sets the tile groups and expands the Ruleset's rules (including those of its regions and its
+    /// connectivity constraint), colors and glyphs to every variant in them; set those first.
+    pub fn with_tile_groups(mut self, tile_groups: Vec<TileGroup<A>>) -> Self {
+        self.tile_groups = tile_groups;
+        self.expand_tile_groups();
+        self
+    }
+
+    /// Expands the Ruleset to every variant in its tile groups; expanding it again changes nothing.
+    fn expand_tile_groups(&mut self) {
+        let transforms = self.tile_transforms();
+        if transforms.is_empty() { return }
+
+        self.layout_rules = transforms.expand_assigner(&self.layout_rules);
+        for region in self.regions.iter_mut() {
+            region.rules = transforms.expand_assigner(&region.rules);
+        }
+        if let Some(connectivity) = self.connectivity.as_mut() {
+            connectivity.walkable = transforms.expand_keys(&connectivity.walkable);
+        }
+        self.coloring_rules = transforms.expand_values(&self.coloring_rules);
+        self.glyphs = transforms.expand_values(&self.glyphs);
+    }
+
+    /// The Ruleset's tile groups, indexed by key; e.g. to look up which sprite to draw a variant with, and how.
+    pub fn tile_transforms(&self) -> TileTransforms<A> {
+        TileTransforms::new(&self.tile_groups)
+    }
+
+    pub fn with_pattern_sample(mut self, pattern_sample: Option<PatternSample>) -> Self {
+        self.pattern_sample = pattern_sample;
+        self.loaded_patterns = OnceLock::new();
+        self
+    }
+
+    /// The patterns of the Ruleset's pattern sample, loaded (with the Ruleset's colors) on first use.
+    /// Samples that fail to load or have no patterns are reported and skipped, and so are the settings
+    /// the pattern model does not support.
+    pub fn pattern_model(&self) -> Option<&Arc<OverlappingModel<A>>> {
+        self.loaded_patterns.get_or_init(|| {
+            let sample = self.pattern_sample.as_ref()?;
+            match sample.load(&self.coloring_rules) {
+                Ok(model) if !model.is_empty() => {
+                    let unsupported: Vec<&str> = [
+                        ("regions", !self.regions.is_empty()),
+                        ("boundary", !self.boundary.is_empty()),
+                        ("field_modulation", !self.field_modulation.is_empty()),
+                        ("symmetry", self.symmetry.is_some()),
+                        ("connectivity", self.connectivity.is_some()),
+                    ].into_iter().filter(|(_, set)| *set).map(|(name, _)| name).collect();
+                    if !unsupported.is_empty() {
+                        eprintln!("WARNING: {} not supported with a pattern_sample, ignoring them", unsupported.join(", "));
+                    }
+                    Some(Arc::new(model))
+                },
+                Ok(_) => {
+                    eprintln!("WARNING: pattern sample {:?} has no patterns, using the layout rules instead", sample.path);
+                    None
+                },
+                Err(err) => {
+                    eprintln!("WARNING: failed to load pattern sample {:?}, using the layout rules instead: {}", sample.path, err);
+                    None
+                }
+            }
+        }).as_ref()
+    }
+
+    /// Sets up an OverlappingJob for a map using the Ruleset's pattern model, cell selection heuristic,
+    /// seed and maximum number of attempts. Returns None if the Ruleset has no pattern model.
+    pub fn pattern_job<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: &Map2D<AG, A, MP>) -> Option<OverlappingJob<AG, A, MP>> {
+        let model = self.pattern_model()?.to_owned();
+        let max_attempts = self.pattern_sample.as_ref()?.max_attempts;
+        let mut job = OverlappingJob::new(model, map)
+            .with_heuristic(Box::new(self.cell_selection.to_owned()))
+            .with_max_attempts(max_attempts);
+        if let Some(seed) = self.seed {
+            job = job.with_seed(seed);
+        }
+        Some(job)
+    }
+
+    /// Collapses a map with the Ruleset's pattern model, if it has one.
+    /// Contradictions left after the last attempt are reported.
+    fn collapse_patterns<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: &Map2D<AG, A, MP>, seed: Option<u64>) -> Option<Map2D<AG, A, MP>> {
+        let mut job = self.pattern_job(map)?;
+        if let Some(seed) = seed {
+            job = job.with_seed(seed);
+        }
+        job.assign();
+        if job.contradictions() > 0 {
+            eprintln!("WARNING: {} contradiction(s) left in the pattern map after {} attempt(s)", job.contradictions(), job.attempts());
+        }
+        Some(job.into_map())
+    }
+
+    pub fn with_terminal_preview(mut self, terminal_preview: Option<TerminalOptions>) -> Self {
+        self.terminal_preview = terminal_preview;
+        self
+    }
+
+    /// A visualizer drawing maps in the terminal with the Ruleset's glyphs and colors.
+    pub fn terminal_visualizer(&self, options: TerminalOptions) -> TerminalVisualizer<A> {
+        TerminalVisualizer::new(self.glyphs.to_owned(), self.coloring_rules.to_owned(), options)
+    }
+
+    /// A visualizer drawing maps as SVG images with the Ruleset's colors.
+    pub fn svg_visualizer(&self, options: SvgOptions) -> SvgVisualizer<A> {
+        SvgVisualizer::new(self.coloring_rules.to_owned(), options)
+    }
+
+    /// A visualizer highlighting the differences between two maps, with the Ruleset's colors for the rest.
+    pub fn diff_visualizer(&self) -> DiffVisualizer<A> {
+        DiffVisualizer::new(self.coloring_rules.to_owned())
+    }
+
+    pub fn with_fitness(mut self, fitness: Vec<FitnessCriterion<A>>) -> Self {
+        self.fitness = fitness;
+        self
+    }
+
+    pub fn with_best_of(mut self, best_of: Option<BestOf>) -> Self {
+        self.best_of = best_of;
+        self
+    }
+
+    /// The Ruleset's field modulations, with their fields loaded on first use.
+    /// Modulations whose field fails to load are reported and skipped.
+    pub fn loaded_modulations(&self) -> &[Arc<LoadedModulation<A>>] {
+        self.loaded_modulation.get_or_init(|| {
+            self.field_modulation.iter().filter_map(|modulation| match modulation.load() {
+                Ok(loaded) => Some(Arc::new(loaded)),
+                Err(err) => {
+                    eprintln!("WARNING: failed to load field {:?}, skipping it: {}", modulation.field, err);
+                    None
+                }
+            }).collect()
+        })
+    }
+
+    /// Sets up a MapColoringJob for a map using the Ruleset's layout rules, cell selection heuristic, seed and chunk size.
+    ///
+    /// The Ruleset's regions are laid out over the map; regions whose mask fails to load are reported and skipped.
+    /// The tiles along the edges of the map are then conditioned on the Ruleset's boundary conditions.
+    /// If the Ruleset is symmetric, the job collapses the counterparts of each tile across the map along with it.
+    ///
+    /// Field modulations of the Ruleset that apply to the initial possibilities are applied
+    /// to the undecided tiles of the map right away; the rest are handed over to the job.
+    pub fn coloring_job<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: Map2D<AG, A, MP>) -> MapColoringJob<AG, A, MP> {
+        let bounds = [map.min_pos, map.max_pos];
+        self.coloring_job_within(map, bounds)
+    }
+
+    /// Like `coloring_job()`, for a map that is a part of a bigger map with the specified bounds;
+    /// boundary conditions only apply where the part touches the edges of the bigger map,
+    /// and modulating fields are laid out over the whole of it.
+    pub fn coloring_job_within<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: Map2D<AG, A, MP>, bounds: [MP; 2]) -> MapColoringJob<AG, A, MP> {
+        let modulations = self.loaded_modulations();
+        for modulation in modulations.iter().filter(|m| m.applies_initially()) {
+            modulation.modulate_map(&map, bounds);
+        }
+
+        let mut region_map = RegionMap::default();
+        for region in self.regions.iter() {
+            match region.shape.select(&map) {
+                Ok(positions) => region_map.add_region(region.rules.to_owned(), positions),
+                Err(err) => eprintln!("WARNING: failed to load region {:?}, skipping it: {}", region.shape, err)
+            }
+        }
+
+        let mut job = MapColoringJob::new(self.layout_rules.to_owned(), map)
+            .with_heuristic(Box::new(self.cell_selection.to_owned()))
+            .with_modulations(modulations.iter().filter(|m| m.applies_on_sampling()).cloned().collect())
+            .with_modulation_bounds(bounds);
+
+        if !region_map.is_empty() {
+            job = job.with_regions(region_map);
+        }
+        job = job.with_boundary(&self.boundary, bounds);
+
+        if let Some(symmetry) = &self.symmetry {
+            job = job.with_symmetry(symmetry.to_owned());
+        }
+
+        if let Some(seed) = self.seed {
+            job = job.with_seed(seed);
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            job = job.with_chunk_size(chunk_size);
+        }
+        job
+    }
+
 }
 
 impl<A: DistributionKey> From<GeneratorRuleset<A>> for MapColoringAssigner<A> {
@@ -115,8 +464,11 @@ impl GeneratorRuleset<i8> {
     pub fn load<P: AsRef<Path> + Debug>(filepath: P) -> Result<Self, Error> {
         let savefile = File::open(&filepath);
         match savefile {
-            Ok(file) => match serde_json::from_reader(file) {
-                Ok(inst) => Ok(inst),
+            Ok(file) => match serde_json::from_reader::<_, Self>(file) {
+                Ok(mut inst) => {
+                    inst.expand_tile_groups();
+                    Ok(inst)
+                },
                 Err(e) => {
                     eprintln!("Failed to parse GeneratorRuleset savefile {:?}.", filepath);
                     Err(Error::from(e))
@@ -168,18 +520,196 @@ impl<DK: DistributionKey> GeneratorRuleset<DK> {
         Map2D::from_tiles(test_tiles)
     }
 
+    pub fn layout_rules(&self) -> &MapColoringAssigner<DK> {
+        &self.layout_rules
+    }
+
+    /// The tile types of the Ruleset, i.e. the ones that have a color assigned.
+    pub fn tile_keys(&self) -> impl Iterator<Item = DK> + '_ {
+        self.coloring_rules.keys().copied()
+    }
+
+    /// Gathers the statistics of a map, checked against the Ruleset's (main) layout rules.
+    pub fn statistics<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: &Map2D<AG, DK, MP>) -> MapStatistics<DK> {
+        MapStatistics::analyze(map, &self.layout_rules)
+    }
+
+    /// Scores a map with the Ruleset's fitness criteria.
+    pub fn fitness_score<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: &Map2D<AG, DK, MP>) -> f64 {
+        fitness_score(&self.fitness, map, &self.layout_rules)
+    }
+
+    /// Prints the terminal preview (to stdout) and the statistics (to stderr) of a generated map, if the Ruleset asks for them.
+    fn report_map<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: &Map2D<AG, DK, MP>) {
+        if let Some(options) = self.terminal_preview {
+            if let Some(preview) = self.terminal_visualizer(options).visualise(map, None) {
+                let _ = StdoutSink::default().write(&preview);
+            }
+        }
+        if self.print_statistics {
+            eprint!("{}", self.statistics(map));
+        }
+    }
+
+    /// Collapses a map using the Ruleset's rules and applies the connectivity constraint (if any).
+    /// If the Ruleset has a pattern model, the map is collapsed with that instead, with no connectivity constraint.
+    ///
+    /// **Returns**: the collapsed map.
+    ///
+    pub fn collapse<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: Map2D<AG, DK, MP>) -> Map2D<AG, DK, MP> {
+        if let Some(collapsed) = self.collapse_patterns(&map, None) {
+            return collapsed
+        }
+
+        let mut job = self.coloring_job(map);
+        job.queue_and_assign();
+        self.enforce_connectivity(job.into_map())
+    }
+
+    /// Applies the Ruleset's connectivity constraint (if any) to a collapsed map,
+    /// re-generating disconnected walkable regions and reporting the components found.
+    pub fn enforce_connectivity<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: Map2D<AG, DK, MP>) -> Map2D<AG, DK, MP> {
+        self.enforce_connectivity_seeded(map, self.seed)
+    }
+
+    /// Like `enforce_connectivity()`, with the seeds of the re-generated regions drawn from the specified seed.
+    fn enforce_connectivity_seeded<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, map: Map2D<AG, DK, MP>, seed: Option<u64>) -> Map2D<AG, DK, MP> {
+        match &self.connectivity {
+            None => map,
+            Some(constraint) => {
+                // each re-generation gets a seed of its own, drawn from the seed if there is one
+                let mut rng = match seed {
+                    Some(seed) => StdRng::seed_from_u64(seed),
+                    None => StdRng::from_entropy()
+                };
+                let (new_map, report) = constraint.enforce(map, &self.regeneration_keys(), |map, region, keys| {
+                    // on symmetric maps, the counterparts of the area are re-generated along with it
+                    let mut region = region.to_owned();
+                    if let Some(symmetry) = &self.symmetry {
+                        let bounds = [map.min_pos, map.max_pos];
+                        let counterparts: Vec<MP> = region.iter().flat_map(|pos| symmetry.counterpart_positions(*pos, bounds)).collect();
+                        region.extend(counterparts);
+                    }
+                    let mut job = self.regeneration_job_over(map, region, keys).with_seed(rng.gen());
+                    job.queue_and_assign();
+                    job.into_map()
+                });
+                eprintln!(
+                    "Connectivity: {} walkable component(s) found, {} remaining after {} re-generation attempt(s), {} stray tile(s) filled in; sizes: {:?}",
+                    report.initial_component_count,
+                    report.components.len(),
+                    report.attempts,
+                    report.filled_tiles,
+                    report.component_sizes()
+                );
+                new_map
+            }
+        }
+    }
+
+    /// Re-generates an area of a map - any set of positions, e.g. selected with a `RegionShape` or `flood_fill()` -
+    /// while keeping the rest of the map as it is. The area's tiles are reset to undecided and conditioned on all of
+    /// their neighbors outside of it, as found by the map's adjacency, so that the new tiles fit in with the old ones.
+    ///
+    ///  **Arguments**:
+    /// * `map` - the map to edit; positions that are not on it are skipped.
+    /// * `positions` - the area to re-generate.
+    ///
+    /// **Returns**: the edited map. It shares its tiles with the original one, which therefore gets edited as well.
+    ///
+    pub fn regenerate_region<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, I: IntoIterator<Item = MP>>(&self, map: Map2D<AG, DK, MP>, positions: I) -> Map2D<AG, DK, MP> {
+        let positions: Vec<MP> = positions.into_iter().collect();
+        let edit = begin_regeneration(&map, &positions);
+
+        let mut job = self.regeneration_job(map, positions);
+        job.queue_and_assign();
+        let mut map = job.into_map();
+        map.commit_edit(edit);
+        map
+    }
+
+    /// Re-generates the rectangle between two corners (inclusive) of a copy of the map; a shim for the old
+    /// `regenerate_region()`, which took corners instead of any set of positions.
+    ///
+    /// **Returns**: the edited map. It shares its tiles with the original one, which therefore gets edited as well.
+    ///
+    #[deprecated(note = "use `regenerate_region()` with the positions selected by a `RegionShape::Rect`")]
+    pub fn regenerate_rect<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, src_map: &Map2D<AG, DK, MP>, start_pos: [MP::Key; 2], end_pos: [MP::Key; 2]) -> Arc<RwLock<Map2D<AG, DK, MP>>> {
+        let corner = |pos: [MP::Key; 2]| pos.map(|dim| dim.to_i64().unwrap_or(0));
+        let rect = RegionShape::Rect { min: corner(start_pos), max: corner(end_pos) };
+        let positions = rect.select(src_map).unwrap_or_default();
+        Arc::new(RwLock::new(self.regenerate_region(src_map.to_owned(), positions)))
+    }
+
+    /// Like `regenerate_region()`, but leaves the area undecided instead of collapsing it again.
+    pub fn erase_region<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, I: IntoIterator<Item = MP>>(&self, map: Map2D<AG, DK, MP>, positions: I) -> Map2D<AG, DK, MP> {
+        let positions: Vec<MP> = positions.into_iter().collect();
+        let edit = begin_regeneration(&map, &positions);
+
+        let mut map = self.regeneration_job(map, positions).into_map();
+        map.commit_edit(edit);
+        map
+    }
+
+    /// Merges two maps of the same extent into a new one, with the tiles inside the mask taken from `inside`
+    /// and the rest from `outside`. The seam - the tiles up to `seam_width` steps away from the edge of the mask,
+    /// on either side of it - is then re-generated, so that the two parts fit together.
+    ///
+    /// **Returns**: the merged map, with its own tiles and an empty history; neither of the maps is changed.
+    ///
+    pub fn merge<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, inside: &Map2D<AG, DK, MP>, outside: &Map2D<AG, DK, MP>, mask: &HashSet<MP>, seam_width: u32) -> Map2D<AG, DK, MP> {
+        let merged = Map2D::from_tiles(outside.tiles.iter().map(|tile| {
+            let reader = tile.read().unwrap();
+            let source = inside.get(reader.position).filter(|_| mask.contains(&reader.position));
+            let state = match source {
+                // the maps may share tiles, e.g. if one is a submap of the other
+                Some(source) if !Arc::ptr_eq(source, tile) => source.read().unwrap().state.to_owned(),
+                _ => reader.state.to_owned()
+            };
+            Map2DNode::with_state(reader.position, state)
+        }));
+
+        let seam = seam_around(&merged, mask, seam_width);
+        let mut merged = self.regenerate_region(merged, seam);
+        merged.history.clear();
+        merged
+    }
+
+    /// A job to collapse the map with the area reset to undecided and conditioned on its neighbors outside of it.
+    pub(crate) fn regeneration_job<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, I: IntoIterator<Item = MP>>(&self, map: Map2D<AG, DK, MP>, positions: I) -> MapColoringJob<AG, DK, MP> {
+        self.regeneration_job_over(map, positions, &self.regeneration_keys())
+    }
+
+    /// Like `regeneration_job()`, with the area's tiles only allowed to take the specified keys.
+    fn regeneration_job_over<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, I: IntoIterator<Item = MP>>(&self, map: Map2D<AG, DK, MP>, positions: I, keys: &HashSet<DK>) -> MapColoringJob<AG, DK, MP> {
+        let mut map = map;
+        let region: HashSet<MP> = positions.into_iter().filter(|pos| map.position_index.contains_key(pos)).collect();
+
+        let region_tiles: Vec<_> = region.iter().filter_map(|pos| map.get(pos).cloned()).collect();
+        map.unassign_tiles(region_tiles.iter(), MultinomialDistribution::uniform_over(keys.iter().copied()));
+
+        self.coloring_job(map).with_conditioned_tiles(region)
+    }
+
+    /// All the keys that the layout rules and the regional rules have rules for.
+    fn regeneration_keys(&self) -> HashSet<DK> {
+        self.layout_rules.transition_rules.keys().chain(
+            self.regions.iter().flat_map(|region| region.rules.transition_rules.keys())
+        ).copied().collect()
+    }
+
     /// Generates an empty (i.e. 'un-collapsed') map.
     /// This can be passed to a generate/infill function to collapse the map to a generated state.
     ///
     ///  **Arguments**:
     /// * `map_size` - optional u32, size of the map to generate (currently only square maps supported,
-    /// so this corresponds to both X & Y axes. If None, falls back to the Ruleset value.
+    ///   so this corresponds to both X & Y axes. If None, falls back to the Ruleset value.
     /// * `map_builder` - optional function that takes a mapsize and returns a map.
-    /// If None, uses the default method (allocate mapsize ** 2 unassigned tiles with uniform probability.)
+    ///   If None, uses the default method (allocate mapsize ** 2 unassigned tiles with uniform probability.)
     ///
     ///  **Returns**: a new Map2D.
     ///
-    pub fn build_unassigned_map_with_size<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, map_size: Option<u32>, map_builder: Option<Box<dyn Fn(u32) -> Map2D<AG, DK, MP>>>) -> Map2D<AG, DK, MP> where
+    pub fn build_unassigned_map_with_size<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, map_size: Option<u32>, map_builder: Option<MapBuilderFn<AG, DK, MP>>) -> Map2D<AG, DK, MP> where
         MP::Key: PositionKey + NumCast,
     {
         let raw_map_size = map_size.unwrap_or(self.map_size);
@@ -203,74 +733,85 @@ impl<DK: DistributionKey> GeneratorRuleset<DK> {
     }
 
     /// Creates a filled (i.e. 'collapsed') map,
-    /// either from a partially un-collapsed map or entirely from scratch,
-    /// and renders it using a provided MapVisualizer.
+    /// either from a partially un-collapsed map or entirely from scratch.
     ///
     ///  **Arguments**:
     /// * init_map - optional; a pre-initialized map to fill out.
-    /// If None, will create a new map using the provided Ruleset's rules
-    /// * visualizer - MapVisualizer interface to use to render the generated map.
+    ///   If None, will create a new map using the provided Ruleset's rules
     ///
-    /// **Returns**: nothing (i.e. Unit); a render will be created as a side-effect.
+    /// **Returns**: the collapsed map; nothing gets rendered (see `render()` for that).
     ///
-    pub fn generate_with_visualizer<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, init_map: Option<Map2D<AG, DK, MP>>, visualiser: V)
-        where MP::Key: PositionKey + NumCast
+    pub fn generate_map<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, init_map: Option<Map2D<AG, DK, MP>>) -> Map2D<AG, DK, MP>
+        where MP::Key: PositionKey + NumCast + Into<u32>
     {
-        let assignment_rules = self.layout_rules.to_owned();
         let gen_map = init_map.unwrap_or_else(
-            || self.build_unassigned_map::<AG, MP, V>()
+            || self.build_unassigned_map::<AG, MP, RilPixelVisualizer<DK>>()
         );
 
-        let mut job = MapColoringJob::new_with_queue(assignment_rules, gen_map);
-        let map_result = job.queue_and_assign();
-        let map_reader = map_result.read().unwrap();
+        let map_result = self.collapse(gen_map);
+        self.report_map(&map_result);
+        map_result
+    }
 
-        visualiser.visualise(&map_reader, None);
+    /// Creates a filled (i.e. 'collapsed') map from scratch.
+    ///
+    /// Effectively sugar over `self.generate_map(None)`
+    ///
+    /// **Arguments** - none
+    ///
+    /// **Returns**: the collapsed map.
+    ///
+    pub fn generate<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self) -> Map2D<AG, DK, MP>
+        where MP::Key: PositionKey + NumCast + Into<u32>
+    {
+        self.generate_map::<AG, MP>(None)
     }
 
     /// Creates a filled (i.e. 'collapsed') map,
     /// either from a partially un-collapsed map or entirely from scratch,
-    /// and renders it using the *default* MapVisualizer.
-    ///
-    /// Effectively sugar over `self.generate_with_visualizer(init_map, <default visualizer>)`
+    /// and renders it to `map.<ext>` using a provided MapVisualizer.
     ///
     ///  **Arguments**:
     /// * init_map - optional; a pre-initialized map to fill out.
-    /// If None, will create a new map using the provided Ruleset's rules
+    ///   If None, will create a new map using the provided Ruleset's rules
+    /// * visualizer - MapVisualizer interface to use to render the generated map.
     ///
-    /// **Returns**: nothing (i.e. Unit); a render will be created as a side-effect.
+    /// **Returns**: the collapsed map.
     ///
-    pub fn generate_map<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, init_map: Option<Map2D<AG, DK, MP>>)
+    pub fn generate_with_visualizer<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, init_map: Option<Map2D<AG, DK, MP>>, visualiser: V) -> Map2D<AG, DK, MP>
+        where MP::Key: PositionKey + NumCast + Into<u32>, V::Output: EncodableOutput
+    {
+        let map_result = self.generate_map(init_map);
+        save_render(&visualiser, &map_result, "map");
+        map_result
+    }
+
+    /// Renders a map with the *default* MapVisualizer and writes it to a sink.
+    pub fn render<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, S: MapSink>(&self, map: &Map2D<AG, DK, MP>, sink: &S) -> Result<(), ril::Error>
         where MP::Key: PositionKey + NumCast + Into<u32>
     {
         let visualizer = RilPixelVisualizer::from(self.coloring_rules.to_owned());
-        self.generate_with_visualizer::<AG, MP, RilPixelVisualizer<DK>>(init_map, visualizer)
+        render_to(&visualizer, map, sink)
     }
 
-    /// Creates a filled (i.e. 'collapsed') map from scratch,
-    /// and renders it using the *default* MapVisualizer.
-    ///
-    /// Effectively sugar over `self.generate_map(None, <default visualizer>)`
+    /// Creates a filled (i.e. 'collapsed') map from scratch and renders it to `map.png`
+    /// using the *default* MapVisualizer - i.e. what the app does.
     ///
-    /// **Arguments** - none
+    /// **Returns**: the collapsed map.
     ///
-    /// **Returns**: nothing (i.e. Unit); a render will be created as a side-effect.
-    ///
-    pub fn generate<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self)
+    pub fn generate_and_render<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self) -> Map2D<AG, DK, MP>
         where MP::Key: PositionKey + NumCast + Into<u32>
     {
-        self.generate_map::<AG, MP>(None)
+        let visualizer = RilPixelVisualizer::from(self.coloring_rules.to_owned());
+        self.generate_with_visualizer::<AG, MP, RilPixelVisualizer<DK>>(None, visualizer)
     }
 }
 
 
 impl<DK: DistributionKey + Send + Sync> GeneratorRuleset<DK> {
-    pub fn default_map_builder_par<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, map_size: u32) -> Map2D<AG, DK, MP>
+    pub fn default_map_builder_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync, V: MapVisualizer<AG, DK, MP>>(&self, map_size: u32) -> Map2D<AG, DK, MP>
         where
-        AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
-        MP: MapPosition<2> + Send + Sync,
         MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync,
-        V: MapVisualizer<AG, DK, MP>,
     {
         let raw_map_size = map_size;
         let map_usize = <usize as NumCast>::from(raw_map_size).unwrap();
@@ -311,17 +852,14 @@ impl<DK: DistributionKey + Send + Sync> GeneratorRuleset<DK> {
     ///
     ///  **Arguments**:
     /// * `map_size` - optional u32, size of the map to generate (currently only square maps supported,
-    /// so this corresponds to both X & Y axes. If None, falls back to the Ruleset value.
+    ///   so this corresponds to both X & Y axes. If None, falls back to the Ruleset value.
     /// * `map_builder` - optional function that takes a mapsize and returns a map.
-    /// If None, uses the default method (allocate mapsize ** 2 unassigned tiles with uniform probability.)
+    ///   If None, uses the default method (allocate mapsize ** 2 unassigned tiles with uniform probability.)
     ///
     ///  **Returns**: a new Map2D.
     ///
-    pub fn build_unassigned_map_with_size_par<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, map_size: Option<u32>, map_builder: Option<Box<dyn Fn(u32) -> Map2D<AG, DK, MP>>>) -> Map2D<AG, DK, MP> where
-        AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
-        MP: MapPosition<2> + Send + Sync,
+    pub fn build_unassigned_map_with_size_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync, V: MapVisualizer<AG, DK, MP>>(&self, map_size: Option<u32>, map_builder: Option<MapBuilderFn<AG, DK, MP>>) -> Map2D<AG, DK, MP> where
         MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync,
-        V: MapVisualizer<AG, DK, MP>,
     {
         let raw_map_size = map_size.unwrap_or(self.map_size);
         match map_builder {
@@ -337,241 +875,374 @@ impl<DK: DistributionKey + Send + Sync> GeneratorRuleset<DK> {
     ///
     /// **Returns**: a new Map2D.
     ///
-    pub fn build_unassigned_map_par<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self) -> Map2D<AG, DK, MP> where
-        AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
-        MP: MapPosition<2> + Send + Sync,
+    pub fn build_unassigned_map_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync, V: MapVisualizer<AG, DK, MP>>(&self) -> Map2D<AG, DK, MP> where
         MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync,
-        V: MapVisualizer<AG, DK, MP>,
     {
         self.build_unassigned_map_with_size_par::<AG, MP, V>(None, None)
     }
 
-    /// Creates a filled (i.e. 'collapsed') map,
+    /// Creates a filled (i.e. 'collapsed') map in parallel,
     /// either from a partially un-collapsed map or entirely from scratch,
-    /// and renders it using a provided MapVisualizer.
+    /// and renders it to `map.<ext>` using a provided MapVisualizer.
     ///
     ///  **Arguments**:
     /// * init_map - optional; a pre-initialized map to fill out.
-    /// If None, will create a new map using the provided Ruleset's rules
+    ///   If None, will create a new map using the provided Ruleset's rules
     /// * visualizer - MapVisualizer interface to use to render the generated map.
     ///
-    /// **Returns**: nothing (i.e. Unit); a render will be created as a side-effect.
+    /// **Returns**: the collapsed map.
     ///
-    pub fn generate_with_visualizer_par<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, init_map: Option<Map2D<AG, DK, MP>>, visualiser: V) where
-        AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
-        MP: MapPosition<2> + Send + Sync,
+    pub fn generate_with_visualizer_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync, V: MapVisualizer<AG, DK, MP>>(&self, init_map: Option<Map2D<AG, DK, MP>>, visualiser: V) -> Map2D<AG, DK, MP> where
         MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync,
-        V: MapVisualizer<AG, DK, MP>,
+        V::Output: EncodableOutput
     {
-        let assignment_rules = self.layout_rules.to_owned();
-        let gen_map = init_map.unwrap_or_else(
-            || self.build_unassigned_map_par::<AG, MP, V>()
-        );
-
-        let mut job = MapColoringJob::new_with_queue(assignment_rules, gen_map);
-        let map_result = job.queue_and_assign();
-        let map_reader = map_result.read().unwrap();
-
-        visualiser.visualise(&map_reader, None);
+        let map_result = self.generate_map_par(init_map);
+        save_render(&visualiser, &map_result, "map");
+        map_result
     }
 
-    pub fn regenerate_region<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>
-    >(
-        &self,
-        src_map: &Map2D<AG, DK, MP>,
-        start_pos: [MP::Key; 2],
-        end_pos: [MP::Key; 2]
-    ) -> Arc<RwLock<Map2D<AG, DK, MP>>> where
-        AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
-        MP: MapPosition<2> + Send + Sync,
-        MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync
+    /// Showcase of Modifying In Blocks approach - generates a map, then edits
+    /// the middle of it by resetting it to an unassigned state and filling it in again.
+    /// The approach here is consistent (i.e. doesn't violate constraints), but may exhibit
+    /// directional artifacts on the edited area's edge (usually, unnaturally straight lines).
+    ///
+    /// Both maps get rendered using the provided MapVisualizer, to `map.<ext>` and `editmap.<ext>` respectively.
+    ///
+    /// **Returns**: the edited map. It shares its tiles with the original one, which therefore gets edited as well.
+    ///
+    pub fn generate_with_visualizer_par_mib<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync, V: MapVisualizer<AG, DK, MP>>(&self, init_map: Option<Map2D<AG, DK, MP>>, visualiser: V) -> Map2D<AG, DK, MP> where
+        MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync,
+        V::Output: EncodableOutput
     {
-        let mut newmap = src_map.to_owned();
-
-        let min_pos_dimval = start_pos.first().unwrap().to_owned();
-        let max_pos_dimval = end_pos.first().unwrap().to_owned();
-
-        let tilerange = range_inclusive(
-            min_pos_dimval,
-            max_pos_dimval
-        );
-        let fuserange = tilerange.to_owned().cartesian_product(tilerange);
-
-        let targ_tiles = fuserange.filter_map(
-            |(x, y)| {
-                let dims = [x, y];
-                let map_pos = MP::from_dims(dims);
-                let map_tile = src_map.get(map_pos);
-                map_tile
-            }
-        );
-
-        let new_assignment_rules = self.layout_rules.to_owned();
-        let trans_rules = new_assignment_rules.transition_rules.to_owned();
-        let map_keys = trans_rules.into_keys();
-
-        newmap.unassign_tiles(
-            targ_tiles,
-            MultinomialDistribution::uniform_over(
-                map_keys
-            )
-        );
-
-        let unity: MP::Key = num::NumCast::from(1).unwrap();
+        let map_result = self.generate_map_par(init_map);
+        save_render(&visualiser, &map_result, "map");
 
-        let edge_tiles_x = range_inclusive(min_pos_dimval, max_pos_dimval).filter_map(
-            |x| {
-                let dims = [x, min_pos_dimval];
-                let map_pos = MP::from_dims(dims);
-                let map_tile = src_map.get(map_pos);
-                map_tile
-            }
-        ).map(
-            |tile| {
-                let tile_reader = tile.read().unwrap();
-                let pos = tile_reader.position.get_dims();
-                let adj_pos = MP::from_dims([pos[0], pos[1] - unity]);
-                (tile, adj_pos)
-            }
-        ).chain(
-        range_inclusive(min_pos_dimval, max_pos_dimval).filter_map(
-            |x| {
-                let dims = [x, max_pos_dimval];
-                let map_pos = MP::from_dims(dims);
-                let map_tile = src_map.get(map_pos);
-                map_tile
-            }).map(|tile| {
-                let tile_reader = tile.read().unwrap();
-                let pos = tile_reader.position.get_dims();
-                let adj_pos = MP::from_dims([pos[0], pos[1] + unity]);
-                (tile, adj_pos)
-            }
-        ));
-
-        let edge_tiles_y = range_inclusive(min_pos_dimval, max_pos_dimval).filter_map(
-            |y| {
-                let dims = [min_pos_dimval, y];
-                let map_pos = MP::from_dims(dims);
-                let map_tile = src_map.get(map_pos);
-                map_tile
-            }
-        ).map(
-            |tile| {
-                let tile_reader = tile.read().unwrap();
-                let pos = tile_reader.position.get_dims();
-                let adj_pos = MP::from_dims([pos[0] - unity, pos[1]]);
-                (tile, adj_pos)
-            }
-        ).chain(
-        range_inclusive(min_pos_dimval, max_pos_dimval).filter_map(
-            |y| {
-                let dims = [max_pos_dimval, y];
-                let map_pos = MP::from_dims(dims);
-                let map_tile = src_map.get(map_pos);
-                map_tile
-            }).map(|tile| {
-                let tile_reader = tile.read().unwrap();
-                let pos = tile_reader.position.get_dims();
-                let adj_pos = MP::from_dims([pos[0] + unity, pos[1]]);
-                (tile, adj_pos)
-            }
-        ));
+        let [max_x, max_y] = map_result.max_pos.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
+        let middle = RegionShape::Rect { min: [max_x / 4, max_y / 4], max: [max_x * 3 / 4, max_y * 3 / 4] };
+        let positions = middle.select(&map_result).unwrap_or_default();
 
-        edge_tiles_x
-            .chain(edge_tiles_y)
-            .for_each(|(tile, adj_pos)| {
-                let mut tile_writer = tile.write().unwrap();
-                let raw_assigned_neighbor = src_map.get(adj_pos);
-                
-                if let Some(assigned_neighbor) = raw_assigned_neighbor {
-                    let neigh_reader = assigned_neighbor.read().unwrap();
-                    let neigh_dist = match &neigh_reader.state {
-                        MapNodeState::Undecided(distribution) => distribution.to_owned(),
-                        MapNodeState::Finalized(assignment) => MultinomialDistribution::uniform_over(
-                            Some(assignment.to_owned())
-                        )
-                    };
-                    let neighbor_state = &tile_writer.state.to_owned();
-                    let my_dist = match &neighbor_state {
-                        MapNodeState::Undecided(distribution) => distribution,
-                        MapNodeState::Finalized(_) => panic!("Tile should have been unassigned!")
-                    };
-                    let updated_dist = neigh_dist.joint_probability(my_dist);
-                    tile_writer.state = MapNodeState::Undecided(updated_dist);
-                }
-        });
+        let newmap = self.regenerate_region(map_result, positions);
+        save_render(&visualiser, &newmap, "editmap");
+        newmap
+    }
 
-        let mut coloring = MapColoringJob::new_with_queue(new_assignment_rules, newmap);
-        let newmap_result = coloring.queue_and_assign();
+    /// Collapses a map the way the app does: with the Ruleset's pattern model if it has one, and otherwise
+    /// chunk by chunk in parallel, followed by the connectivity constraint (if any).
+    ///
+    /// The seed, if any, stands in for the Ruleset's own, so that a map collapsed with a seed is the same
+    /// as one collapsed by a Ruleset with that seed.
+    ///
+    /// **Returns**: the collapsed map.
+    ///
+    pub fn collapse_seeded<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync>(&self, map: Map2D<AG, DK, MP>, seed: Option<u64>) -> Map2D<AG, DK, MP> {
+        let seed = seed.or(self.seed);
+        if let Some(collapsed) = self.collapse_patterns(&map, seed) {
+            return collapsed
+        }
 
-        newmap_result.to_owned()
+        let mut job = self.coloring_job(map);
+        if let Some(seed) = seed {
+            job = job.with_seed(seed);
+        }
+        job.par_queue_and_assign();
+        self.enforce_connectivity_seeded(job.into_map(), seed)
     }
 
-    /// Showcase of Modifying In Blocks approach - generates a map, then edits
-    /// the top-left quadrant by restting it to an unassigned state and filling it in again.
-    /// The approach here is consistent (i.e. doesn't violate constraints), but may exhibit
-    /// directional artifacts on the quadrant edge (usually, unnaturally straight lines).
-    pub fn generate_with_visualizer_par_mib<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>, V: MapVisualizer<AG, DK, MP>>(&self, init_map: Option<Map2D<AG, DK, MP>>, visualiser: V) where
+    /// Generates a batch of maps from scratch in parallel, one per rayon task, and keeps the best-scoring ones.
+    ///
+    /// Each run gets its own seed, drawn from the Ruleset's seed if it has one. Runs are collapsed with
+    /// `collapse_seeded()`, so `collapse_seeded(map, Some(seed))` on an empty map reproduces the run with that seed;
+    /// so does the app, given the seed as the Ruleset's seed and with `best_of` removed.
+    ///
+    ///  **Arguments**:
+    /// * `batch` - how many maps to generate and how many of them to keep.
+    /// * `fitness` - scores a generated map; higher is better.
+    ///
+    /// **Returns**: the seeds and scores of all runs and the best maps, best first.
+    ///
+    pub fn generate_best_of<AG, MP, F>(&self, batch: BestOf, fitness: F) -> BatchResult<AG, DK, MP>
+    where
         AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
         MP: MapPosition<2> + Send + Sync,
         MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync,
-        V: MapVisualizer<AG, DK, MP>,
-        V::Args: From<&'static str>
+        F: Fn(&Map2D<AG, DK, MP>) -> f64 + Sync
     {
-        let assignment_rules = self.layout_rules.to_owned();
-        let gen_map = init_map.unwrap_or_else(
-            || self.build_unassigned_map_par::<AG, MP, V>()
-        );
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy()
+        };
+        let seeds: Vec<u64> = (0..batch.runs).map(|_| rng.gen()).collect();
 
-        let mut job = MapColoringJob::new_with_queue(assignment_rules, gen_map.to_owned());
-        let map_result = job.queue_and_assign();
-        let map_reader = map_result.read().unwrap();
+        let runs = seeds.into_par_iter().map(|seed| {
+            let map = self.build_unassigned_map_par::<AG, MP, RilPixelVisualizer<DK>>();
+            let map = self.collapse_seeded(map, Some(seed));
+            ScoredMap { seed, score: fitness(&map), map }
+        }).collect();
 
-        visualiser.visualise(&map_reader, None);
+        BatchResult::from_runs(runs, batch.keep)
+    }
 
-        let min_pos = map_reader.max_pos.get_dims().map(|d| d.div(num::NumCast::from(4).unwrap()));
-        let max_pos = min_pos.map(|d| d.mul(num::NumCast::from(3).unwrap()));
+    /// Generates a batch of maps as specified by the Ruleset's `best_of` setting, scores them with its
+    /// fitness criteria and renders the best ones with the *default* MapVisualizer, reporting all the scores.
+    /// The best map goes to `map.png`, the runner-ups to `map_2.png`, `map_3.png` and so on.
+    ///
+    /// **Returns**: the seeds and scores of all runs and the best maps, best first.
+    ///
+    pub fn generate_best_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync>(&self, batch: BestOf) -> BatchResult<AG, DK, MP> where
+        MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync
+    {
+        let result = self.generate_best_of::<AG, MP, _>(batch, |map| self.fitness_score(map));
+        for (rank, (seed, score)) in result.scores.iter().enumerate() {
+            eprintln!("#{}: seed {} scored {:.4}", rank + 1, seed, score);
+        }
 
-        let newmap_result = self.regenerate_region::<AG, MP, V>(&gen_map, min_pos, max_pos);
-        let newmap_reader = newmap_result.read().unwrap();
-        visualiser.visualise(&newmap_reader, Some("editmap.png".into()));
+        let visualizer = RilPixelVisualizer::from(self.coloring_rules.to_owned());
+        for (rank, scored) in result.best.iter().enumerate() {
+            self.report_map(&scored.map);
+            let stem = match rank {
+                0 => "map".to_string(),
+                _ => format!("map_{}", rank + 1)
+            };
+            save_render(&visualizer, &scored.map, &stem);
+        }
+        result
     }
 
-    /// Creates a filled (i.e. 'collapsed') map,
-    /// either from a partially un-collapsed map or entirely from scratch,
-    /// and renders it using the *default* MapVisualizer.
-    ///
-    /// Effectively sugar over `self.generate_with_visualizer(init_map, <default visualizer>)`
+    /// Creates a filled (i.e. 'collapsed') map in parallel,
+    /// either from a partially un-collapsed map or entirely from scratch.
     ///
     ///  **Arguments**:
     /// * init_map - optional; a pre-initialized map to fill out.
-    /// If None, will create a new map using the provided Ruleset's rules
+    ///   If None, will create a new map using the provided Ruleset's rules
     ///
-    /// **Returns**: nothing (i.e. Unit); a render will be created as a side-effect.
+    /// **Returns**: the collapsed map; nothing gets rendered (see `render()` for that).
     ///
-    pub fn generate_map_par<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self, init_map: Option<Map2D<AG, DK, MP>>) where
-        AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
-        MP: MapPosition<2> + Send + Sync,
+    pub fn generate_map_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync>(&self, init_map: Option<Map2D<AG, DK, MP>>) -> Map2D<AG, DK, MP> where
         MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync
     {
-        let visualizer = RilPixelVisualizer::from(self.coloring_rules.to_owned());
-        self.generate_with_visualizer_par_mib::<AG, MP, RilPixelVisualizer<DK>>(init_map, visualizer)
+        let gen_map = init_map.unwrap_or_else(
+            || self.build_unassigned_map_par::<AG, MP, RilPixelVisualizer<DK>>()
+        );
+
+        let map_result = self.collapse_seeded(gen_map, None);
+        self.report_map(&map_result);
+        map_result
     }
 
-    /// Creates a filled (i.e. 'collapsed') map from scratch,
-    /// and renders it using the *default* MapVisualizer.
+    /// Creates a filled (i.e. 'collapsed') map from scratch, in parallel.
     ///
-    /// Effectively sugar over `self.generate_map(None, <default visualizer>)`
+    /// Effectively sugar over `self.generate_map_par(None)`
     ///
     /// **Arguments** - none
     ///
-    /// **Returns**: nothing (i.e. Unit); a render will be created as a side-effect.
+    /// **Returns**: the collapsed map.
     ///
-    pub fn generate_par<AG: AdjacencyGenerator<2, Input = MP>, MP: MapPosition<2>>(&self) where
-        AG: AdjacencyGenerator<2, Input = MP> + Send + Sync,
-        MP: MapPosition<2> + Send + Sync,
+    pub fn generate_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync>(&self) -> Map2D<AG, DK, MP> where
         MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync
     {
         self.generate_map_par::<AG, MP>(None)
     }
+
+    /// Creates a filled (i.e. 'collapsed') map from scratch in parallel and renders it to `map.png`
+    /// using the *default* MapVisualizer - i.e. what the app does.
+    /// If the Ruleset asks for the best of a batch, this is `self.generate_best_par()` instead.
+    ///
+    /// **Returns**: the collapsed map, or the best one of the batch (if any were kept).
+    ///
+    pub fn generate_and_render_par<AG: AdjacencyGenerator<2, Input = MP> + Send + Sync, MP: MapPosition<2> + Send + Sync>(&self) -> Option<Map2D<AG, DK, MP>> where
+        MP::Key: PositionKey + NumCast + Into<u32> + Send + Sync
+    {
+        match self.best_of {
+            Some(batch) => self.generate_best_par::<AG, MP>(batch).best.into_iter().next().map(|scored| scored.map),
+            None => {
+                let visualizer = RilPixelVisualizer::from(self.coloring_rules.to_owned());
+                Some(self.generate_with_visualizer_par::<AG, MP, RilPixelVisualizer<DK>>(None, visualizer))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::OctileAdjacencyGenerator;
+    use ril::Rgb;
+    use crate::diff::MapDiff;
+    use crate::map2dnode::MapNodeState;
+    use crate::finalized::FinalizedMap;
+    use crate::position2d::Position2D;
+    use crate::symmetry::SymmetryMode;
+    use crate::transform::{SymmetryClass, Transform};
+    use super::*;
+
+    type TestMap = Map2D<OctileAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+
+    #[test]
+    fn regenerated_regions_fit_in() {
+        // 1s and 3s may not touch; 2s go with anything
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (1, MultinomialDistribution::uniform_over([1, 2])),
+            (2, MultinomialDistribution::uniform_over([1, 2, 3])),
+            (3, MultinomialDistribution::uniform_over([2, 3])),
+        ]));
+        let ruleset = GeneratorRuleset::new(rules.to_owned(), HashMap::new(), Some(12), None).with_seed(Some(7));
+
+        let key_at = |x: u8| match x { 0..=5 => 1, 6 => 2, _ => 3 };
+        let map: TestMap = Map2D::from_tiles((0..12).flat_map(
+            |x| (0..12).map(move |y| Map2DNode::with_assignment(Position2D::new(x, y), key_at(x)))
+        ));
+        let region = RegionShape::Circle { center: [6, 6], radius: 3.5 }.select(&map).unwrap();
+
+        let map = ruleset.regenerate_region(map, region.iter().copied());
+        assert!(map.undecided_tiles.is_empty());
+        assert_eq!(MapStatistics::analyze(&map, &rules).violations, 0);
+
+        let outside_kept = map.position_index.iter().filter(|(pos, _)| !region.contains(pos)).all(
+            |(pos, tile)| matches!(tile.read().unwrap().state, MapNodeState::Finalized(key) if key == key_at(pos.x))
+        );
+        assert!(outside_kept);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn rects_are_regenerated_between_their_corners() {
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (1, MultinomialDistribution::uniform_over([1, 2])),
+            (2, MultinomialDistribution::uniform_over([1, 2])),
+        ]));
+        let ruleset = GeneratorRuleset::new(rules, HashMap::new(), Some(8), None).with_seed(Some(5));
+        let map: TestMap = Map2D::from_tiles((0..8).flat_map(
+            |x| (0..8).map(move |y| Map2DNode::with_assignment(Position2D::new(x, y), 1))
+        ));
+
+        let edited = ruleset.regenerate_rect(&map, [1, 2], [3, 6]);
+        let edited = edited.read().unwrap();
+        let changed: HashSet<Position2D<u8>> = edited.history.edits()[0].after.iter().map(|(pos, _)| *pos).collect();
+        let expected: HashSet<Position2D<u8>> = (1..=3).flat_map(|x| (2..=6).map(move |y| Position2D::new(x, y))).collect();
+        assert_eq!(changed, expected);
+        assert!(edited.undecided_tiles.is_empty());
+    }
+
+    #[test]
+    fn merged_maps_are_repaired_along_the_seam() {
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (1, MultinomialDistribution::uniform_over([1, 2])),
+            (2, MultinomialDistribution::uniform_over([1, 2, 3])),
+            (3, MultinomialDistribution::uniform_over([2, 3])),
+        ]));
+        let ruleset = GeneratorRuleset::new(rules.to_owned(), HashMap::new(), Some(8), None).with_seed(Some(3));
+        let filled = |key: u8| -> TestMap { Map2D::from_tiles((0..8).flat_map(
+            |x| (0..8).map(move |y| Map2DNode::with_assignment(Position2D::new(x, y), key))
+        )) };
+        let (land, sea) = (filled(1), filled(3));
+
+        let mask = RegionShape::Rect { min: [0, 0], max: [3, 7] }.select(&land).unwrap();
+        let merged = ruleset.merge(&land, &sea, &mask, 1);
+        assert_eq!(MapStatistics::analyze(&merged, &rules).violations, 0);
+
+        // away from the seam, the tiles come from their own side
+        let diff = MapDiff::between(&sea, &merged);
+        assert!(diff.changed_positions().contains(&Position2D::new(0, 0)));
+        assert!(!diff.changed_positions().contains(&Position2D::new(7, 7)));
+        assert!(diff.changed_share() >= 0.375 && diff.changed_share() <= 0.75);
+
+        // the inputs are left alone
+        assert!(MapDiff::between(&sea, &filled(3)).is_empty());
+        let image = ruleset.diff_visualizer().render(&merged, &diff);
+        assert_eq!((image.width(), image.height()), (8, 8));
+        assert_eq!(image.pixel(0, 0), &Rgb::new(255, 0, 64));
+    }
+
+    #[test]
+    fn best_of_runs_are_reproducible_from_their_seed() {
+        let ruleset = |seed| {
+            let rules = MapColoringAssigner::with_rules(HashMap::from([
+                (1, MultinomialDistribution::uniform_over([1, 2])),
+                (2, MultinomialDistribution::uniform_over([1, 2, 3])),
+                (3, MultinomialDistribution::uniform_over([2, 3])),
+            ]));
+            let colors = HashMap::from([(1, MapColor::Rgb(0, 0, 255)), (2, MapColor::Rgb(0, 255, 0)), (3, MapColor::Rgb(128, 128, 128))]);
+            GeneratorRuleset::new(rules, colors, Some(16), None)
+                .with_seed(Some(seed))
+                .with_chunk_size(Some(4))
+                .with_connectivity(Some(ConnectivityConstraint::new([1])))
+        };
+
+        let batch = ruleset(11);
+        let result = batch.generate_best_of::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>, _>(BestOf::new(3, 1), |map| batch.fitness_score(map));
+        let best = &result.best[0];
+
+        // the same map the app would generate with the reported seed
+        let rerun = ruleset(best.seed).generate_par::<OctileAdjacencyGenerator<Position2D<u8>>, Position2D<u8>>();
+        assert!(MapDiff::between(&best.map, &rerun).is_empty());
+    }
+
+    #[test]
+    fn symmetric_maps_mirror_their_counterparts() {
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (1, MultinomialDistribution::uniform_over([1, 2])),
+            (2, MultinomialDistribution::uniform_over([1, 2, 3])),
+            (3, MultinomialDistribution::uniform_over([2, 3])),
+        ]));
+        // a quarter turn swaps 1s and 3s around
+        let symmetry = Symmetry::new(SymmetryMode::Rotate4).with_key_mapping(HashMap::from([(1, 3), (3, 1)]));
+        let ruleset = GeneratorRuleset::new(rules, HashMap::new(), Some(8), None)
+            .with_seed(Some(5))
+            .with_symmetry(Some(symmetry));
+
+        let map: TestMap = ruleset.collapse(Map2D::from_tiles((0..8).flat_map(
+            |x| (0..8).map(move |y| Map2DNode::with_possibilities(Position2D::new(x, y), MultinomialDistribution::uniform_over([1, 2, 3])))
+        )));
+        assert_eq!(ruleset.statistics(&map).violations, 0);
+        let grid = FinalizedMap::from_map(&map).unwrap();
+        let swapped = |key: u8| match key { 1 => 3, 3 => 1, key => key };
+        for (pos, key) in grid.iter() {
+            assert_eq!(grid[Position2D::new(7 - pos.y, pos.x)], swapped(*key));
+        }
+    }
+
+    #[test]
+    fn connectivity_keeps_maps_symmetric() {
+        let anything = MultinomialDistribution::uniform_over([1, 2]);
+        let rules = MapColoringAssigner::with_rules(HashMap::from([(1, anything.to_owned()), (2, anything)]));
+        let ruleset = GeneratorRuleset::new(rules, HashMap::new(), Some(10), None)
+            .with_seed(Some(8))
+            .with_symmetry(Some(Symmetry::new(SymmetryMode::MirrorX)))
+            .with_connectivity(Some(ConnectivityConstraint { max_attempts: 1, ..ConnectivityConstraint::new([1]) }));
+
+        // two mirrored pockets of floor, neither of which is bigger than the other
+        let map: TestMap = Map2D::from_tiles((0..10).flat_map(|x| (0..10).map(move |y| {
+            let key = match (x, y) { (1, 1) | (8, 1) => 1, _ => 2 };
+            Map2DNode::with_assignment(Position2D::new(x, y), key)
+        })));
+        let map = ruleset.enforce_connectivity(map);
+
+        let grid = FinalizedMap::from_map(&map).unwrap();
+        for (pos, key) in grid.iter() {
+            assert_eq!(grid[Position2D::new(9 - pos.x, pos.y)], *key);
+        }
+    }
+
+    #[test]
+    fn tile_groups_expand_the_ruleset() {
+        // corners joining the top and right edges, and a horizontal and vertical pipe
+        let (corner, pipe) = (vec![10, 11, 12, 13], vec![20, 21]);
+        let rules = MapColoringAssigner::with_rules(HashMap::from([
+            (10, MultinomialDistribution::uniform_over([20, 21, 12])),
+            (20, MultinomialDistribution::uniform_over([20, 10, 11])),
+        ]));
+        let ruleset = GeneratorRuleset::new(rules, HashMap::from([(10, MapColor::Rgb(0, 0, 0)), (20, MapColor::Rgb(9, 9, 9))]), Some(6), None)
+            .with_seed(Some(2))
+            .with_tile_groups(vec![
+                TileGroup { symmetry: SymmetryClass::L, variants: corner.to_owned() },
+                TileGroup { symmetry: SymmetryClass::I, variants: pipe.to_owned() },
+            ]);
+
+        let keys: HashSet<u8> = ruleset.tile_keys().collect();
+        assert_eq!(keys, corner.iter().chain(pipe.iter()).copied().collect());
+        assert_eq!(ruleset.layout_rules().transition_rules[&13].keys(), &[11, 20, 21]);
+        assert_eq!(ruleset.tile_transforms().sprite_of(21), Some((20, Transform { quarter_turns: 1, reflected: false })));
+
+        let map: TestMap = ruleset.generate_map(None);
+        assert_eq!(MapStatistics::analyze(&map, ruleset.layout_rules()).violations, 0);
+    }
 }
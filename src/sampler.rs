@@ -3,7 +3,7 @@ use std::collections::{HashMap, HashSet};
 use std::default::Default;
 use std::fmt::Debug;
 use std::hash::Hash;
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, OnceLock};
 // use std::rc::{Rc, Weak};
 use rand::distributions::{Standard};
 use rand::prelude::*;
@@ -23,8 +23,8 @@ use serde::{Serialize, Deserialize};
 //     }
 // }
 
-pub trait  DistributionKey: Copy + Eq + Hash + Debug + Default {}
-impl<T: Copy + Eq + Hash + Debug + Default> DistributionKey for T {}
+pub trait  DistributionKey: Copy + Eq + Ord + Hash + Debug + Default {}
+impl<T: Copy + Eq + Ord + Hash + Debug + Default> DistributionKey for T {}
 
 // pub trait Sample<K: DistributionKey> {
 //     fn sample(&self) -> Option<K>;
@@ -37,50 +37,82 @@ impl<T: Copy + Eq + Hash + Debug + Default> DistributionKey for T {}
 //     }
 // }
 
+/// A set of keys with non-negative weights; the odds of sampling a key are proportional to its weight.
+///
+/// Keys are kept sorted, so for a given RNG state sampling always produces the same result.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "RawMultinomialDistribution<K>")]
+#[serde(bound(deserialize = "K: DistributionKey + Deserialize<'de>"))]
 pub struct MultinomialDistribution<K: DistributionKey> {
     weights: HashMap<Arc<K>, f32>,
-    keys: Vec<Weak<K>>
+    keys: Vec<K>,
+    /// Weights of the keys, in the same order, with non-positive weights clamped to zero.
+    #[serde(skip)]
+    key_weights: Vec<f32>,
+    #[serde(skip)]
+    total: f32,
+    #[serde(skip)]
+    alias_table: OnceLock<AliasTable<K>>
+}
+
+/// The serialized form of a MultinomialDistribution; everything else is derived from the weights.
+#[derive(Deserialize)]
+struct RawMultinomialDistribution<K: DistributionKey> {
+    weights: HashMap<K, f32>
+}
+
+impl<K: DistributionKey> From<RawMultinomialDistribution<K>> for MultinomialDistribution<K> {
+    fn from(value: RawMultinomialDistribution<K>) -> Self {
+        Self::from(value.weights)
+    }
 }
 
 impl<K: DistributionKey> From<HashMap<K, f32>> for MultinomialDistribution<K> {
     fn from(value: HashMap<K, f32>) -> Self {
-        let mut weightmap = HashMap::with_capacity(value.len());
-        let mut weightkeys = Vec::with_capacity(value.len());
-        for (key, val) in value {
-            let key_ref = Arc::new(key);
-            weightmap.insert(key_ref.to_owned(), val);
-            weightkeys.push(Arc::downgrade(&key_ref));
-        }
-        Self {
-            weights: weightmap,
-            keys: weightkeys
-        }
+        let weightmap: HashMap<Arc<K>, f32> = value.into_iter().map(
+            |(key, val)| (Arc::new(key), val)
+        ).collect();
+        Self::from(weightmap)
     }
 }
 
 impl<K: DistributionKey> From<HashMap<Arc<K>, f32>> for MultinomialDistribution<K> {
     fn from(value: HashMap<Arc<K>, f32>) -> Self {
-        let mut weightmap = HashMap::with_capacity(value.len());
-        let mut weightkeys = Vec::with_capacity(value.len());
-        for (key_ref, val) in value {
-            weightmap.insert(key_ref.to_owned(), val);
-            weightkeys.push(Arc::downgrade(&key_ref));
-        }
+        let mut weightkeys: Vec<K> = value.keys().map(|key_ref| **key_ref).collect();
+        weightkeys.sort();
+        let key_weights: Vec<f32> = weightkeys.iter().map(|key| value[key].max(0.)).collect();
+        let total = key_weights.iter().sum();
+
         Self {
-            weights: weightmap,
-            keys: weightkeys
+            weights: value,
+            keys: weightkeys,
+            key_weights,
+            total,
+            alias_table: OnceLock::new()
         }
     }
 }
 
 impl<K: DistributionKey + Copy> MultinomialDistribution<K> {
     pub fn total_weights(&self) -> f32 {
-        let mut total = 0.0;
-        for weight in self.weights.values() {
-            total += weight;
-        }
-        total
+        self.total
+    }
+
+    /// The keys of the distribution, in sorted order.
+    pub fn keys(&self) -> &[K] {
+        &self.keys
+    }
+
+    pub fn weight(&self, key: &K) -> f32 {
+        self.weights.get(key).copied().unwrap_or(0.).max(0.)
+    }
+
+    /// A precomputed table for sampling the distribution in constant time.
+    ///
+    /// The table is built on first use and kept for the lifetime of the distribution;
+    /// it only pays off if the same distribution gets sampled many times.
+    pub fn alias_table(&self) -> &AliasTable<K> {
+        self.alias_table.get_or_init(|| AliasTable::new(self))
     }
 
     pub fn uniform_over<I: IntoIterator<Item=K>>(keys: I) -> Self {
@@ -105,10 +137,46 @@ impl<K: DistributionKey + Copy> MultinomialDistribution<K> {
         normalized_map
     }
 
+    /// Shannon entropy of the distribution, in bits.
     pub fn entropy(&self) -> f32 {
-        self.normalized_weights().values().map(
-            |weight| weight * weight.log2()
-        ).sum()
+        if self.total_weights() <= 0. { return 0. }
+
+        -self.normalized_weights().values().filter(|p| **p > 0.).map(
+            |p| p * p.log2()
+        ).sum::<f32>()
+    }
+
+    /// Number of keys that can still be sampled (i.e. have a positive weight).
+    pub fn remaining_options(&self) -> usize {
+        self.weights.values().filter(|weight| **weight > 0.).count()
+    }
+
+    /// Rényi entropy of order `alpha`, in bits.
+    ///
+    /// Lower orders treat all remaining options more equally (`alpha = 0` is the log of the option count),
+    /// higher ones are dominated by the most likely options. Order 1 is the Shannon entropy.
+    pub fn renyi_entropy(&self, alpha: f32) -> f32 {
+        if (alpha - 1.).abs() < f32::EPSILON { return self.entropy() }
+        if self.total_weights() <= 0. { return 0. }
+
+        let power_sum: f32 = self.normalized_weights().values().filter(|p| **p > 0.).map(
+            |p| p.powf(alpha)
+        ).sum();
+        power_sum.log2() / (1. - alpha)
+    }
+
+    /// Collision entropy (Rényi entropy of order 2), in bits - the negative log of the chance
+    /// that two independent samples come out the same.
+    pub fn collision_entropy(&self) -> f32 {
+        self.renyi_entropy(2.)
+    }
+
+    /// A copy of the distribution with each weight replaced by `reweight(key, weight)`.
+    pub fn reweighted<F: Fn(&K, f32) -> f32>(&self, reweight: F) -> Self {
+        let new_weights: HashMap<Arc<K>, f32> = self.weights.iter().map(
+            |(key, weight)| (key.to_owned(), reweight(key, *weight))
+        ).collect();
+        Self::from(new_weights)
     }
 
     pub fn joint_probability_weights<BMD: Borrow<Self>>(&self, other: BMD) -> HashMap<Arc<K>, f32> {
@@ -159,31 +227,113 @@ impl<K: DistributionKey + Copy> MultinomialDistribution<K> {
     }
 }
 
+/// A measure of how uncertain a distribution is; used by the assigner to rank tiles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum EntropyMetric {
+    /// Shannon entropy.
+    #[default]
+    Shannon,
+    /// The number of options with a positive weight, ignoring how large the weights are.
+    RemainingOptions,
+    /// Collision entropy, i.e. Rényi entropy of order 2.
+    Collision,
+    /// Rényi entropy of an arbitrary order.
+    Renyi { alpha: f32 },
+}
+
+impl EntropyMetric {
+    pub fn measure<K: DistributionKey>(&self, distribution: &MultinomialDistribution<K>) -> f32 {
+        match self {
+            Self::Shannon => distribution.entropy(),
+            Self::RemainingOptions => distribution.remaining_options() as f32,
+            Self::Collision => distribution.collision_entropy(),
+            Self::Renyi { alpha } => distribution.renyi_entropy(*alpha),
+        }
+    }
+}
+
+/// Walker's alias table for a MultinomialDistribution, built using Vose's method.
+///
+/// Sampling picks a column uniformly and then flips a biased coin between the column's
+/// own key and its alias, so each sample costs O(1) regardless of the number of keys.
+#[derive(Clone, Debug)]
+pub struct AliasTable<K: DistributionKey> {
+    keys: Vec<K>,
+    probability: Vec<f32>,
+    alias: Vec<usize>
+}
+
+impl<K: DistributionKey> AliasTable<K> {
+    pub fn new(distribution: &MultinomialDistribution<K>) -> Self {
+        let (keys, weights): (Vec<K>, Vec<f32>) = distribution.keys.iter().copied()
+            .zip(distribution.key_weights.iter().copied())
+            .filter(|(_, weight)| *weight > 0.)
+            .unzip();
+        let size = keys.len();
+        let total = distribution.total_weights();
+
+        let mut scaled: Vec<f32> = weights.iter().map(|weight| weight * size as f32 / total).collect();
+        let mut probability = vec![1.; size];
+        let mut alias: Vec<usize> = (0..size).collect();
+
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..size).partition(|idx| scaled[*idx] < 1.);
+
+        while let (Some(lesser), Some(greater)) = (small.pop(), large.pop()) {
+            probability[lesser] = scaled[lesser];
+            alias[lesser] = greater;
+            scaled[greater] -= 1. - scaled[lesser];
+
+            if scaled[greater] < 1. {
+                small.push(greater);
+            } else {
+                large.push(greater);
+            }
+        }
+        // whatever is left over is only off from 1.0 due to rounding errors, so it keeps the default
+
+        Self {
+            keys,
+            probability,
+            alias
+        }
+    }
+}
+
+impl<K: DistributionKey> rand::distributions::Distribution<K> for AliasTable<K> {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> K {
+        if self.keys.is_empty() { return K::default() }
+
+        let column = rng.gen_range(0..self.keys.len());
+        let coin: f32 = rng.sample(Standard);
+
+        match coin < self.probability[column] {
+            true => self.keys[column],
+            false => self.keys[self.alias[column]]
+        }
+    }
+}
+
+/// Samples by walking the cumulative weights of the keys in sorted order - O(n) per sample,
+/// but with no setup cost, which is the better deal for distributions that only get sampled once.
+/// For repeated sampling, use `alias_table()` instead.
 impl<K: DistributionKey> rand::distributions::Distribution<K> for MultinomialDistribution<K> {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> K {
-        let weights = &self.total_weights();
-        let rope_len: f32 = rng.sample::<f32, _>(Standard) * weights;
+        let rope_len: f32 = rng.sample::<f32, _>(Standard) * self.total;
         let mut curr_rope_len = rope_len;
-        let curr_candidate_ref: Option<&Weak<K>> = self.keys.get(0);
-        let mut curr_candidate = curr_candidate_ref.map(|x| x.upgrade().unwrap());
-
-        while curr_rope_len > 0. {
-            for (key, weight) in self.weights.iter() {
-                if curr_rope_len <= *weight {
-                    let owned_key = key.to_owned();
-                    curr_candidate = Some(owned_key);
-                    curr_rope_len -= *weight;
-                    break
-                }
-                curr_rope_len -= *weight
+        let mut curr_candidate = None;
+
+        for (key, weight) in self.keys.iter().zip(self.key_weights.iter().copied()) {
+            if weight <= 0. { continue }
+
+            // the last positive-weight key catches anything left over due to rounding
+            curr_candidate = Some(*key);
+            if curr_rope_len < weight {
+                break
             }
+            curr_rope_len -= weight;
         }
 
-        // match curr_candidate {
-        //     Some(good_candidate) => *good_candidate,
-        //     None => MaybeDistributionKey::None
-        // }
-        *curr_candidate.unwrap_or(K::default().into())
+        curr_candidate.unwrap_or_default()
     }
 }
 
@@ -204,15 +354,35 @@ mod tests {
         let keys = vec!["a", "b"];
         let dist = MultinomialDistribution::uniform_over(keys);
         assert_eq!(dist.total_weights(), 2.);
-        let key_one = dist.keys.get(0).unwrap().upgrade().unwrap();
-        assert!(key_one.contains("a"));
-        //assert_eq!(dist.keys.get(0).unwrap().upgrade().unwrap(), keys.get(0).unwrap());
+        assert_eq!(dist.keys(), &["a", "b"]);
         assert_eq!(dist.weights.get(&"a").unwrap(), &1.);
         assert_eq!(dist.weights.get(&"b").unwrap(), &1.);
         assert_eq!(dist.weights.get(&"c").unwrap_or(&-666.), &-666.);
         assert_eq!(dist.weights.len(), 2);
     }
 
+    #[test]
+    fn entropy_is_positive_shannon() {
+        let uniform = MultinomialDistribution::uniform_over(vec![1, 2, 3, 4]);
+        assert!((uniform.entropy() - 2.).abs() < 1e-5);
+
+        let certain = MultinomialDistribution::uniform_over(vec![1]);
+        assert_eq!(certain.entropy(), 0.);
+    }
+
+    #[test]
+    fn weight_aware_metrics() {
+        let uniform = MultinomialDistribution::uniform_over(vec![1, 2, 3, 4]);
+        assert!((uniform.collision_entropy() - 2.).abs() < 1e-5);
+        assert_eq!(EntropyMetric::RemainingOptions.measure(&uniform), 4.);
+
+        let skewed = MultinomialDistribution::from(HashMap::from([(1, 97.), (2, 1.), (3, 1.), (4, 1.), (5, 0.)]));
+        assert_eq!(skewed.remaining_options(), 4);
+        assert!(skewed.collision_entropy() < skewed.entropy());
+        assert!(skewed.entropy() < uniform.entropy());
+        assert!((skewed.renyi_entropy(1.) - skewed.entropy()).abs() < 1e-5);
+    }
+
     #[test]
     fn sampling_works() {
         let dist = MultinomialDistribution::uniform_over(vec![1, 2]);
@@ -222,6 +392,50 @@ mod tests {
         assert!(sample < 3);
     }
 
+    #[test]
+    fn sampling_is_deterministic_for_a_seed() {
+        let dist = MultinomialDistribution::from(HashMap::from([(1, 5.), (2, 1.), (3, 3.), (4, 0.)]));
+        let draw = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..50).map(|_| dist.sample(&mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(42), draw(42));
+        assert!(!draw(42).contains(&4));
+    }
+
+    #[test]
+    fn alias_table_matches_weights() {
+        let dist = MultinomialDistribution::from(HashMap::from([(1, 6.), (2, 3.), (3, 1.), (4, 0.)]));
+        let table = dist.alias_table();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts: HashMap<i32, usize> = HashMap::new();
+        for sample in table.sample_iter(&mut rng).take(10_000) {
+            *counts.entry(sample).or_default() += 1;
+        }
+
+        assert!(!counts.contains_key(&4));
+        assert!((5600..6400).contains(&counts[&1]));
+        assert!((2600..3400).contains(&counts[&2]));
+        assert!((800..1200).contains(&counts[&3]));
+    }
+
+    #[test]
+    fn empty_distribution_samples_default() {
+        let dist = MultinomialDistribution::from(HashMap::from([(1, 0.)]));
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(dist.sample(&mut rng), 0);
+        assert_eq!(dist.alias_table().sample(&mut rng), 0);
+    }
+
+    #[test]
+    fn serde_roundtrip_rebuilds_keys() {
+        let dist = MultinomialDistribution::from(HashMap::from([(3, 1.), (1, 2.)]));
+        let json = serde_json::to_string(&dist).unwrap();
+        let restored: MultinomialDistribution<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.keys(), &[1, 3]);
+        assert_eq!(restored.total_weights(), 3.);
+    }
+
     #[test]
     fn sampling_with_default_works() {
         let dist = MultinomialDistribution::uniform_over(vec![1, 2]);
@@ -0,0 +1,186 @@
+use std::cmp::Ordering;
+use std::f64::consts::TAU;
+use num::{NumCast, ToPrimitive};
+use rand::{Rng, RngCore};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use crate::adjacency::AdjacencyGenerator;
+use crate::map2dnode::ThreadsafeNodeRef;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+/// Decides which undecided tile gets collapsed next.
+///
+/// The assigner keeps a priority queue of tiles; a heuristic picks the tiles
+/// the queue starts out with and how highly each queued tile ranks.
+pub trait SelectionHeuristic<MP: MapPosition<2>>: Send + Sync {
+    /// Picks the positions to enqueue when assignment (re)starts.
+    ///
+    ///  **Arguments**:
+    /// * `undecided` - positions of all undecided tiles, sorted by coordinates.
+    /// * `bounds` - the min & max positions of the map.
+    /// * `rng` - the random number generator of the job.
+    ///
+    /// **Returns**: the positions to seed the queue with; positions that are not undecided are ignored.
+    ///
+    fn seeds(&self, undecided: &[MP], bounds: [MP; 2], rng: &mut dyn RngCore) -> Vec<MP>;
+
+    /// Ranks a queued tile; the highest-priority tile gets collapsed first.
+    /// Ties are broken randomly by the queue.
+    ///
+    ///  **Arguments**:
+    /// * `position` - position of the tile.
+    /// * `entropy` - uncertainty of the tile's current possibilities, as measured by the rules' priority metric.
+    /// * `bounds` - the min & max positions of the map.
+    /// * `rng` - the random number generator of the job.
+    ///
+    fn priority(&self, position: MP, entropy: f64, bounds: [MP; 2], rng: &mut dyn RngCore) -> f64;
+
+    /// If true, a tile is re-queued with a fresh priority every time its possibilities change.
+    /// Heuristics with static priorities can return false to keep the queue small.
+    fn reprioritize_on_update(&self) -> bool {
+        true
+    }
+}
+
+/// The built-in selection heuristics, as specified in a Ruleset.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CellSelection {
+    /// Grows outwards from a random tile, always collapsing the least uncertain tile next.
+    #[default]
+    MinEntropy,
+    /// Row by row, left to right, top to bottom.
+    Scanline,
+    /// Uniformly random order across the whole map.
+    Random,
+    /// Ring by ring outwards from the origin, sweeping by angle within each ring.
+    Spiral { origin: [u32; 2] },
+    /// Grows outwards from all the seed points at once, by lowest entropy.
+    MultiSeed { seeds: Vec<[u32; 2]> },
+}
+
+fn relative_dims<MP: MapPosition<2>>(position: MP, origin: MP) -> [f64; 2] {
+    let pos = position.get_dims();
+    let orig = origin.get_dims();
+    [
+        pos[0].to_f64().unwrap_or(0.) - orig[0].to_f64().unwrap_or(0.),
+        pos[1].to_f64().unwrap_or(0.) - orig[1].to_f64().unwrap_or(0.),
+    ]
+}
+
+fn cast_position<MP: MapPosition<2>>(raw: &[u32; 2]) -> Option<MP> {
+    Some(MP::from_dims([
+        <MP::Key as NumCast>::from(raw[0])?,
+        <MP::Key as NumCast>::from(raw[1])?,
+    ]))
+}
+
+impl<MP: MapPosition<2>> SelectionHeuristic<MP> for CellSelection {
+    fn seeds(&self, undecided: &[MP], _bounds: [MP; 2], rng: &mut dyn RngCore) -> Vec<MP> {
+        match self {
+            Self::MinEntropy => undecided.choose(rng).copied().into_iter().collect(),
+            Self::Scanline | Self::Random | Self::Spiral { .. } => undecided.to_vec(),
+            Self::MultiSeed { seeds } => seeds.iter().filter_map(cast_position).collect(),
+        }
+    }
+
+    fn priority(&self, position: MP, entropy: f64, bounds: [MP; 2], rng: &mut dyn RngCore) -> f64 {
+        match self {
+            Self::MinEntropy | Self::MultiSeed { .. } => -entropy,
+            Self::Scanline => {
+                let [x, y] = relative_dims(position, bounds[0]);
+                let [width, _] = relative_dims(bounds[1], bounds[0]);
+                -(y * (width + 1.) + x)
+            },
+            Self::Random => rng.gen(),
+            Self::Spiral { origin } => {
+                let [dx, dy] = match cast_position::<MP>(origin) {
+                    Some(origin_pos) => relative_dims(position, origin_pos),
+                    None => relative_dims(position, bounds[0])
+                };
+                let ring = dx.abs().max(dy.abs());
+                let sweep = dy.atan2(dx).rem_euclid(TAU) / TAU;
+                -(ring + sweep)
+            }
+        }
+    }
+
+    fn reprioritize_on_update(&self) -> bool {
+        matches!(self, Self::MinEntropy | Self::MultiSeed { .. })
+    }
+}
+
+/// A queue entry: a tile together with its priority at the time it was queued.
+#[derive(Serialize, Deserialize)]
+pub struct PrioritizedNode<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> {
+    pub priority: f64,
+    tiebreak: u32,
+    pub node: ThreadsafeNodeRef<AG, K, MP>
+}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> PrioritizedNode<AG, K, MP> {
+    pub fn new<R: Rng + ?Sized>(priority: f64, node: ThreadsafeNodeRef<AG, K, MP>, rng: &mut R) -> Self {
+        Self {
+            priority,
+            tiebreak: rng.gen(),
+            node
+        }
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> PartialEq for PrioritizedNode<AG, K, MP> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> Eq for PrioritizedNode<AG, K, MP> {}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> PartialOrd for PrioritizedNode<AG, K, MP> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, K: DistributionKey, MP: MapPosition<2>> Ord for PrioritizedNode<AG, K, MP> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .total_cmp(&other.priority)
+            .then(self.tiebreak.cmp(&other.tiebreak))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::thread_rng;
+    use crate::position2d::Position2D;
+    use super::*;
+
+    const BOUNDS: [Position2D<u8>; 2] = [Position2D { x: 0, y: 0 }, Position2D { x: 9, y: 9 }];
+
+    fn rank(heuristic: &CellSelection, pos: (u8, u8)) -> f64 {
+        heuristic.priority(Position2D::from(pos), 1., BOUNDS, &mut thread_rng())
+    }
+
+    #[test]
+    fn scanline_goes_row_by_row() {
+        let heuristic = CellSelection::Scanline;
+        assert!(rank(&heuristic, (0, 0)) > rank(&heuristic, (1, 0)));
+        assert!(rank(&heuristic, (9, 0)) > rank(&heuristic, (0, 1)));
+    }
+
+    #[test]
+    fn spiral_goes_ring_by_ring() {
+        let heuristic = CellSelection::Spiral { origin: [5, 5] };
+        assert!(rank(&heuristic, (5, 5)) > rank(&heuristic, (4, 4)));
+        assert!(rank(&heuristic, (4, 4)) > rank(&heuristic, (7, 5)));
+        assert!(rank(&heuristic, (3, 7)) > rank(&heuristic, (9, 9)));
+    }
+
+    #[test]
+    fn multi_seed_uses_all_seeds() {
+        let heuristic = CellSelection::MultiSeed { seeds: vec![[1, 1], [8, 8]] };
+        let seeds: Vec<Position2D<u8>> = heuristic.seeds(&[], BOUNDS, &mut thread_rng());
+        assert_eq!(seeds, vec![Position2D::new(1, 1), Position2D::new(8, 8)]);
+    }
+}
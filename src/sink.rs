@@ -0,0 +1,158 @@
+use std::io::Write;
+use std::path::PathBuf;
+use ril::{Image, ImageFormat, Rgb};
+use crate::adjacency::AdjacencyGenerator;
+use crate::map2d::Map2D;
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+use crate::visualizers::MapVisualizer;
+
+/// A rendered map, as returned by a MapVisualizer, that can be turned into bytes.
+pub trait EncodableOutput {
+    /// Encodes the output in a format; text outputs (like SVG) ignore the format and are encoded as UTF-8.
+    fn encode(&self, format: ImageFormat) -> Result<Vec<u8>, ril::Error>;
+}
+
+impl EncodableOutput for Image<Rgb> {
+    fn encode(&self, format: ImageFormat) -> Result<Vec<u8>, ril::Error> {
+        let format = if format.is_unknown() { ImageFormat::Png } else { format };
+        let mut encoded = Vec::new();
+        Image::encode(self, format, &mut encoded)?;
+        Ok(encoded)
+    }
+}
+
+impl EncodableOutput for String {
+    fn encode(&self, _format: ImageFormat) -> Result<Vec<u8>, ril::Error> {
+        Ok(self.as_bytes().to_vec())
+    }
+}
+
+/// Somewhere to write rendered maps to.
+pub trait MapSink {
+    fn write<O: EncodableOutput>(&self, output: &O) -> Result<(), ril::Error>;
+}
+
+/// Renders a map with a visualizer and writes the result to a sink.
+/// Nothing is written if the visualizer has nothing to show for the map.
+pub fn render_to<AG, K, MP, V, S>(visualiser: &V, map: &Map2D<AG, K, MP>, sink: &S) -> Result<(), ril::Error>
+where
+    AG: AdjacencyGenerator<2>,
+    K: DistributionKey,
+    MP: MapPosition<2>,
+    V: MapVisualizer<AG, K, MP>,
+    V::Output: EncodableOutput,
+    S: MapSink
+{
+    match visualiser.visualise(map, None) {
+        Some(rendered) => sink.write(&rendered),
+        None => Ok(())
+    }
+}
+
+/// Writes rendered maps to a file, in the format given by the file's extension unless specified otherwise.
+/// Images go out as PNGs if the format is neither specified nor known from the extension.
+#[derive(Debug, Clone)]
+pub struct FileSink {
+    path: PathBuf,
+    format: Option<ImageFormat>,
+}
+
+impl FileSink {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            format: None
+        }
+    }
+
+    pub fn with_format(mut self, format: ImageFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+}
+
+impl MapSink for FileSink {
+    fn write<O: EncodableOutput>(&self, output: &O) -> Result<(), ril::Error> {
+        let format = match self.format {
+            Some(format) => format,
+            None => ImageFormat::from_path(&self.path).unwrap_or(ImageFormat::Unknown)
+        };
+        std::fs::write(&self.path, output.encode(format)?)?;
+        Ok(())
+    }
+}
+
+/// Writes rendered maps to the standard output - text as-is, images encoded in the specified format.
+#[derive(Debug, Clone, Copy)]
+pub struct StdoutSink {
+    format: ImageFormat,
+}
+
+impl StdoutSink {
+    pub fn new(format: ImageFormat) -> Self {
+        Self {
+            format
+        }
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new(ImageFormat::Png)
+    }
+}
+
+impl MapSink for StdoutSink {
+    fn write<O: EncodableOutput>(&self, output: &O) -> Result<(), ril::Error> {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&output.encode(self.format)?)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use crate::visualizers::{MapColor, RilPixelVisualizer};
+    use super::*;
+
+    #[derive(Default)]
+    struct MemorySink(RefCell<Vec<u8>>);
+
+    impl MapSink for MemorySink {
+        fn write<O: EncodableOutput>(&self, output: &O) -> Result<(), ril::Error> {
+            self.0.borrow_mut().extend(output.encode(ImageFormat::Png)?);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn images_encode_in_memory() {
+        let image = Image::new(2, 2, Rgb::new(10, 20, 30));
+        let png = EncodableOutput::encode(&image, ImageFormat::Unknown).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        let decoded: Image<Rgb> = Image::from_bytes(ImageFormat::Png, png).unwrap();
+        assert_eq!(decoded.pixel(1, 1), &Rgb::new(10, 20, 30));
+    }
+
+    #[test]
+    fn maps_render_to_sinks() {
+        let map: Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>> = Map2D::from_tiles(
+            (0..3).flat_map(|x| (0..2).map(move |y| Map2DNode::with_assignment(Position2D::new(x, y), 1)))
+        );
+        let visualizer = RilPixelVisualizer::from(HashMap::from([(1, MapColor::Rgb(10, 20, 30))]));
+        let sink = MemorySink::default();
+        render_to(&visualizer, &map, &sink).unwrap();
+
+        let decoded: Image<Rgb> = Image::from_bytes(ImageFormat::Png, sink.0.into_inner()).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (3, 2));
+        assert_eq!(decoded.pixel(2, 1), &Rgb::new(10, 20, 30));
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use num::{NumCast, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use crate::position::MapPosition;
+use crate::sampler::DistributionKey;
+
+/// How a map gets mirrored or rotated onto itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymmetryMode {
+    /// The left half mirrors the right half.
+    MirrorX,
+    /// The top half mirrors the bottom half.
+    MirrorY,
+    /// Each quarter mirrors the quarters next to it.
+    MirrorBoth,
+    /// The map looks the same after a half turn around its center.
+    Rotate2,
+    /// The map looks the same after a quarter turn around its center; only square maps can be fully symmetric.
+    Rotate4,
+}
+
+/// Makes maps symmetric, e.g. so that a competitive map is fair to all players.
+///
+/// Whenever a tile gets collapsed, its counterparts get collapsed along with it, and the rules
+/// are then propagated from all of them. The tile's key is picked from those that its counterparts can take as well.
+/// Counterparts get the same key, unless the key mapping says otherwise;
+/// the mapping is applied once per step of the mode (once per reflection, or per half or quarter turn),
+/// so that e.g. a mapping from one team's base to the other team's and back swaps the bases around.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symmetry<K: DistributionKey> {
+    pub mode: SymmetryMode,
+    /// The keys to put on counterparts instead of the original key; unmapped keys stay as they are.
+    #[serde(default)]
+    pub key_mapping: HashMap<K, K>,
+}
+
+impl<K: DistributionKey> Symmetry<K> {
+    pub fn new(mode: SymmetryMode) -> Self {
+        Self {
+            mode,
+            key_mapping: HashMap::new()
+        }
+    }
+
+    pub fn with_key_mapping(mut self, key_mapping: HashMap<K, K>) -> Self {
+        self.key_mapping = key_mapping;
+        self
+    }
+
+    /// The key mapped the specified number of times.
+    pub fn mapped_key(&self, key: K, steps: usize) -> K {
+        (0..steps).fold(key, |key, _| self.key_mapping.get(&key).copied().unwrap_or(key))
+    }
+
+    /// The counterparts of a tile with a key, along with the keys they should get.
+    /// The tile itself and counterparts that fall outside of the bounds are left out.
+    pub fn counterparts<MP: MapPosition<2>>(&self, position: MP, key: K, bounds: [MP; 2]) -> Vec<(MP, K)> {
+        self.images(position, bounds).into_iter().map(|(image, steps)| (image, self.mapped_key(key, steps))).collect()
+    }
+
+    /// The positions of the counterparts of a tile, as in `counterparts()`.
+    pub fn counterpart_positions<MP: MapPosition<2>>(&self, position: MP, bounds: [MP; 2]) -> Vec<MP> {
+        self.images(position, bounds).into_iter().map(|(image, _)| image).collect()
+    }
+
+    /// The distinct counterparts of a tile within the bounds, with the number of steps of the mode it takes to reach them.
+    fn images<MP: MapPosition<2>>(&self, position: MP, bounds: [MP; 2]) -> Vec<(MP, usize)> {
+        let [x, y] = position.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
+        let [[min_x, min_y], [max_x, max_y]] = bounds.map(|bound| bound.get_dims().map(|dim| dim.to_i64().unwrap_or(0)));
+
+        // coordinates doubled and relative to the center, so that the center of even-sized maps stays whole
+        let (sum_x, sum_y) = (min_x + max_x, min_y + max_y);
+        let (dx, dy) = (2 * x - sum_x, 2 * y - sum_y);
+
+        let offsets: Vec<((i64, i64), usize)> = match self.mode {
+            SymmetryMode::MirrorX => vec![((-dx, dy), 1)],
+            SymmetryMode::MirrorY => vec![((dx, -dy), 1)],
+            SymmetryMode::MirrorBoth => vec![((-dx, dy), 1), ((dx, -dy), 1), ((-dx, -dy), 2)],
+            SymmetryMode::Rotate2 => vec![((-dx, -dy), 1)],
+            SymmetryMode::Rotate4 => vec![((-dy, dx), 1), ((-dx, -dy), 2), ((dy, -dx), 3)],
+        };
+
+        let mut images: Vec<(MP, usize)> = Vec::new();
+        for ((image_dx, image_dy), steps) in offsets {
+            let (doubled_x, doubled_y) = (image_dx + sum_x, image_dy + sum_y);
+            // on maps with sides of different parity, quarter turns land between tiles
+            if doubled_x % 2 != 0 || doubled_y % 2 != 0 { continue }
+
+            let (image_x, image_y) = (doubled_x / 2, doubled_y / 2);
+            if image_x < min_x || image_x > max_x || image_y < min_y || image_y > max_y { continue }
+            if (image_x, image_y) == (x, y) { continue }
+
+            let image = match (NumCast::from(image_x), NumCast::from(image_y)) {
+                (Some(image_x), Some(image_y)) => MP::from_dims([image_x, image_y]),
+                _ => continue
+            };
+            if images.iter().all(|(pos, _)| *pos != image) {
+                images.push((image, steps));
+            }
+        }
+        images
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::position2d::Position2D;
+    use super::*;
+
+    #[test]
+    fn finds_counterparts_within_bounds() {
+        let bounds = [Position2D::new(0u8, 0), Position2D::new(3, 3)];
+        let corner = Position2D::new(0, 1);
+
+        assert_eq!(Symmetry::new(SymmetryMode::MirrorX).counterparts(corner, 1, bounds), vec![(Position2D::new(3, 1), 1)]);
+        assert_eq!(Symmetry::new(SymmetryMode::Rotate2).counterparts(corner, 1, bounds), vec![(Position2D::new(3, 2), 1)]);
+
+        let swapped = Symmetry::new(SymmetryMode::Rotate4).with_key_mapping(HashMap::from([(1, 2), (2, 1)]));
+        assert_eq!(swapped.counterparts(corner, 1, bounds), vec![
+            (Position2D::new(2, 0), 2),
+            (Position2D::new(3, 2), 1),
+            (Position2D::new(1, 3), 2),
+        ]);
+
+        // tiles on the axis are their own counterparts
+        let odd_bounds = [Position2D::new(0u8, 0), Position2D::new(4, 4)];
+        assert!(Symmetry::new(SymmetryMode::MirrorX).counterparts(Position2D::new(2, 0), 1, odd_bounds).is_empty());
+        assert!(Symmetry::new(SymmetryMode::Rotate4).counterparts(Position2D::new(2, 2), 1, odd_bounds).is_empty());
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use crate::assigner::MapColoringAssigner;
+use crate::sampler::{DistributionKey, MultinomialDistribution};
+
+/// How a tile looks under rotations and reflections, which decides how many distinct variants it has.
+/// The classes are named after tiles that have them; the variants are numbered as in `transform_of()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymmetryClass {
+    /// Looks the same however it is turned, e.g. a plain floor; 1 variant.
+    X,
+    /// A straight line across the tile, e.g. a vertical corridor; 2 variants.
+    I,
+    /// A diagonal line from the top left to the bottom right corner; 2 variants.
+    Diagonal,
+    /// A junction of the left, right and bottom edges; 4 variants.
+    T,
+    /// A corner joining the top and right edges; 4 variants.
+    L,
+    /// No symmetry at all; 4 rotations, then the same 4 rotations of the mirrored tile, for 8 variants.
+    F,
+}
+
+impl SymmetryClass {
+    /// The number of distinct variants.
+    pub fn cardinality(&self) -> usize {
+        match self {
+            Self::X => 1,
+            Self::I | Self::Diagonal => 2,
+            Self::T | Self::L => 4,
+            Self::F => 8,
+        }
+    }
+
+    /// The variant a variant turns into after a clockwise quarter turn.
+    pub fn rotate(&self, variant: usize) -> usize {
+        match self {
+            Self::X => 0,
+            Self::I | Self::Diagonal => 1 - variant,
+            Self::T | Self::L => (variant + 1) % 4,
+            Self::F => 4 * (variant / 4) + (variant + 1) % 4,
+        }
+    }
+
+    /// The variant a variant turns into when mirrored left to right.
+    pub fn reflect(&self, variant: usize) -> usize {
+        match self {
+            Self::X => 0,
+            Self::I => variant,
+            Self::Diagonal => 1 - variant,
+            Self::T => (4 - variant) % 4,
+            Self::L => 3 - variant,
+            Self::F => match variant < 4 {
+                true => 4 + (4 - variant) % 4,
+                false => (4 - variant % 4) % 4,
+            },
+        }
+    }
+
+    /// How to draw a variant with the sprite of the first one.
+    pub fn transform_of(&self, variant: usize) -> Transform {
+        Transform {
+            quarter_turns: (variant % 4) as u8,
+            reflected: variant >= 4
+        }
+    }
+
+    /// The variant a transform turns a variant into.
+    pub fn apply(&self, variant: usize, transform: Transform) -> usize {
+        let reflected = match transform.reflected {
+            true => self.reflect(variant),
+            false => variant
+        };
+        (0..transform.quarter_turns % 4).fold(reflected, |variant, _| self.rotate(variant))
+    }
+}
+
+/// A rotation and reflection of a tile: mirrored left to right first (if reflected), then turned clockwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Transform {
+    pub quarter_turns: u8,
+    pub reflected: bool,
+}
+
+impl Transform {
+    /// All 8 rotations and reflections of a square.
+    pub fn all() -> impl Iterator<Item = Self> {
+        [false, true].into_iter().flat_map(
+            |reflected| (0..4).map(move |quarter_turns| Self { quarter_turns, reflected })
+        )
+    }
+}
+
+/// The rotated and reflected variants of a tile, each with a key of its own.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileGroup<K: DistributionKey> {
+    pub symmetry: SymmetryClass,
+    /// Keys of the variants, in the order of `SymmetryClass::transform_of()`;
+    /// the first one is the tile as drawn by its sprite.
+    pub variants: Vec<K>,
+}
+
+/// Expands rules written for some variants of the tiles to all of their rotations and reflections.
+///
+/// For every rule that a tile may be next to another, the same goes for each rotation and reflection of the two
+/// tiles, turned together. Keys that are not in any group are left as they are by every transform.
+#[derive(Clone, Debug, Default)]
+pub struct TileTransforms<K: DistributionKey> {
+    groups: Vec<TileGroup<K>>,
+    /// The group and variant of each key.
+    variant_index: HashMap<K, (usize, usize)>,
+}
+
+impl<K: DistributionKey> TileTransforms<K> {
+    /// Indexes the groups; groups with the wrong number of variants for their class, or sharing keys
+    /// with an earlier group, are reported and skipped.
+    pub fn new(groups: &[TileGroup<K>]) -> Self {
+        let mut transforms = Self {
+            groups: Vec::new(),
+            variant_index: HashMap::new()
+        };
+
+        for group in groups {
+            if group.variants.len() != group.symmetry.cardinality() {
+                eprintln!(
+                    "WARNING: tile group {:?} has {} variant(s) instead of {} for class {:?}, skipping it",
+                    group.variants, group.variants.len(), group.symmetry.cardinality(), group.symmetry
+                );
+                continue
+            }
+            let distinct: HashSet<&K> = group.variants.iter().collect();
+            if distinct.len() != group.variants.len() || group.variants.iter().any(|key| transforms.variant_index.contains_key(key)) {
+                eprintln!("WARNING: tile group {:?} repeats keys, skipping it", group.variants);
+                continue
+            }
+
+            let group_index = transforms.groups.len();
+            for (variant, key) in group.variants.iter().enumerate() {
+                transforms.variant_index.insert(*key, (group_index, variant));
+            }
+            transforms.groups.push(group.to_owned());
+        }
+        transforms
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// The key of the variant a transform turns a key into.
+    pub fn transformed(&self, key: K, transform: Transform) -> K {
+        match self.variant_index.get(&key) {
+            Some((group_index, variant)) => {
+                let group = &self.groups[*group_index];
+                group.variants[group.symmetry.apply(*variant, transform)]
+            },
+            None => key
+        }
+    }
+
+    /// The first variant of the key's group and how to draw the key with its sprite, or None if the key is in no group.
+    pub fn sprite_of(&self, key: K) -> Option<(K, Transform)> {
+        let (group_index, variant) = self.variant_index.get(&key)?;
+        let group = &self.groups[*group_index];
+        Some((group.variants[0], group.symmetry.transform_of(*variant)))
+    }
+
+    /// The transition rules for every variant of the tiles with rules. If several rules end up
+    /// weighting the same pair of variants, the highest weight wins.
+    pub fn expand_rules(&self, rules: &HashMap<K, MultinomialDistribution<K>>) -> HashMap<K, MultinomialDistribution<K>> {
+        if self.is_empty() { return rules.to_owned() }
+
+        let mut expanded: HashMap<K, HashMap<K, f32>> = HashMap::new();
+        for (key, dist) in rules.iter() {
+            for transform in Transform::all() {
+                let weights = expanded.entry(self.transformed(*key, transform)).or_default();
+                for neighbor in dist.keys() {
+                    let weight = weights.entry(self.transformed(*neighbor, transform)).or_insert(0.);
+                    *weight = weight.max(dist.weight(neighbor));
+                }
+            }
+        }
+        expanded.into_iter().map(|(key, weights)| (key, MultinomialDistribution::from(weights))).collect()
+    }
+
+    /// The transition and distance rules of the assigner, expanded to every variant.
+    pub fn expand_assigner(&self, rules: &MapColoringAssigner<K>) -> MapColoringAssigner<K> {
+        let mut expanded = rules.to_owned();
+        expanded.transition_rules = self.expand_rules(&rules.transition_rules);
+        for band in expanded.distance_rules.iter_mut() {
+            band.rules = self.expand_rules(&band.rules);
+        }
+        expanded
+    }
+
+    /// The keys along with every variant of them.
+    pub fn expand_keys(&self, keys: &HashSet<K>) -> HashSet<K> {
+        keys.iter().flat_map(|key| Transform::all().map(|transform| self.transformed(*key, transform))).collect()
+    }
+
+    /// Fills in the values (e.g. colors or glyphs) of the variants without one with the value
+    /// of the first variant in their group that has one.
+    pub fn expand_values<V: Clone>(&self, values: &HashMap<K, V>) -> HashMap<K, V> {
+        let mut expanded = values.to_owned();
+        for group in self.groups.iter() {
+            let value = match group.variants.iter().find_map(|key| values.get(key)) {
+                Some(value) => value,
+                None => continue
+            };
+            for key in group.variants.iter() {
+                expanded.entry(*key).or_insert_with(|| value.to_owned());
+            }
+        }
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOOR: u8 = 0;
+    // a corner joining the top and right edges, then turned clockwise
+    const CORNERS: [u8; 4] = [10, 11, 12, 13];
+
+    fn transforms() -> TileTransforms<u8> {
+        TileTransforms::new(&[
+            TileGroup { symmetry: SymmetryClass::X, variants: vec![FLOOR] },
+            TileGroup { symmetry: SymmetryClass::L, variants: CORNERS.to_vec() },
+        ])
+    }
+
+    #[test]
+    fn classes_form_groups() {
+        let classes = [SymmetryClass::X, SymmetryClass::I, SymmetryClass::Diagonal, SymmetryClass::T, SymmetryClass::L, SymmetryClass::F];
+        for class in classes {
+            for variant in 0..class.cardinality() {
+                // four quarter turns or two reflections make a full circle
+                assert_eq!(class.apply(variant, Transform { quarter_turns: 4, reflected: false }), variant);
+                assert_eq!(class.reflect(class.reflect(variant)), variant);
+                // mirroring turns the other way around
+                assert_eq!(class.reflect(class.rotate(class.reflect(variant))), class.apply(variant, Transform { quarter_turns: 3, reflected: false }));
+            }
+            let reachable: HashSet<usize> = Transform::all().map(|transform| class.apply(0, transform)).collect();
+            assert_eq!(reachable.len(), class.cardinality());
+        }
+    }
+
+    #[test]
+    fn rules_get_turned_with_their_tiles() {
+        let transforms = transforms();
+        let rules = HashMap::from([
+            (CORNERS[0], MultinomialDistribution::from(HashMap::from([(CORNERS[1], 2.), (FLOOR, 1.)]))),
+        ]);
+
+        let expanded = transforms.expand_rules(&rules);
+        assert_eq!(expanded.len(), 4);
+        for variant in 0..4 {
+            let dist = &expanded[&CORNERS[variant]];
+            assert_eq!(dist.weight(&CORNERS[(variant + 1) % 4]), 2.);
+            assert_eq!(dist.weight(&FLOOR), 1.);
+        }
+        // mirrored corners turn the other way, e.g. 11 next to 12 becomes 12 next to 11
+        assert_eq!(expanded[&CORNERS[2]].weight(&CORNERS[1]), 2.);
+
+        assert_eq!(transforms.sprite_of(CORNERS[3]), Some((CORNERS[0], Transform { quarter_turns: 3, reflected: false })));
+        assert_eq!(transforms.sprite_of(1), None);
+
+        let glyphs = transforms.expand_values(&HashMap::from([(CORNERS[0], 'L'), (FLOOR, '.')]));
+        assert_eq!(glyphs.len(), 5);
+        assert_eq!(glyphs[&CORNERS[2]], 'L');
+    }
+}
@@ -1,15 +1,17 @@
 use std::borrow::Borrow;
 use std::collections::HashMap;
-use std::fmt::Debug;
-use num::{Bounded, NumCast, One};
+use std::fmt::{Debug, Write};
+use num::{Bounded, NumCast, One, ToPrimitive};
 use crate::map2d::Map2D;
-use crate::sampler::DistributionKey;
+use crate::sampler::{DistributionKey, EntropyMetric};
 use ril;
 use ril::{Draw, Rgb};
 use serde::{Deserialize, Serialize};
 use crate::adjacency::AdjacencyGenerator;
-use crate::map2dnode::MapNodeState;
+use crate::diff::MapDiff;
+use crate::map2dnode::{MapNodeState, ThreadsafeNodeRef};
 use crate::position::{MapPosition, PositionKey};
+use crate::sink::EncodableOutput;
 
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -17,10 +19,19 @@ pub enum MapColor {
     Rgb(u8, u8, u8)
 }
 
-impl Into<ril::Rgb> for MapColor {
-    fn into(self) -> Rgb {
+impl MapColor {
+    /// Relative luminance of the color, from 0 (black) to 1 (white).
+    pub fn luminance(&self) -> f32 {
         match self {
-            Self::Rgb(r, g, b) => ril::Rgb::new(r, g, b)
+            Self::Rgb(r, g, b) => (0.2126 * *r as f32 + 0.7152 * *g as f32 + 0.0722 * *b as f32) / 255.
+        }
+    }
+}
+
+impl From<MapColor> for ril::Rgb {
+    fn from(value: MapColor) -> Self {
+        match value {
+            MapColor::Rgb(r, g, b) => ril::Rgb::new(r, g, b)
         }
     }
 }
@@ -32,11 +43,18 @@ impl<R: Borrow<ril::Rgb>> From<R> for MapColor {
 }
 
 
+/// Renders a map into some in-memory output (an image, a text...);
+/// writing the output out anywhere is up to a `MapSink`.
 pub trait MapVisualizer<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> {
     type Output;
     type Args;
 
     fn visualise(&self, map: &Map2D<AG, N, MP>, args: Option<Self::Args>) -> Option<Self::Output>;
+
+    /// The usual file extension of the output, for naming files it gets saved to.
+    fn file_extension(&self) -> &'static str {
+        "png"
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,10 +91,11 @@ impl<N: DistributionKey> From<HashMap<N, MapColor>> for RilPixelVisualizer<N> {
 impl<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> MapVisualizer<AG, N, MP> for RilPixelVisualizer<N>
 where MP::Key: PositionKey + NumCast + Into<u32>
 {
-    type Output = ();
-    type Args = String;
+    type Output = ril::Image<Rgb>;
+    type Args = ();
 
-    fn visualise(&self, map: &Map2D<AG, N, MP>, output: Option<Self::Args>) -> Option<Self::Output> {
+    /// Renders the map as an image, with one pixel per tile.
+    fn visualise(&self, map: &Map2D<AG, N, MP>, _args: Option<Self::Args>) -> Option<Self::Output> {
         const MAP_SCALE_FACTOR: u32 = 1;
 
         let min_pos = map.min_pos.get_dims();
@@ -146,14 +165,644 @@ where MP::Key: PositionKey + NumCast + Into<u32>
             repr.draw(&mut image);
         }
 
-        let fname = output.unwrap_or(Self::Args::from("map.png"));
-        let result = image.save_inferred(fname);
-        match result {
-            Ok(_) => Some(()),
-            Err(err) => {
-                eprintln!("{}", err);
-                None
+        Some(image)
+    }
+}
+
+
+/// The tiles of a map laid out in a grid by their position relative to the map's min position.
+struct TileGrid<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> {
+    /// Absolute position of the first tile.
+    origin: [i64; 2],
+    width: usize,
+    height: usize,
+    /// Row by row; positions without a tile are None.
+    tiles: Vec<Option<ThreadsafeNodeRef<AG, N, MP>>>,
+}
+
+impl<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> TileGrid<AG, N, MP> {
+    fn new(map: &Map2D<AG, N, MP>) -> Self {
+        let to_dims = |pos: MP| pos.get_dims().map(|dim| dim.to_i64().unwrap_or(0));
+        let [min_x, min_y] = to_dims(map.min_pos);
+        let [max_x, max_y] = to_dims(map.max_pos);
+        let (width, height) = ((max_x - min_x + 1).max(0) as usize, (max_y - min_y + 1).max(0) as usize);
+
+        let mut tiles = vec![None; width * height];
+        for (pos, tile) in map.position_index.iter() {
+            let [x, y] = to_dims(*pos);
+            tiles[(y - min_y) as usize * width + (x - min_x) as usize] = Some(tile.to_owned());
+        }
+
+        Self {
+            origin: [min_x, min_y],
+            width,
+            height,
+            tiles
+        }
+    }
+
+    fn rows(&self) -> std::slice::Chunks<'_, Option<ThreadsafeNodeRef<AG, N, MP>>> {
+        self.tiles.chunks(self.width.max(1))
+    }
+}
+
+/// Characters for undecided tiles, from the least to the most uncertain.
+const ENTROPY_RAMP: [char; 9] = ['.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+/// How the TerminalVisualizer draws a map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TerminalOptions {
+    /// Paint the background of each tile with its color, using ANSI truecolor escape codes.
+    #[serde(default)]
+    pub truecolor: bool,
+    /// Draw undecided tiles by how uncertain they are, rather than as a '?'.
+    #[serde(default)]
+    pub show_entropy: bool,
+}
+
+/// Draws a map as text, one character per tile and one line per row.
+///
+/// Tiles are drawn with their glyph, or the first character of their key if they have none.
+/// Undecided tiles are drawn as a '?', or with the entropy ramp `.:-=+*#%@` scaled
+/// to the most uncertain tile on the map. Missing tiles are left blank.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalVisualizer<N: DistributionKey> {
+    glyphs: HashMap<N, char>,
+    color_lookup: HashMap<N, MapColor>,
+    options: TerminalOptions,
+}
+
+impl<N: DistributionKey> TerminalVisualizer<N> {
+    pub fn new(glyphs: HashMap<N, char>, color_lookup: HashMap<N, MapColor>, options: TerminalOptions) -> Self {
+        Self {
+            glyphs,
+            color_lookup,
+            options
+        }
+    }
+
+    fn glyph(&self, key: &N) -> char {
+        self.glyphs.get(key).copied().unwrap_or_else(
+            || format!("{:?}", key).chars().next().unwrap_or(' ')
+        )
+    }
+
+    /// Draws the map as a String.
+    pub fn render<AG: AdjacencyGenerator<2>, MP: MapPosition<2>>(&self, map: &Map2D<AG, N, MP>) -> String {
+        let grid = TileGrid::new(map);
+
+        let max_entropy = grid.tiles.iter().flatten().map(|tile| tile.read().unwrap().entropy()).fold(0f32, f32::max);
+        let entropy_glyph = |entropy: f32| match max_entropy > 0. {
+            true => ENTROPY_RAMP[((entropy / max_entropy) * (ENTROPY_RAMP.len() - 1) as f32).round() as usize],
+            false => ENTROPY_RAMP[0]
+        };
+
+        let mut rendered = String::with_capacity((grid.width + 1) * grid.height);
+        for row in grid.rows() {
+            for tile in row {
+                let (glyph, color) = match tile.as_ref().map(|tile| tile.read().unwrap().state.to_owned()) {
+                    None => (' ', None),
+                    Some(MapNodeState::Finalized(key)) => (self.glyph(&key), self.color_lookup.get(&key)),
+                    Some(MapNodeState::Undecided(dist)) => match self.options.show_entropy {
+                        true => (entropy_glyph(dist.entropy()), None),
+                        false => ('?', None)
+                    }
+                };
+
+                match (self.options.truecolor, color) {
+                    (true, Some(color)) => {
+                        let MapColor::Rgb(r, g, b) = color;
+                        let foreground = if color.luminance() > 0.5 { 0 } else { 255 };
+                        let _ = write!(
+                            rendered, "\x1b[48;2;{};{};{}m\x1b[38;2;{fg};{fg};{fg}m{}\x1b[0m",
+                            r, g, b, glyph, fg = foreground
+                        );
+                    },
+                    _ => rendered.push(glyph)
+                }
             }
+            rendered.push('\n');
         }
+        rendered
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> MapVisualizer<AG, N, MP> for TerminalVisualizer<N> {
+    type Output = String;
+    type Args = ();
+
+    fn visualise(&self, map: &Map2D<AG, N, MP>, _args: Option<Self::Args>) -> Option<Self::Output> {
+        Some(self.render(map))
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "txt"
+    }
+}
+
+/// How tiles are laid out in an SVG image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SvgLayout {
+    /// A square grid, for the cardinal, octile and other square adjacencies.
+    #[default]
+    Square,
+    /// Pointy-topped hexagons, with every odd row (by absolute Y coordinate) shifted
+    /// half a tile to the right, for hexagonal adjacencies using offset coordinates.
+    Hex,
+}
+
+fn default_tile_size() -> f32 { 10. }
+
+fn default_merge_runs() -> bool { true }
+
+/// How the SvgVisualizer draws a map.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SvgOptions {
+    /// Width of a tile, in SVG units.
+    #[serde(default = "default_tile_size")]
+    pub tile_size: f32,
+    /// Draw each horizontal run of tiles of the same type as a single rectangle
+    /// to keep the file small; only applies to the square layout.
+    #[serde(default = "default_merge_runs")]
+    pub merge_runs: bool,
+    /// Label each tile with its coordinates.
+    #[serde(default)]
+    pub labels: bool,
+    #[serde(default)]
+    pub layout: SvgLayout,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            tile_size: default_tile_size(),
+            merge_runs: default_merge_runs(),
+            labels: false,
+            layout: SvgLayout::default()
+        }
+    }
+}
+
+/// Draws a map as an SVG image.
+///
+/// Each tile gets the CSS classes `tile` and `tile-<key>`, or `undecided` if it has not been
+/// assigned yet, with the colors of the keys declared as CSS rules in the image's stylesheet,
+/// so that the colors can be restyled without touching the tiles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SvgVisualizer<N: DistributionKey> {
+    color_lookup: HashMap<N, MapColor>,
+    options: SvgOptions,
+}
+
+impl<N: DistributionKey> SvgVisualizer<N> {
+    pub fn new(color_lookup: HashMap<N, MapColor>, options: SvgOptions) -> Self {
+        Self {
+            color_lookup,
+            options
+        }
+    }
+
+    /// The CSS class of tiles with a key; characters that are not valid in a class name are replaced with '_'.
+    pub fn css_class(key: &N) -> String {
+        let sanitized: String = format!("{:?}", key).chars().map(
+            |c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }
+        ).collect();
+        format!("tile-{}", sanitized)
+    }
+
+    /// Draws the map as the text of an SVG file.
+    pub fn render<AG: AdjacencyGenerator<2>, MP: MapPosition<2>>(&self, map: &Map2D<AG, N, MP>) -> String {
+        let grid = TileGrid::new(map);
+        let size = self.options.tile_size;
+        let hex_height = size * 2. / 3f32.sqrt();
+
+        let (image_width, image_height) = match self.options.layout {
+            SvgLayout::Square => (grid.width as f32 * size, grid.height as f32 * size),
+            SvgLayout::Hex => (
+                (grid.width as f32 + 0.5) * size,
+                grid.height.saturating_sub(1) as f32 * 0.75 * hex_height + hex_height
+            )
+        };
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = image_width, h = image_height
+        );
+
+        let mut colors: Vec<(&N, &MapColor)> = self.color_lookup.iter().collect();
+        colors.sort_by_key(|(key, _)| **key);
+        svg.push_str("<style>\n.undecided { fill: rgb(128,128,128); }\n");
+        for (key, MapColor::Rgb(r, g, b)) in colors {
+            let _ = writeln!(svg, ".{} {{ fill: rgb({},{},{}); }}", Self::css_class(key), r, g, b);
+        }
+        let _ = writeln!(svg, "text {{ font-family: monospace; font-size: {}px; text-anchor: middle; dominant-baseline: central; }}", size / 4.);
+        svg.push_str("</style>\n");
+
+        let mut labels = String::new();
+        for (row_idx, row) in grid.rows().enumerate() {
+            let classes: Vec<Option<String>> = row.iter().map(|tile| tile.as_ref().map(
+                |tile| match &tile.read().unwrap().state {
+                    MapNodeState::Finalized(key) => format!("tile {}", Self::css_class(key)),
+                    MapNodeState::Undecided(_) => "undecided".to_string()
+                }
+            )).collect();
+            let abs_y = grid.origin[1] + row_idx as i64;
+
+            let mut col_idx = 0;
+            while col_idx < classes.len() {
+                let class = match &classes[col_idx] {
+                    Some(class) => class,
+                    None => { col_idx += 1; continue }
+                };
+
+                match self.options.layout {
+                    SvgLayout::Square => {
+                        let run = match self.options.merge_runs {
+                            true => classes[col_idx..].iter().take_while(|other| other.as_ref() == Some(class)).count(),
+                            false => 1
+                        };
+                        let _ = writeln!(
+                            svg, r#"<rect class="{}" x="{}" y="{}" width="{}" height="{}"/>"#,
+                            class, col_idx as f32 * size, row_idx as f32 * size, run as f32 * size, size
+                        );
+                        if self.options.labels {
+                            for labelled in col_idx..col_idx + run {
+                                let _ = writeln!(
+                                    labels, r#"<text x="{}" y="{}">{},{}</text>"#,
+                                    (labelled as f32 + 0.5) * size, (row_idx as f32 + 0.5) * size,
+                                    grid.origin[0] + labelled as i64, abs_y
+                                );
+                            }
+                        }
+                        col_idx += run;
+                    },
+                    SvgLayout::Hex => {
+                        let shift = if abs_y.rem_euclid(2) == 1 { 0.5 } else { 0. };
+                        let center_x = (col_idx as f32 + 0.5 + shift) * size;
+                        let center_y = row_idx as f32 * 0.75 * hex_height + hex_height / 2.;
+                        let (half_w, quarter_h) = (size / 2., hex_height / 4.);
+
+                        let corners = [
+                            (0., -2. * quarter_h), (half_w, -quarter_h), (half_w, quarter_h),
+                            (0., 2. * quarter_h), (-half_w, quarter_h), (-half_w, -quarter_h)
+                        ].map(|(dx, dy)| format!("{},{}", center_x + dx, center_y + dy)).join(" ");
+                        let _ = writeln!(svg, r#"<polygon class="{}" points="{}"/>"#, class, corners);
+                        if self.options.labels {
+                            let _ = writeln!(
+                                labels, r#"<text x="{}" y="{}">{},{}</text>"#,
+                                center_x, center_y, grid.origin[0] + col_idx as i64, abs_y
+                            );
+                        }
+                        col_idx += 1;
+                    }
+                }
+            }
+        }
+
+        svg.push_str(&labels);
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> MapVisualizer<AG, N, MP> for SvgVisualizer<N> {
+    type Output = String;
+    type Args = ();
+
+    fn visualise(&self, map: &Map2D<AG, N, MP>, _args: Option<Self::Args>) -> Option<Self::Output> {
+        Some(self.render(map))
+    }
+
+    fn file_extension(&self) -> &'static str {
+        "svg"
+    }
+}
+
+/// A gradient of colors, evenly spaced from 0 to 1.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColorRamp(pub Vec<MapColor>);
+
+impl Default for ColorRamp {
+    /// Viridis-like, from dark purple to yellow.
+    fn default() -> Self {
+        Self(vec![
+            MapColor::Rgb(68, 1, 84),
+            MapColor::Rgb(59, 82, 139),
+            MapColor::Rgb(33, 145, 140),
+            MapColor::Rgb(94, 201, 98),
+            MapColor::Rgb(253, 231, 37),
+        ])
+    }
+}
+
+impl ColorRamp {
+    /// The color at a point of the ramp; values outside of [0, 1] are clamped.
+    pub fn color_at(&self, value: f32) -> Rgb {
+        let stops = &self.0;
+        if stops.is_empty() { return Rgb::black() }
+
+        let scaled = value.clamp(0., 1.) * (stops.len() - 1) as f32;
+        let lower = (scaled.floor() as usize).min(stops.len() - 1);
+        let upper = (lower + 1).min(stops.len() - 1);
+        let frac = scaled - lower as f32;
+
+        let (MapColor::Rgb(r0, g0, b0), MapColor::Rgb(r1, g1, b1)) = (stops[lower], stops[upper]);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * frac).round() as u8;
+        Rgb::new(mix(r0, r1), mix(g0, g1), mix(b0, b1))
+    }
+}
+
+/// What a HeatmapVisualizer shows for each tile.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HeatmapQuantity<N: DistributionKey> {
+    /// How uncertain the tile is; assigned tiles are at 0.
+    Entropy(EntropyMetric),
+    /// The probability of the tile being assigned a key; assigned tiles are at 0 or 1.
+    Probability(N),
+}
+
+/// A rendered heatmap, along with the values at the bottom and the top of its legend.
+pub struct Heatmap {
+    pub image: ril::Image<Rgb>,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl EncodableOutput for Heatmap {
+    fn encode(&self, format: ril::ImageFormat) -> Result<Vec<u8>, ril::Error> {
+        EncodableOutput::encode(&self.image, format)
+    }
+}
+
+/// Draws the uncertainty of a (usually partially collapsed) map, or the probability of a key,
+/// as a heatmap with a square of `scale` pixels per tile.
+///
+/// The colors are scaled from the lowest to the highest value on the map, unless a fixed range
+/// is set, e.g. to keep snapshots taken while a map gets collapsed comparable to each other.
+/// The legend is a strip of the ramp along the right edge of the image, with the top of the range
+/// at the top; the values it spans are returned along with the image.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeatmapVisualizer<N: DistributionKey> {
+    quantity: HeatmapQuantity<N>,
+    ramp: ColorRamp,
+    scale: u32,
+    range: Option<[f32; 2]>,
+    legend: bool,
+}
+
+impl<N: DistributionKey> HeatmapVisualizer<N> {
+    pub fn new(quantity: HeatmapQuantity<N>) -> Self {
+        Self {
+            quantity,
+            ramp: ColorRamp::default(),
+            scale: 1,
+            range: None,
+            legend: true
+        }
+    }
+
+    pub fn with_ramp(mut self, ramp: ColorRamp) -> Self {
+        self.ramp = ramp;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    pub fn with_range(mut self, range: Option<[f32; 2]>) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn with_legend(mut self, legend: bool) -> Self {
+        self.legend = legend;
+        self
+    }
+
+    fn value_of(&self, state: &MapNodeState<N>) -> f32 {
+        match (&self.quantity, state) {
+            (HeatmapQuantity::Entropy(_), MapNodeState::Finalized(_)) => 0.,
+            (HeatmapQuantity::Entropy(metric), MapNodeState::Undecided(dist)) => metric.measure(dist),
+            (HeatmapQuantity::Probability(key), MapNodeState::Finalized(assignment)) => if assignment == key { 1. } else { 0. },
+            (HeatmapQuantity::Probability(key), MapNodeState::Undecided(dist)) => match dist.total_weights() {
+                total if total > 0. => dist.weight(key).max(0.) / total,
+                _ => 0.
+            },
+        }
+    }
+
+    pub fn render<AG: AdjacencyGenerator<2>, MP: MapPosition<2>>(&self, map: &Map2D<AG, N, MP>) -> Heatmap {
+        let grid = TileGrid::new(map);
+        let values: Vec<Option<f32>> = grid.tiles.iter().map(
+            |tile| tile.as_ref().map(|tile| self.value_of(&tile.read().unwrap().state))
+        ).collect();
+
+        let [min, max] = self.range.unwrap_or_else(|| {
+            let known = values.iter().flatten();
+            let min = known.clone().copied().fold(f32::INFINITY, f32::min);
+            let max = known.copied().fold(f32::NEG_INFINITY, f32::max);
+            if min.is_finite() { [min, max] } else { [0., 0.] }
+        });
+        let normalize = |value: f32| if max > min { (value - min) / (max - min) } else { 0. };
+
+        let (map_width, height) = (grid.width as u32 * self.scale, grid.height as u32 * self.scale);
+        let legend_width = if self.legend { (2 * self.scale).max(8) } else { 0 };
+        let legend_gap = if self.legend { self.scale.max(2) } else { 0 };
+        let mut image = ril::Image::new(map_width + legend_gap + legend_width, height.max(1), Rgb::black());
+
+        for (idx, value) in values.iter().enumerate() {
+            let value = match value {
+                Some(value) => *value,
+                None => continue
+            };
+            let (x, y) = ((idx % grid.width.max(1)) as u32, (idx / grid.width.max(1)) as u32);
+            ril::Rectangle::<Rgb>::new()
+                .with_position(x * self.scale, y * self.scale)
+                .with_size(self.scale, self.scale)
+                .with_fill(self.ramp.color_at(normalize(value)))
+                .draw(&mut image);
+        }
+
+        if self.legend {
+            for row in 0..height {
+                let level = 1. - row as f32 / (height.max(2) - 1) as f32;
+                ril::Rectangle::<Rgb>::new()
+                    .with_position(map_width + legend_gap, row)
+                    .with_size(legend_width, 1)
+                    .with_fill(self.ramp.color_at(level))
+                    .draw(&mut image);
+            }
+        }
+
+        Heatmap { image, min, max }
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> MapVisualizer<AG, N, MP> for HeatmapVisualizer<N> {
+    type Output = Heatmap;
+    type Args = ();
+
+    fn visualise(&self, map: &Map2D<AG, N, MP>, _args: Option<Self::Args>) -> Option<Self::Output> {
+        Some(self.render(map))
+    }
+}
+
+/// Draws a map with the tiles that changed since another map highlighted, and the rest faded out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffVisualizer<N: DistributionKey> {
+    color_lookup: HashMap<N, MapColor>,
+    highlight: MapColor,
+    /// How far unchanged tiles are faded towards black, from 0 (not at all) to 1 (all the way).
+    fade: f32,
+    scale: u32,
+}
+
+impl<N: DistributionKey> DiffVisualizer<N> {
+    pub fn new(color_lookup: HashMap<N, MapColor>) -> Self {
+        Self {
+            color_lookup,
+            highlight: MapColor::Rgb(255, 0, 64),
+            fade: 0.6,
+            scale: 1
+        }
+    }
+
+    pub fn with_highlight(mut self, highlight: MapColor) -> Self {
+        self.highlight = highlight;
+        self
+    }
+
+    pub fn with_fade(mut self, fade: f32) -> Self {
+        self.fade = fade.clamp(0., 1.);
+        self
+    }
+
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    /// Renders the map (usually the newer of the two that were compared) with the changes highlighted.
+    pub fn render<AG: AdjacencyGenerator<2>, MP: MapPosition<2>>(&self, map: &Map2D<AG, N, MP>, diff: &MapDiff<N, MP>) -> ril::Image<Rgb> {
+        let grid = TileGrid::new(map);
+        let changed = diff.changed_positions();
+        let keep = 1. - self.fade;
+        let mut image = ril::Image::new(
+            (grid.width as u32 * self.scale).max(1),
+            (grid.height as u32 * self.scale).max(1),
+            Rgb::black()
+        );
+
+        for (idx, tile) in grid.tiles.iter().enumerate() {
+            let reader = match tile {
+                Some(tile) => tile.read().unwrap(),
+                None => continue
+            };
+            let fill = match (changed.contains(&reader.position), &reader.state) {
+                (true, _) => self.highlight.into(),
+                (false, MapNodeState::Finalized(key)) => {
+                    let color: Rgb = self.color_lookup.get(key).copied().map(Rgb::from).unwrap_or(Rgb::white());
+                    Rgb::new((color.r as f32 * keep) as u8, (color.g as f32 * keep) as u8, (color.b as f32 * keep) as u8)
+                },
+                (false, MapNodeState::Undecided(_)) => continue
+            };
+
+            let (x, y) = ((idx % grid.width.max(1)) as u32, (idx / grid.width.max(1)) as u32);
+            ril::Rectangle::<Rgb>::new()
+                .with_position(x * self.scale, y * self.scale)
+                .with_size(self.scale, self.scale)
+                .with_fill(fill)
+                .draw(&mut image);
+        }
+        image
+    }
+}
+
+impl<AG: AdjacencyGenerator<2>, N: DistributionKey, MP: MapPosition<2>> MapVisualizer<AG, N, MP> for DiffVisualizer<N> {
+    type Output = ril::Image<Rgb>;
+    /// The changes to highlight; without them, the whole map is faded out.
+    type Args = MapDiff<N, MP>;
+
+    fn visualise(&self, map: &Map2D<AG, N, MP>, args: Option<Self::Args>) -> Option<Self::Output> {
+        Some(self.render(map, &args.unwrap_or_default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::adjacency::CardinalAdjacencyGenerator;
+    use crate::map2dnode::Map2DNode;
+    use crate::position2d::Position2D;
+    use crate::sampler::MultinomialDistribution;
+    use super::*;
+
+    type TestMap = Map2D<CardinalAdjacencyGenerator<Position2D<u8>>, u8, Position2D<u8>>;
+
+    #[test]
+    fn terminal_rows_follow_y() {
+        let map: TestMap = Map2D::from_tiles([
+            Map2DNode::with_assignment(Position2D::new(0, 0), 1),
+            Map2DNode::with_assignment(Position2D::new(1, 0), 2),
+            Map2DNode::with_assignment(Position2D::new(0, 1), 12),
+            Map2DNode::with_possibilities(Position2D::new(1, 1), MultinomialDistribution::uniform_over([1, 2])),
+        ]);
+        let mut visualizer = TerminalVisualizer::new(HashMap::from([(2, '~')]), HashMap::new(), TerminalOptions::default());
+        assert_eq!(visualizer.render(&map), "1~\n1?\n");
+
+        visualizer.options.show_entropy = true;
+        assert_eq!(visualizer.render(&map), "1~\n1@\n");
+
+        visualizer.color_lookup.insert(1, MapColor::Rgb(255, 255, 255));
+        visualizer.options.truecolor = true;
+        assert!(visualizer.render(&map).starts_with("\x1b[48;2;255;255;255m\x1b[38;2;0;0;0m1\x1b[0m~"));
+    }
+
+    #[test]
+    fn svg_merges_runs() {
+        let map: TestMap = Map2D::from_tiles((0..4).map(
+            |x| Map2DNode::with_assignment(Position2D::new(x, 0), if x < 3 { 1 } else { 2 })
+        ));
+        let mut visualizer = SvgVisualizer::new(HashMap::from([(1, MapColor::Rgb(0, 0, 255))]), SvgOptions::default());
+
+        let merged = visualizer.render(&map);
+        assert!(merged.contains(".tile-1 { fill: rgb(0,0,255); }"));
+        assert!(merged.contains(r#"<rect class="tile tile-1" x="0" y="0" width="30" height="10"/>"#));
+        assert_eq!(merged.matches("<rect").count(), 2);
+
+        visualizer.options = SvgOptions { merge_runs: false, labels: true, layout: SvgLayout::Hex, ..Default::default() };
+        let hexes = visualizer.render(&map);
+        assert_eq!(hexes.matches("<polygon").count(), 4);
+        assert!(hexes.contains(">3,0</text>"));
+    }
+
+    #[test]
+    fn heatmap_ramps_over_values() {
+        let map: TestMap = Map2D::from_tiles([
+            Map2DNode::with_assignment(Position2D::new(0, 0), 1),
+            Map2DNode::with_possibilities(Position2D::new(1, 0), MultinomialDistribution::uniform_over([1, 2])),
+            Map2DNode::with_possibilities(Position2D::new(2, 0), MultinomialDistribution::from(HashMap::from([(1, 3.), (2, 1.)]))),
+        ]);
+        let ramp = ColorRamp(vec![MapColor::Rgb(0, 0, 0), MapColor::Rgb(200, 100, 0)]);
+
+        let entropy = HeatmapVisualizer::new(HeatmapQuantity::Entropy(EntropyMetric::Shannon))
+            .with_ramp(ramp.to_owned())
+            .with_legend(false)
+            .render(&map);
+        assert_eq!((entropy.min, entropy.max), (0., 1.));
+        assert_eq!(entropy.image.width(), 3);
+        assert_eq!(entropy.image.pixel(0, 0), &Rgb::new(0, 0, 0));
+        assert_eq!(entropy.image.pixel(1, 0), &Rgb::new(200, 100, 0));
+
+        let probability = HeatmapVisualizer::new(HeatmapQuantity::Probability(2))
+            .with_ramp(ramp)
+            .with_range(Some([0., 1.]))
+            .with_scale(2)
+            .render(&map);
+        assert_eq!(probability.image.pixel(5, 1), &Rgb::new(50, 25, 0));
+        assert!(probability.image.width() > 6);
     }
 }